@@ -0,0 +1,106 @@
+// Throughput of the anonymizer on cache hits and misses, and of the cache's
+// hasher on its own
+// Run with `cargo bench`; pass a filter such as `cargo bench -- hit` to run
+// one group.
+
+use ahash::AHashMap;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use scrub_db_core::{AnonymizationType, Anonymizer};
+use std::collections::HashMap;
+use std::hint::black_box;
+
+const VALUES: usize = 10_000;
+
+fn emails() -> Vec<String> {
+    (0..VALUES)
+        .map(|i| format!("user{}@example.com", i))
+        .collect()
+}
+
+/// Every value is new, so each one is generated and inserted into the cache
+fn cache_miss(c: &mut Criterion) {
+    let emails = emails();
+    let mut group = c.benchmark_group("cache_miss");
+    group.throughput(Throughput::Elements(VALUES as u64));
+
+    group.bench_function("anonymize", |b| {
+        b.iter_batched(
+            || Anonymizer::with_seed(1),
+            |mut anonymizer| {
+                for email in &emails {
+                    anonymizer.anonymize(email, &AnonymizationType::FakeEmail, true);
+                }
+                anonymizer
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let batch: Vec<(&str, AnonymizationType)> = emails
+        .iter()
+        .map(|email| (email.as_str(), AnonymizationType::FakeEmail))
+        .collect();
+    group.bench_function("anonymize_batch", |b| {
+        b.iter_batched(
+            || Anonymizer::with_seed(1),
+            |mut anonymizer| {
+                anonymizer.anonymize_batch(&batch, true);
+                anonymizer
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+/// Every value is already cached, so each call is a single map lookup
+fn cache_hit(c: &mut Criterion) {
+    let emails = emails();
+    let mut anonymizer = Anonymizer::with_seed(1);
+    for email in &emails {
+        anonymizer.anonymize(email, &AnonymizationType::FakeEmail, true);
+    }
+
+    let mut group = c.benchmark_group("cache_hit");
+    group.throughput(Throughput::Elements(VALUES as u64));
+    group.bench_function("anonymize", |b| {
+        b.iter(|| {
+            for email in &emails {
+                anonymizer.anonymize(email, &AnonymizationType::FakeEmail, true);
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Lookups in the relationship cache with SipHash against aHash
+fn cache_map(c: &mut Criterion) {
+    let emails = emails();
+    let entries = emails
+        .iter()
+        .map(|email| (email.clone(), format!("fake-{}", email)));
+    let sip: HashMap<String, String> = entries.clone().collect();
+    let ahash: AHashMap<String, String> = entries.collect();
+
+    let mut group = c.benchmark_group("cache_map");
+    group.throughput(Throughput::Elements(VALUES as u64));
+    group.bench_function("siphash", |b| {
+        b.iter(|| {
+            for email in &emails {
+                black_box(sip.get(email));
+            }
+        })
+    });
+    group.bench_function("ahash", |b| {
+        b.iter(|| {
+            for email in &emails {
+                black_box(ahash.get(email));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, cache_miss, cache_hit, cache_map);
+criterion_main!(benches);
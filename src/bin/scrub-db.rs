@@ -2,11 +2,43 @@
 // Requires manual configuration via scrub-db.yaml
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
 use regex::Regex;
-use scrub_db_core::{Anonymizer, AnonymizationType, Config};
-use std::io::{self, BufRead, BufReader, IsTerminal, Write};
-use std::path::PathBuf;
+use scrub_db_core::{
+    decode_key, detect_type_for_column, interpolate_env, parse_ipv4_range, redact,
+    sql::{self, Block},
+    AnonymizationType, Anonymizer, Config, Detector, FileSink, Locale, OutputSink, Processor,
+    RuleConfig, RuleSet, StdoutSink, MIN_HASH_LENGTH,
+};
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by `--quiet`; warnings, errors and requested reports still print
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `eprintln!` for banners, progress and tips, silenced by `--quiet`
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Relationship cache size past which an unbounded run suggests `max_cache_entries`
+const LARGE_CACHE_ENTRIES: usize = 1_000_000;
 
 /// Database Anonymization Tool - Manual Configuration
 #[derive(Parser)]
@@ -14,170 +46,1021 @@ use std::path::PathBuf;
 #[command(about = "Anonymize PII in database dumps using manual configuration", long_about = None)]
 #[command(version)]
 struct Cli {
-    /// Config file path (auto-detects scrub-db.yaml if not specified)
-    #[arg(short = 'c', long = "cfg", alias = "config")]
+    /// Config file path (YAML, or TOML/JSON by extension), or - for stdin (auto-detects scrub-db.yaml if not specified)
+    #[arg(short = 'c', long = "cfg", alias = "config", global = true)]
     config: Option<PathBuf>,
 
+    /// Config YAML given directly, e.g. from an environment variable
+    #[arg(
+        long = "config-inline",
+        value_name = "YAML",
+        conflicts_with = "config",
+        global = true
+    )]
+    config_inline: Option<String>,
+
     /// Force stdin mode (auto-detected by default)
     #[arg(long = "stdin")]
     use_stdin: bool,
 
+    /// Input SQL dump (defaults to stdin)
+    #[arg(short = 'i', long = "input")]
+    input: Option<PathBuf>,
+
+    /// Output file (defaults to stdout)
+    #[arg(short = 'o', long = "output")]
+    output: Option<PathBuf>,
+
+    /// Decompress gzip input (implied when --input ends in .gz)
+    #[arg(long = "gzip")]
+    gzip: bool,
+
+    /// Compress the output with gzip (implied when --output ends in .gz)
+    #[arg(long = "gzip-output")]
+    gzip_output: bool,
+
+    /// Input format
+    #[arg(long = "format", value_enum, default_value_t = Format::Sql)]
+    format: Format,
+
+    /// Report which rules would fire without writing any output
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// JSON file holding the relationship cache, loaded before and saved after the run
+    #[arg(long = "cache-file")]
+    cache_file: Option<PathBuf>,
+
+    /// JSON file listing each replacement with a SHA-256 of its original, for audits
+    #[arg(long = "mapping-out")]
+    mapping_out: Option<PathBuf>,
+
+    /// Summary format printed after the run
+    #[arg(long = "report", value_enum, default_value_t = ReportFormat::Text)]
+    report: ReportFormat,
+
+    /// Write the report to a file instead of stderr
+    #[arg(long = "report-file")]
+    report_file: Option<PathBuf>,
+
+    /// Worker threads for SQL input (0 uses every core)
+    #[arg(short = 'j', long = "jobs", default_value_t = 1)]
+    jobs: usize,
+
+    /// Seed for reproducible fake data across runs
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+
+    /// Stop after about this many lines of SQL input, at a statement boundary
+    #[arg(long = "limit", value_name = "N")]
+    limit: Option<usize>,
+
+    /// Print up to N original-vs-fake examples per rule at the end, with
+    /// the originals masked
+    #[arg(long = "sample", value_name = "N")]
+    sample: Option<usize>,
+
+    /// Only print warnings, errors and requested reports to stderr
+    #[arg(short = 'q', long = "quiet", global = true)]
+    quiet: bool,
+
+    /// Progress bar for --input files; auto shows it when stderr is a
+    /// terminal and --quiet isn't set, a bare --progress means always
+    #[arg(
+        long = "progress",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = Progress::Auto,
+        default_missing_value = "always"
+    )]
+    progress: Progress,
+
     /// Subcommand
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// SQL dump (INSERT statements are parsed, other lines scanned)
+    Sql,
+    /// CSV with a header row; rules match by column name
+    Csv,
+    /// Newline-delimited JSON; rules match by dotted path (e.g. user.email)
+    Jsonl,
+    /// pg_dump output with COPY ... FROM stdin blocks; other lines pass through
+    PgCopy,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ReportFormat {
+    /// Human-readable summary
+    Text,
+    /// Machine-readable JSON object
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, ValueEnum)]
+enum Progress {
+    /// When stderr is a terminal and --quiet isn't set
+    Auto,
+    /// Whenever the input is a file of known size
+    Always,
+    /// Not even on a terminal
+    Never,
+}
+
+impl Progress {
+    /// Whether to show the bar, given where stderr goes
+    fn enabled(self, quiet: bool, stderr_is_terminal: bool) -> bool {
+        match self {
+            Progress::Auto => stderr_is_terminal && !quiet,
+            Progress::Always => true,
+            Progress::Never => false,
+        }
+    }
+}
+
+/// Config file syntax, chosen by the file's extension
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// TOML for `.toml`, JSON for `.json` and YAML for anything else
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => Self::Toml,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+}
+
+/// How `merge-cache` resolves an original mapped to different fakes
+#[derive(Clone, Copy, PartialEq, Debug, ValueEnum)]
+enum MergeStrategy {
+    /// Fail, listing the conflicting originals
+    Error,
+    /// Keep the fake from the earliest file
+    First,
+    /// Keep the fake from the latest file
+    Last,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Scan SQL dump for potential PII (Pro feature teaser)
-    Scan,
+    Scan {
+        /// List each finding with its line number and a redacted preview
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+
+        /// Write each finding to stdout as a JSON line
+        #[arg(long = "json")]
+        json: bool,
+    },
+
+    /// Print a starter scrub-db.yaml for the tables in a SQL dump read from stdin
+    Init,
+
+    /// Check an anonymized dump from stdin for values that still look like real PII
+    Verify,
+
+    /// Load and validate the config without reading any SQL
+    Check,
+
+    /// Merge --cache-file outputs from several runs into one relationship cache
+    MergeCache {
+        /// Cache files to merge, earliest first
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Write the merged cache here instead of stdout
+        #[arg(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+
+        /// What to do when files map the same original to different fakes
+        #[arg(long = "strategy", value_enum, default_value = "error")]
+        strategy: MergeStrategy,
+    },
+}
+
+/// One value found by `scan`, with the value itself redacted
+#[derive(Serialize)]
+struct Finding {
+    line: usize,
+    detector: &'static str,
+    preview: String,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    QUIET.store(cli.quiet, Ordering::Relaxed);
 
     // Handle scan command (Pro teaser)
-    if let Some(Commands::Scan) = cli.command {
-        return handle_scan_command();
+    match cli.command {
+        Some(Commands::Scan { verbose, json }) => return handle_scan_command(verbose, json),
+        Some(Commands::Init) => return handle_init_command(),
+        Some(Commands::Verify) => return handle_verify_command(),
+        Some(Commands::Check) => return handle_check_command(cli.config, cli.config_inline),
+        Some(Commands::MergeCache {
+            files,
+            output,
+            strategy,
+        }) => return handle_merge_cache_command(&files, output.as_deref(), strategy),
+        None => {}
     }
 
     // Determine if we're in stdin mode
-    let stdin_mode = cli.use_stdin || !io::stdin().is_terminal();
+    let stdin_mode = cli.use_stdin || cli.input.is_some() || !io::stdin().is_terminal();
 
     if !stdin_mode {
-        eprintln!("🔍 Scrub-DB Free - Manual Database Anonymization Tool");
-        eprintln!("====================================================\n");
-        eprintln!("⚠️  This is the FREE version. It requires manual configuration.");
-        eprintln!("    Create a scrub-db.yaml file to specify anonymization rules.\n");
-        eprintln!("💡 Want automatic PII detection? Upgrade to Scrub-DB Pro!");
-        eprintln!("   Visit https://scrub-db.com for pricing.\n");
+        info!("🔍 Scrub-DB Free - Manual Database Anonymization Tool");
+        info!("====================================================\n");
+        info!("⚠️  This is the FREE version. It requires manual configuration.");
+        info!("    Create a scrub-db.yaml file to specify anonymization rules.\n");
+        info!("💡 Want automatic PII detection? Upgrade to Scrub-DB Pro!");
+        info!("   Visit https://scrub-db.com for pricing.\n");
         return Ok(());
     }
 
-    // Auto-detect config file in current directory
-    let config_path = if let Some(path) = cli.config {
-        Some(path)
-    } else {
-        ["scrub-db.yaml", ".scrub-db.yaml", "scrub-db.yml", ".scrub-db.yml"]
-            .iter()
-            .find(|name| PathBuf::from(name).exists())
-            .map(PathBuf::from)
-    };
-
-    // Load config
-    let config = if let Some(config_path) = &config_path {
-        let config_str = std::fs::read_to_string(config_path)
-            .context(format!("Failed to read config file: {:?}", config_path))?;
-        eprintln!("📄 Using config: {:?}", config_path);
-        serde_yaml::from_str(&config_str).context("Failed to parse config file")?
+    if cli.config.as_deref() == Some(Path::new("-")) && cli.input.is_none() {
+        anyhow::bail!("`-c -` reads the config from stdin, so pass the dump with --input");
+    }
+    let config = if let Some((source, config_str, format)) =
+        config_source(cli.config.clone(), cli.config_inline.clone())?
+    {
+        info!("📄 Using config: {}", source);
+        load_config(&source, &config_str, format)?
     } else {
         eprintln!("⚠️  No config file found!");
-        eprintln!("   Create scrub-db.yaml with anonymization rules.");
-        eprintln!("   Example:");
-        eprintln!("   ```yaml");
-        eprintln!("   preserve_relationships: true");
-        eprintln!("   custom_rules:");
-        eprintln!("     users.email: fake_email");
-        eprintln!("     users.phone: fake_phone");
-        eprintln!("   ```\n");
-        eprintln!("💡 Or use `scrub-db scan` to see what PII was detected (Pro feature preview)\n");
+        info!("   Create scrub-db.yaml with anonymization rules.");
+        info!("   Example:");
+        info!("   ```yaml");
+        info!("   preserve_relationships: true");
+        info!("   custom_rules:");
+        info!("     users.email: fake_email");
+        info!("     users.phone: fake_phone");
+        info!("   ```\n");
+        info!("💡 Or use `scrub-db scan` to see what PII was detected (Pro feature preview)\n");
         Config::default()
     };
+    let settings = parse_settings(&config)?;
 
-    eprintln!("📥 Reading SQL dump from stdin...");
+    // Refuse to truncate the input by writing over it
+    if let (Some(input), Some(output)) = (&cli.input, &cli.output) {
+        if output.exists() && input.canonicalize()? == output.canonicalize()? {
+            anyhow::bail!("Output file {:?} is the same as the input file", output);
+        }
+    }
 
-    let stdin = io::stdin();
-    let reader = BufReader::new(stdin.lock());
-    let mut stdout = io::stdout();
+    let mut progress = None;
+    let input: Box<dyn Read> = match &cli.input {
+        Some(path) => {
+            info!("📥 Reading input from {:?}...", path);
+            let file =
+                File::open(path).context(format!("Failed to open input file: {:?}", path))?;
+            let show = cli.progress.enabled(cli.quiet, io::stderr().is_terminal());
+            match progress_bar(&file).filter(|_| show) {
+                Some(bar) => {
+                    progress = Some(bar.clone());
+                    Box::new(bar.wrap_read(file))
+                }
+                None => Box::new(file),
+            }
+        }
+        None => {
+            info!("📥 Reading input from stdin...");
+            Box::new(io::stdin().lock())
+        }
+    };
+    let reader: Box<dyn BufRead> = if cli.gzip || cli.input.as_deref().is_some_and(is_gzip_path) {
+        Box::new(Interruptible::new(
+            BufReader::new(MultiGzDecoder::new(input)),
+            &INTERRUPTED,
+        ))
+    } else {
+        Box::new(Interruptible::new(BufReader::new(input), &INTERRUPTED))
+    };
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        info!("\n⏹️  Interrupted, finishing up (press Ctrl-C again to quit now)...");
+    })
+    .context("Failed to install the Ctrl-C handler")?;
 
-    // Initialize anonymizer
-    let mut anonymizer = Anonymizer::new();
+    let sink: Box<dyn Write> = match &cli.output {
+        _ if cli.dry_run => Box::new(io::sink()),
+        Some(path) => Box::new(
+            FileSink::create(path).context(format!("Failed to create output file: {:?}", path))?,
+        ),
+        None => Box::new(StdoutSink::new()),
+    };
+    let gzip_output = cli.gzip_output || cli.output.as_deref().is_some_and(is_gzip_path);
+    let mut output = if gzip_output && !cli.dry_run {
+        Output::Gzip(GzEncoder::new(sink, Compression::default()))
+    } else {
+        Output::Plain(sink)
+    };
 
-    // Build regex patterns from custom rules
-    let mut rules: Vec<(Regex, AnonymizationType)> = Vec::new();
-    for (pattern, method_str) in &config.custom_rules {
-        if let Some(anon_type) = AnonymizationType::from_str(method_str) {
-            // Convert table.column pattern to regex
-            let regex_pattern = format!(r"\b{}\b", regex::escape(pattern));
-            if let Ok(regex) = Regex::new(&regex_pattern) {
-                rules.push((regex, anon_type));
-            }
+    // Initialize anonymizer
+    let mut anonymizer = match cli.seed {
+        Some(seed) => Anonymizer::with_seed(seed),
+        None => Anonymizer::new(),
+    };
+    if let Some(key) = settings.key {
+        anonymizer.set_key(key);
+    }
+    anonymizer.set_date_shift_max_days(config.date_shift_max_days);
+    anonymizer.set_perturb_percent(config.perturb_percent);
+    anonymizer.set_keep_email_domain(config.fake_email_keep_domain);
+    anonymizer.set_normalize_email_keys(config.normalize_email_keys);
+    anonymizer.set_keep_phone_format(config.fake_phone_keep_format);
+    if config.gender_values.is_empty() {
+        eprintln!("⚠️  gender_values is empty, using M, F and X");
+    }
+    anonymizer.set_gender_values(config.gender_values.clone());
+    if let Some((network, prefix_len)) = settings.ip_range {
+        anonymizer.set_fake_ip_range(network, prefix_len);
+    }
+    anonymizer.set_mask_char(config.mask_char);
+    anonymizer.set_redact_label(&config.redact_label);
+    if let Some(salt) = &config.hash_salt {
+        anonymizer.set_hash_salt(salt);
+    }
+    if config.deterministic {
+        if config.hash_salt.is_none() {
+            eprintln!("⚠️  deterministic without hash_salt: anyone can recompute the fakes");
+        }
+        anonymizer.set_deterministic(true);
+    }
+    if let Some(length) = config.hash_length {
+        if length < MIN_HASH_LENGTH {
+            eprintln!(
+                "⚠️  hash_length {} is too short, using {}",
+                length, MIN_HASH_LENGTH
+            );
+        }
+        anonymizer.set_hash_length(length);
+    }
+    if config.hash_preserve_length {
+        if config.hash_length.is_some() {
+            eprintln!("⚠️  hash_length is ignored with hash_preserve_length");
+        }
+        anonymizer.set_hash_preserve_length(true);
+    }
+    if let Some(key) = &config.hmac_key {
+        anonymizer.set_hmac_key(key);
+    }
+    match Locale::from_str(&config.locale) {
+        Some(locale) => anonymizer.set_locale(locale),
+        None => eprintln!(
+            "⚠️  Unknown locale {:?}, falling back to \"en\"",
+            config.locale
+        ),
+    }
+    for (key, rule) in &config.custom_rules {
+        if let Some(locale) = rule.locale().filter(|l| Locale::from_str(l).is_none()) {
+            eprintln!(
+                "⚠️  Unknown locale {:?} for rule {:?}, using {:?}",
+                locale, key, config.locale
+            );
         }
     }
+    if let Some(max) = config.max_cache_entries {
+        anonymizer.set_max_cache_entries(max);
+    }
+    if let Some(cache_path) = &cli.cache_file {
+        if cache_path.exists() {
+            let cache = read_cache_file(cache_path)?;
+            info!(
+                "🗂️  Loaded {} cached mappings from {:?}",
+                cache.len(),
+                cache_path
+            );
+            anonymizer.import_cache(cache);
+        }
+    }
+
+    let column_rules = RuleSet::from_config(&config);
+    if config.hmac_key.is_none()
+        && column_rules
+            .iter()
+            .any(|rule| rule.anon_type == AnonymizationType::Hmac)
+    {
+        eprintln!("⚠️  hmac rules without hmac_key: falling back to hash");
+    }
 
-    if rules.is_empty() {
+    let Settings {
+        value_rules,
+        detectors,
+        ..
+    } = settings;
+
+    if column_rules.is_empty() && value_rules.is_empty() && !config.auto_detect {
         eprintln!("⚠️  No anonymization rules defined!");
         eprintln!("   Data will pass through unchanged.");
-        eprintln!("   Add custom_rules to your scrub-db.yaml file.\n");
-    } else {
-        eprintln!("✅ Loaded {} anonymization rules", rules.len());
+        info!("   Add custom_rules to your scrub-db.yaml file.\n");
+    }
+    if !column_rules.is_empty() {
+        info!("✅ Loaded {} anonymization rules", column_rules.len());
+    }
+    if !value_rules.is_empty() {
+        info!("✅ Loaded {} value rules", value_rules.len());
+    }
+    let custom = column_rules
+        .iter()
+        .filter(|rule| matches!(rule.anon_type, AnonymizationType::Custom(_)))
+        .count();
+    if custom > 0 {
+        eprintln!(
+            "⚠️  {} rule(s) use a custom generator, which only library users can register; hashing their values instead",
+            custom
+        );
     }
 
-    // Process SQL dump line by line
-    let mut line_count = 0;
-    for line in reader.lines() {
-        let line = line?;
-        let mut anonymized_line = line.clone();
+    if cli.jobs != 1 && !matches!(cli.format, Format::Sql) {
+        eprintln!("⚠️  --jobs only applies to SQL input; processing on one thread");
+    }
+    if cli.limit.is_some() && !matches!(cli.format, Format::Sql) {
+        eprintln!("⚠️  --limit only applies to SQL input; processing all of it");
+    }
+    let mut processor = Processor::new(column_rules, anonymizer);
+    processor.value_rules = value_rules;
+    processor.detectors = detectors;
+    processor.auto_detect = config.auto_detect;
+    processor.skip_tables = config.skip_tables.clone();
+    processor.preserve_relationships = config.preserve_relationships;
+    processor.sample_size = cli.sample.unwrap_or(0);
+    let (line_count, truncated) = match cli.format {
+        Format::Sql if cli.jobs != 1 => {
+            process_sql_parallel(reader, &mut output, &mut processor, cli.jobs, cli.limit)?
+        }
+        Format::Sql => process_sql(reader, &mut output, &mut processor, cli.limit)?,
+        Format::Csv => {
+            // `table.column` rules match with the file name as the table
+            let table = cli
+                .input
+                .as_ref()
+                .and_then(|path| path.file_stem())
+                .map(|stem| stem.to_string_lossy().into_owned());
+            let rows =
+                scrub_db_core::csv::anonymize(reader, &mut output, |column, value, row_key| {
+                    processor
+                        .value(table.as_deref(), column, value, row_key, &[])
+                        .map(|outcome| outcome.value)
+                })
+                .context("Failed to process CSV input")?;
+            (rows, false)
+        }
+        Format::Jsonl => {
+            let lines = scrub_db_core::json::anonymize(reader, &mut output, |path, value| {
+                processor.value(None, path, value, "", &[])
+            })
+            .context("Failed to process JSONL input")?;
+            (lines, false)
+        }
+        Format::PgCopy => {
+            let lines = scrub_db_core::pg_copy::anonymize(
+                reader,
+                &mut output,
+                |table, column, value, row_key| {
+                    processor.value(Some(table), column, value, row_key, &[])
+                },
+            )
+            .context("Failed to process COPY input")?;
+            (lines, false)
+        }
+    };
+
+    if let Some(bar) = &progress {
+        bar.finish_and_clear();
+    }
+    output.finish()?;
+
+    let unit = match cli.format {
+        Format::Csv => "rows",
+        Format::Sql | Format::Jsonl | Format::PgCopy => "lines",
+    };
+    let report = Report::new(&processor, line_count);
+    match cli.report {
+        ReportFormat::Json => {
+            let json = serde_json::to_string_pretty(&report)?;
+            match &cli.report_file {
+                Some(path) => std::fs::write(path, json + "\n")
+                    .context(format!("Failed to write report file: {:?}", path))?,
+                None => eprintln!("{}", json),
+            }
+        }
+        ReportFormat::Text => {
+            info!("✅ Processed {} {}!", line_count, unit);
+            report.print_methods();
+            if cli.dry_run {
+                report.print();
+            } else if let Some(path) = &cli.output {
+                info!("💾 Wrote anonymized dump to {:?}", path);
+            }
+        }
+    }
+
+    print_samples(&processor);
+
+    let evictions = processor.anonymizer.cache_evictions();
+    if evictions > 0 {
+        eprintln!(
+            "⚠️  Evicted {} cached fakes to stay within max_cache_entries; values seen again after eviction got new fakes",
+            evictions
+        );
+    } else if config.max_cache_entries.is_none()
+        && processor.anonymizer.cache_len() >= LARGE_CACHE_ENTRIES
+    {
+        info!(
+            "💡 The relationship cache holds {} values; set max_cache_entries to bound its memory",
+            processor.anonymizer.cache_len()
+        );
+    }
+
+    if let Some(cache_path) = &cli.cache_file {
+        let cache_json = serde_json::to_string_pretty(&processor.anonymizer.export_cache())?;
+        std::fs::write(cache_path, cache_json)
+            .context(format!("Failed to write cache file: {:?}", cache_path))?;
+        info!("🗂️  Saved relationship cache to {:?}", cache_path);
+    }
+
+    if let Some(mapping_path) = &cli.mapping_out {
+        let mapping = processor.anonymizer.export_mapping();
+        std::fs::write(mapping_path, serde_json::to_string_pretty(&mapping)?)
+            .context(format!("Failed to write mapping file: {:?}", mapping_path))?;
+        info!("🔏 Wrote {} mappings to {:?}", mapping.len(), mapping_path);
+    }
+
+    if truncated {
+        info!(
+            "✂️  Stopped after {} {} (--limit); the rest of the input was not processed",
+            line_count, unit
+        );
+    }
 
-        // Simple pattern matching for common PII in INSERT statements
-        // This is basic - real pattern matching happens via config rules
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        eprintln!(
+            "⚠️  Interrupted after {} {}; the output is incomplete",
+            line_count, unit
+        );
+        std::process::exit(130);
+    }
 
-        // Detect emails in the line
-        let email_regex = Regex::new(r"\b[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}\b").unwrap();
-        for cap in email_regex.find_iter(&line) {
-            let original = cap.as_str();
-            // Check if this matches any of our rules
-            let anon_type = rules
-                .iter()
-                .find(|(pattern, _)| pattern.is_match(&line))
-                .map(|(_, t)| t)
-                .unwrap_or(&AnonymizationType::Skip);
+    if processor.column_rules.is_empty() {
+        info!("\n💡 Tip: Want automatic PII detection?");
+        info!("   Try: scrub-db scan  (shows what Pro version would detect)");
+    }
 
-            if matches!(anon_type, AnonymizationType::FakeEmail) {
-                let fake = anonymizer.anonymize(original, anon_type, config.preserve_relationships);
-                anonymized_line = anonymized_line.replace(original, &fake);
+    if config.require_all_rules_match {
+        let unused = report.unused_rules();
+        if !unused.is_empty() {
+            eprintln!("❌ Rules that matched no values:");
+            for rule in &unused {
+                eprintln!("   {}", rule);
             }
+            anyhow::bail!("{} rule(s) matched nothing in the input", unused.len());
         }
+    }
+
+    Ok(())
+}
+
+/// A progress bar over the bytes of an input file, if its size is known
+///
+/// Pipes and other special files have no meaningful length, so they get no
+/// bar. For gzip input the bar counts compressed bytes.
+fn progress_bar(file: &File) -> Option<ProgressBar> {
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return None;
+    }
+    let style = ProgressStyle::with_template(
+        "{bar:40} {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}, {eta} left)",
+    )
+    .expect("progress template is valid");
+    Some(ProgressBar::new(metadata.len()).with_style(style))
+}
 
-        // Detect phone numbers
-        let phone_regex = Regex::new(r"\b\d{3}[-.]?\d{3}[-.]?\d{4}\b").unwrap();
-        for cap in phone_regex.find_iter(&line) {
-            let original = cap.as_str();
-            let anon_type = rules
-                .iter()
-                .find(|(pattern, _)| pattern.is_match(&line))
-                .map(|(_, t)| t)
-                .unwrap_or(&AnonymizationType::Skip);
+/// Whether a path names a gzip file
+fn is_gzip_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
 
-            if matches!(anon_type, AnonymizationType::FakePhone) {
-                let fake = anonymizer.anonymize(original, anon_type, config.preserve_relationships);
-                anonymized_line = anonymized_line.replace(original, &fake);
+/// Where anonymized output goes, compressed on the fly when requested
+enum Output {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+}
+
+impl Output {
+    /// Flush everything, writing the gzip trailer if compressing
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Anonymize a SQL dump, returning the number of lines processed and
+/// whether `limit` stopped it before the end of the input
+///
+/// INSERT/CREATE TABLE statements are parsed, other lines go through the
+/// line-by-line detectors.
+fn process_sql(
+    reader: Box<dyn BufRead>,
+    output: &mut dyn OutputSink,
+    processor: &mut Processor,
+    limit: Option<usize>,
+) -> Result<(usize, bool)> {
+    let line_count = Cell::new(0);
+    let lines = lossy_lines(reader).inspect(|_| line_count.set(line_count.get() + 1));
+    let mut processed = 0;
+
+    for block in sql::blocks(lines) {
+        let block = block?;
+        if is_past_limit(processed, limit) {
+            return Ok((processed, true));
+        }
+        match block {
+            block if is_cut_off(&block) => break,
+            block => output.write_statement(&processor.block(&block))?,
+        }
+        processed = line_count.get();
+    }
+
+    Ok((processed, false))
+}
+
+/// Whether `--limit` ends the input before the next block
+///
+/// Checked between blocks, so a statement straddling the limit is finished
+/// rather than cut off.
+fn is_past_limit(processed: usize, limit: Option<usize>) -> bool {
+    limit.is_some_and(|limit| processed >= limit)
+}
+
+/// Whether a statement never saw its terminating semicolon (or custom
+/// delimiter) because an interrupt stopped the input before the rest of it
+/// was read
+///
+/// Such a statement is left out rather than written half-processed; one
+/// unterminated at the real end of the input is still written.
+fn is_cut_off(block: &Block) -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+        && match block {
+            Block::Statement(statement) => !sql::is_complete(statement),
+            Block::Delimited {
+                statement,
+                delimiter,
+            } => !sql::is_terminated(statement, delimiter),
+            Block::Line(_) | Block::Comment(_) | Block::Routine(_) => false,
+        }
+}
+
+/// Blocks read before a chunk is handed to the thread pool
+const CHUNK_BLOCKS: usize = 10_000;
+
+/// Anonymize a SQL dump on a thread pool, returning the number of lines processed
+///
+/// The input is read in chunks of blocks that are anonymized in parallel and
+/// written back in their original order. CREATE TABLE statements flush the
+/// pending chunk first, so later INSERTs always see the column order that
+/// was in effect at their position in the dump.
+fn process_sql_parallel(
+    reader: Box<dyn BufRead>,
+    output: &mut dyn OutputSink,
+    processor: &mut Processor,
+    jobs: usize,
+    limit: Option<usize>,
+) -> Result<(usize, bool)> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .context("Failed to start worker threads")?;
+    let mut blocks = Vec::new();
+    let line_count = Cell::new(0);
+    let lines = lossy_lines(reader).inspect(|_| line_count.set(line_count.get() + 1));
+    let mut processed = 0;
+    let mut truncated = false;
+
+    for block in sql::blocks(lines) {
+        let block = block?;
+        if is_past_limit(processed, limit) {
+            truncated = true;
+            break;
+        }
+        let is_create_table = block
+            .statement()
+            .and_then(sql::parse_create_table)
+            .is_some();
+        match block {
+            block if is_cut_off(&block) => break,
+            block if is_create_table => {
+                chunk(processor, &pool, &mut blocks, output)?;
+                output.write_statement(&processor.block(&block))?;
             }
+            block => blocks.push(block),
         }
+        processed = line_count.get();
 
-        // Write line to stdout
-        writeln!(stdout, "{}", anonymized_line)?;
-        line_count += 1;
+        if blocks.len() >= CHUNK_BLOCKS {
+            chunk(processor, &pool, &mut blocks, output)?;
+        }
     }
+    chunk(processor, &pool, &mut blocks, output)?;
 
-    eprintln!("✅ Processed {} lines!", line_count);
+    Ok((processed, truncated))
+}
 
-    if rules.is_empty() {
-        eprintln!("\n💡 Tip: Want automatic PII detection?");
-        eprintln!("   Try: scrub-db scan  (shows what Pro version would detect)");
+/// Set by the Ctrl-C handler; the input then ends at the next line boundary
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Input that ends early once `interrupted` is set
+///
+/// The input stops at the end of the current line rather than mid-line, so
+/// a half-read value never reaches the output unanonymized. Every format
+/// then finishes as if it had reached the end of the input, and the usual
+/// flushing and cache saving run.
+struct Interruptible<R> {
+    inner: R,
+    interrupted: &'static AtomicBool,
+    at_line_start: bool,
+}
+
+impl<R: BufRead> Interruptible<R> {
+    fn new(inner: R, interrupted: &'static AtomicBool) -> Self {
+        Self {
+            inner,
+            interrupted,
+            at_line_start: true,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Interruptible<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Interruptible<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let interrupted = self.interrupted.load(Ordering::SeqCst);
+        if interrupted && self.at_line_start {
+            return Ok(&[]);
+        }
+        let buf = self.inner.fill_buf()?;
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(end) if interrupted => Ok(&buf[..=end]),
+            _ => Ok(buf),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt > 0 {
+            if let Ok(buf) = self.inner.fill_buf() {
+                self.at_line_start = buf.get(amt - 1) == Some(&b'\n');
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Lines of the input, tolerating bytes that aren't valid UTF-8
+///
+/// Dumps with the odd latin-1 byte would otherwise abort the whole run, so
+/// invalid sequences are replaced with U+FFFD and the line number reported.
+fn lossy_lines<R: BufRead>(mut reader: R) -> impl Iterator<Item = io::Result<String>> {
+    let mut line_number = 0;
+    std::iter::from_fn(move || {
+        let mut buf = Vec::new();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        line_number += 1;
+
+        if buf.ends_with(b"\n") {
+            buf.pop();
+            if buf.ends_with(b"\r") {
+                buf.pop();
+            }
+        }
+        Some(Ok(match String::from_utf8(buf) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!(
+                    "⚠️  Line {} is not valid UTF-8; invalid bytes replaced with U+FFFD",
+                    line_number
+                );
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            }
+        }))
+    })
+}
+
+/// Summary of a run, printed for humans or serialized with `--report json`
+#[derive(Serialize)]
+struct Report {
+    lines: usize,
+    /// Lines outside parsed statements changed by the line detectors
+    detector_lines: usize,
+    rules: BTreeMap<String, RuleReport>,
+    /// Values anonymized per method, across column rules and line detectors
+    methods: BTreeMap<String, MethodReport>,
+}
+
+#[derive(Serialize)]
+struct RuleReport {
+    method: String,
+    values: usize,
+    distinct_values: usize,
+    cache_hits: usize,
+}
+
+#[derive(Serialize)]
+struct MethodReport {
+    values: usize,
+    distinct_values: usize,
+}
+
+impl Report {
+    /// Summarize how often each rule fired, including rules that never did
+    fn new(processor: &Processor, lines: usize) -> Self {
+        let mut rules = BTreeMap::new();
+        for rule in processor.column_rules.iter() {
+            let report = match processor.rule_counts.get(&rule.key) {
+                Some(count) => RuleReport {
+                    method: count.anon_type.to_string(),
+                    values: count.values,
+                    distinct_values: count.distinct.count(),
+                    cache_hits: count.cache_hits,
+                },
+                None => RuleReport {
+                    method: rule.anon_type.to_string(),
+                    values: 0,
+                    distinct_values: 0,
+                    cache_hits: 0,
+                },
+            };
+            rules.insert(rule.key.clone(), report);
+        }
+        let methods = processor
+            .anonymizer
+            .stats()
+            .into_iter()
+            .map(|(anon_type, stats)| {
+                let report = MethodReport {
+                    values: stats.invocations,
+                    distinct_values: stats.distinct,
+                };
+                (anon_type.to_string(), report)
+            })
+            .collect();
+        Report {
+            lines,
+            detector_lines: processor.detector_lines,
+            rules,
+            methods,
+        }
+    }
+
+    /// Print how many values each method anonymized
+    fn print_methods(&self) {
+        if self.methods.is_empty() {
+            return;
+        }
+        info!("📊 Values by method:");
+        for (method, count) in &self.methods {
+            info!(
+                "   {:<18} {:>8} values {:>8} distinct",
+                method, count.values, count.distinct_values
+            );
+        }
+    }
+
+    /// Keys of the rules that matched no values, for `require_all_rules_match`
+    fn unused_rules(&self) -> Vec<&str> {
+        self.rules
+            .iter()
+            .filter(|(_, count)| count.values == 0)
+            .map(|(rule, _)| rule.as_str())
+            .collect()
     }
 
+    /// Print the per-rule table shown for `--dry-run`
+    fn print(&self) {
+        eprintln!("\n🧪 Dry run - no data written");
+        eprintln!(
+            "   {:<32} {:<18} {:>8} {:>10}",
+            "Rule", "Method", "Values", "Cache hits"
+        );
+        for (rule, count) in &self.rules {
+            eprintln!(
+                "   {:<32} {:<18} {:>8} {:>10}",
+                rule, count.method, count.values, count.cache_hits
+            );
+        }
+        eprintln!(
+            "   {} other lines changed by the line detectors\n",
+            self.detector_lines
+        );
+    }
+}
+
+/// Anonymize a chunk of blocks on the thread pool and write them in order
+fn chunk(
+    processor: &mut Processor,
+    pool: &rayon::ThreadPool,
+    blocks: &mut Vec<Block>,
+    output: &mut dyn OutputSink,
+) -> io::Result<()> {
+    if blocks.is_empty() {
+        return Ok(());
+    }
+
+    // Several parts per thread so one slow part doesn't stall the chunk
+    let part_size = blocks.len().div_ceil(pool.current_num_threads() * 4);
+    let this = &*processor;
+    let parts: Vec<(Vec<String>, Processor)> = pool.install(|| {
+        blocks
+            .par_chunks(part_size)
+            .map(|part| {
+                let mut worker = this.worker();
+                let anonymized = part.iter().map(|block| worker.block(block)).collect();
+                (anonymized, worker)
+            })
+            .collect()
+    });
+
+    for (anonymized, worker) in parts {
+        for sql in &anonymized {
+            output.write_statement(sql)?;
+        }
+        processor.merge(worker);
+    }
+    blocks.clear();
     Ok(())
 }
 
-fn handle_scan_command() -> Result<()> {
-    eprintln!("🔍 Scrub-DB Scan - PII Detection Preview");
-    eprintln!("=========================================\n");
+/// Print the `--sample` examples, diff style: the masked original, then
+/// what it became
+fn print_samples(processor: &Processor) {
+    if processor.samples.is_empty() {
+        return;
+    }
+    eprintln!("\n🔍 Sample transformations:");
+    for (rule, samples) in &processor.samples {
+        eprintln!("   {} ({})", rule, processor.rule_counts[rule].anon_type);
+        for (original, anonymized) in samples {
+            eprintln!("   - {}", original);
+            eprintln!("   + {}", anonymized);
+        }
+    }
+    eprintln!();
+}
+
+fn handle_scan_command(verbose: bool, json: bool) -> Result<()> {
+    info!("🔍 Scrub-DB Scan - PII Detection Preview");
+    info!("=========================================\n");
 
-    eprintln!("📥 Reading SQL dump from stdin...\n");
+    info!("📥 Reading SQL dump from stdin...\n");
 
     let stdin = io::stdin();
     let reader = BufReader::new(stdin.lock());
+    let mut stdout = io::stdout().lock();
 
     let mut potential_emails = 0;
     let mut potential_phones = 0;
@@ -185,24 +1068,45 @@ fn handle_scan_command() -> Result<()> {
     let mut line_count = 0;
 
     // Scan for potential PII patterns
-    let email_regex = Regex::new(r"\b[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}\b").unwrap();
-    let phone_regex = Regex::new(r"\b\d{3}[-.]?\d{3}[-.]?\d{4}\b").unwrap();
-    let cc_regex = Regex::new(r"\b\d{4}[-\s]?\d{4}[-\s]?\d{4}[-\s]?\d{4}\b").unwrap();
-
-    for line in reader.lines() {
+    for line in lossy_lines(reader) {
         let line = line?;
         line_count += 1;
 
-        if email_regex.is_match(&line) {
-            potential_emails += 1;
-        }
-        if phone_regex.is_match(&line) {
-            potential_phones += 1;
-        }
-        if cc_regex.is_match(&line) {
-            potential_cc += 1;
+        for detector in Detector::ALL {
+            let mut matches = detector.regex().find_iter(&line).peekable();
+            if matches.peek().is_none() {
+                continue;
+            }
+            match detector {
+                Detector::Email => potential_emails += 1,
+                Detector::Phone => potential_phones += 1,
+                Detector::CreditCard => potential_cc += 1,
+            }
+            if !verbose && !json {
+                continue;
+            }
+
+            for found in matches {
+                let finding = Finding {
+                    line: line_count,
+                    detector: detector.name(),
+                    preview: redact(found.as_str()),
+                };
+                if json {
+                    writeln!(stdout, "{}", serde_json::to_string(&finding)?)?;
+                }
+                if verbose {
+                    eprintln!(
+                        "   line {:<8} {:<12} {}",
+                        finding.line, finding.detector, finding.preview
+                    );
+                }
+            }
         }
     }
+    if verbose {
+        eprintln!();
+    }
 
     eprintln!("✨ Scan Results:");
     eprintln!("   📧 {} lines with potential email addresses", potential_emails);
@@ -211,25 +1115,1125 @@ fn handle_scan_command() -> Result<()> {
     eprintln!("   📄 {} total lines scanned\n", line_count);
 
     if potential_emails + potential_phones + potential_cc > 0 {
-        eprintln!("🚀 Upgrade to Scrub-DB Pro for:");
-        eprintln!("   ✅ Automatic PII detection (no config needed)");
-        eprintln!("   ✅ Smart column name analysis");
-        eprintln!("   ✅ Live database connections");
-        eprintln!("   ✅ Database-to-database anonymization");
-        eprintln!("   ✅ Compliance reporting\n");
-        eprintln!("   Visit https://scrub-db.com for pricing and features.\n");
+        info!("🚀 Upgrade to Scrub-DB Pro for:");
+        info!("   ✅ Automatic PII detection (no config needed)");
+        info!("   ✅ Smart column name analysis");
+        info!("   ✅ Live database connections");
+        info!("   ✅ Database-to-database anonymization");
+        info!("   ✅ Compliance reporting\n");
+        info!("   Visit https://scrub-db.com for pricing and features.\n");
     } else {
         eprintln!("✅ No obvious PII patterns detected in this dump.\n");
     }
 
-    eprintln!("💡 Free version: Create scrub-db.yaml with manual rules");
-    eprintln!("   Example:");
-    eprintln!("   ```yaml");
-    eprintln!("   custom_rules:");
-    eprintln!("     users.email: fake_email");
-    eprintln!("     users.phone: fake_phone");
-    eprintln!("     orders.credit_card_number: mask_credit_card");
-    eprintln!("   ```");
+    info!("💡 Free version: Create scrub-db.yaml with manual rules");
+    info!("   Example:");
+    info!("   ```yaml");
+    info!("   custom_rules:");
+    info!("     users.email: fake_email");
+    info!("     users.phone: fake_phone");
+    info!("     orders.credit_card_number: mask_credit_card");
+    info!("   ```");
 
     Ok(())
 }
+
+/// What `verify` found in a supposedly anonymized dump
+struct Verification {
+    lines: usize,
+    /// Emails, phone numbers and credit card numbers that aren't this tool's fakes
+    leaks: Vec<Finding>,
+}
+
+/// Run the detectors over a dump, keeping the values that don't look fake
+fn verify<R: BufRead>(reader: R) -> io::Result<Verification> {
+    let mut verification = Verification {
+        lines: 0,
+        leaks: Vec::new(),
+    };
+    for line in lossy_lines(reader) {
+        let line = line?;
+        verification.lines += 1;
+
+        for detector in Detector::ALL {
+            for found in detector.regex().find_iter(&line) {
+                if !detector.is_known_fake(found.as_str()) {
+                    verification.leaks.push(Finding {
+                        line: verification.lines,
+                        detector: detector.name(),
+                        preview: redact(found.as_str()),
+                    });
+                }
+            }
+        }
+    }
+    Ok(verification)
+}
+
+/// Read the config from `--config-inline`, the `-c` file (or stdin for
+/// `-c -`), or else the first default config file in the current directory
+///
+/// Returns where it came from, for messages, with its text and format, or
+/// `None` when there is no config at all.
+fn config_source(
+    config: Option<PathBuf>,
+    config_inline: Option<String>,
+) -> Result<Option<(String, String, ConfigFormat)>> {
+    if let Some(yaml) = config_inline {
+        return Ok(Some((
+            "--config-inline".to_string(),
+            yaml,
+            ConfigFormat::Yaml,
+        )));
+    }
+    let config_path = config.or_else(|| {
+        [
+            "scrub-db.yaml",
+            ".scrub-db.yaml",
+            "scrub-db.yml",
+            ".scrub-db.yml",
+            "scrub-db.toml",
+            ".scrub-db.toml",
+            "scrub-db.json",
+            ".scrub-db.json",
+        ]
+        .iter()
+        .find(|name| PathBuf::from(name).exists())
+        .map(PathBuf::from)
+    });
+    let Some(config_path) = config_path else {
+        return Ok(None);
+    };
+    if config_path == Path::new("-") {
+        let mut config_str = String::new();
+        io::stdin()
+            .read_to_string(&mut config_str)
+            .context("Failed to read config from stdin")?;
+        return Ok(Some(("stdin".to_string(), config_str, ConfigFormat::Yaml)));
+    }
+    let config_str = std::fs::read_to_string(&config_path)
+        .context(format!("Failed to read config file: {:?}", config_path))?;
+    Ok(Some((
+        format!("{:?}", config_path),
+        config_str,
+        ConfigFormat::from_path(&config_path),
+    )))
+}
+
+/// Interpolate, parse and validate config text, listing invalid rules on stderr
+fn load_config(source: &str, config_str: &str, format: ConfigFormat) -> Result<Config> {
+    let config_str = interpolate_env(config_str).map_err(|name| {
+        anyhow::anyhow!(
+            "Environment variable {} used in the config from {} is not set",
+            name,
+            source
+        )
+    })?;
+    let config = parse_config(&config_str, format).context("Failed to parse config file")?;
+    if let Err(invalid) = config.validate() {
+        eprintln!("❌ Invalid anonymization method in custom_rules:");
+        for key in &invalid {
+            let rule = &config.custom_rules[key];
+            match (rule, AnonymizationType::try_from(rule.method())) {
+                (RuleConfig::Method(_), Err(error)) => eprintln!("   {}: {}", key, error),
+                _ => eprintln!("   {}: {} (missing or invalid options)", key, rule.method()),
+            }
+        }
+        anyhow::bail!("{} invalid rule(s) in {}", invalid.len(), source);
+    }
+    Ok(config)
+}
+
+/// Config settings that only turn out to be invalid once they are parsed
+struct Settings {
+    key: Option<[u8; 32]>,
+    ip_range: Option<(Ipv4Addr, u8)>,
+    value_rules: Vec<(Regex, AnonymizationType)>,
+    detectors: Vec<Detector>,
+}
+
+/// Parse the settings of a loaded config, failing the same way for `check` and a real run
+fn parse_settings(config: &Config) -> Result<Settings> {
+    let key = match &config.encryption_key {
+        Some(encoded) => Some(
+            decode_key(encoded)
+                .context("encryption_key must be 32 bytes, given as 64 hex chars or base64")?,
+        ),
+        None => None,
+    };
+    let ip_range = match &config.fake_ip_range {
+        Some(range) => Some(
+            parse_ipv4_range(range)
+                .with_context(|| format!("fake_ip_range {:?} is not an IPv4 CIDR range", range))?,
+        ),
+        None => None,
+    };
+
+    // Value rules are compiled once, sorted so overlap ties resolve the same way every run
+    let mut value_rules: Vec<(Regex, AnonymizationType)> = Vec::new();
+    let mut patterns: Vec<(&String, &String)> = config.value_rules.iter().collect();
+    patterns.sort();
+    for (pattern, method) in patterns {
+        let anon_type = AnonymizationType::try_from(method.as_str())
+            .with_context(|| format!("Invalid method for value rule {:?}", pattern))?;
+        let regex = Regex::new(pattern).context(format!("Invalid value rule {:?}", pattern))?;
+        value_rules.push((regex, anon_type));
+    }
+
+    let detectors = match &config.enabled_detectors {
+        Some(names) => {
+            let mut detectors = Vec::new();
+            for name in names {
+                match Detector::from_str(name) {
+                    Some(detector) => detectors.push(detector),
+                    None => anyhow::bail!(
+                        "Unknown detector {:?} in enabled_detectors (expected email, phone or credit_card)",
+                        name
+                    ),
+                }
+            }
+            detectors
+        }
+        None => Detector::DEFAULT.to_vec(),
+    };
+
+    Ok(Settings {
+        key,
+        ip_range,
+        value_rules,
+        detectors,
+    })
+}
+
+fn handle_check_command(config: Option<PathBuf>, config_inline: Option<String>) -> Result<()> {
+    let Some((source, config_str, format)) = config_source(config, config_inline)? else {
+        anyhow::bail!("No config file found; pass one with -c or create scrub-db.yaml");
+    };
+    let config = load_config(&source, &config_str, format)?;
+    parse_settings(&config)?;
+    eprintln!(
+        "✅ {} is valid: {} rule(s)",
+        source,
+        config.custom_rules.len()
+    );
+    Ok(())
+}
+
+fn handle_verify_command() -> Result<()> {
+    info!("🔎 Verifying anonymized dump from stdin...\n");
+
+    let stdin = io::stdin();
+    let verification = verify(BufReader::new(stdin.lock()))?;
+
+    for finding in &verification.leaks {
+        eprintln!(
+            "   line {:<8} {:<12} {}",
+            finding.line, finding.detector, finding.preview
+        );
+    }
+    if !verification.leaks.is_empty() {
+        eprintln!();
+    }
+    info!("📄 {} lines verified", verification.lines);
+
+    if !verification.leaks.is_empty() {
+        anyhow::bail!("{} values look like real PII", verification.leaks.len());
+    }
+    info!("✅ No real-looking emails, phone numbers or credit card numbers found");
+    Ok(())
+}
+
+fn handle_merge_cache_command(
+    files: &[PathBuf],
+    output: Option<&Path>,
+    strategy: MergeStrategy,
+) -> Result<()> {
+    let caches = files
+        .iter()
+        .map(|path| read_cache_file(path))
+        .collect::<Result<Vec<_>>>()?;
+    let (merged, conflicts) = merge_caches(caches, strategy);
+
+    if !conflicts.is_empty() {
+        let listed: Vec<String> = conflicts
+            .iter()
+            .take(5)
+            .map(|original| format!("{:?}", original))
+            .collect();
+        let more = match conflicts.len() {
+            n if n > listed.len() => format!(" and {} more", n - listed.len()),
+            _ => String::new(),
+        };
+        if strategy == MergeStrategy::Error {
+            anyhow::bail!(
+                "{} originals map to different fakes: {}{} (pick one with --strategy first or last)",
+                conflicts.len(),
+                listed.join(", "),
+                more
+            );
+        }
+        eprintln!(
+            "⚠️  {} originals map to different fakes, keeping the {} file's: {}{}",
+            conflicts.len(),
+            if strategy == MergeStrategy::First {
+                "earliest"
+            } else {
+                "latest"
+            },
+            listed.join(", "),
+            more
+        );
+    }
+
+    // Same format as --cache-file writes, sorted so merges diff cleanly
+    let json = serde_json::to_string_pretty(&merged)?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, json).context(format!("Failed to write cache file: {:?}", path))?
+        }
+        None => println!("{}", json),
+    }
+    info!(
+        "🗂️  Merged {} cache files into {} mappings",
+        files.len(),
+        merged.len()
+    );
+    Ok(())
+}
+
+/// Parse config text in the syntax it was written in
+fn parse_config(text: &str, format: ConfigFormat) -> Result<Config> {
+    Ok(match format {
+        ConfigFormat::Yaml => serde_yaml::from_str(text)?,
+        ConfigFormat::Toml => toml::from_str(text)?,
+        ConfigFormat::Json => serde_json::from_str(text)?,
+    })
+}
+
+/// Read a relationship cache written by `--cache-file`
+fn read_cache_file(path: &Path) -> Result<HashMap<String, String>> {
+    let cache_str =
+        std::fs::read_to_string(path).context(format!("Failed to read cache file: {:?}", path))?;
+    serde_json::from_str(&cache_str).context(format!("Failed to parse cache file: {:?}", path))
+}
+
+/// Merge relationship caches in order
+///
+/// Returns the merged cache and, sorted, the originals that two caches map
+/// to different fakes; those keep the first or last cache's fake as
+/// `strategy` says.
+fn merge_caches(
+    caches: Vec<HashMap<String, String>>,
+    strategy: MergeStrategy,
+) -> (BTreeMap<String, String>, Vec<String>) {
+    let mut merged = BTreeMap::new();
+    let mut conflicts = BTreeSet::new();
+    for cache in caches {
+        for (original, fake) in cache {
+            match merged.entry(original) {
+                Entry::Vacant(entry) => {
+                    entry.insert(fake);
+                }
+                Entry::Occupied(mut entry) if *entry.get() != fake => {
+                    conflicts.insert(entry.key().clone());
+                    if strategy == MergeStrategy::Last {
+                        entry.insert(fake);
+                    }
+                }
+                Entry::Occupied(_) => {}
+            }
+        }
+    }
+    (merged, conflicts.into_iter().collect())
+}
+
+fn handle_init_command() -> Result<()> {
+    info!("📥 Reading SQL dump from stdin...");
+
+    let stdin = io::stdin();
+    let tables = discover_tables(BufReader::new(stdin.lock()))?;
+    let yaml = init_config(&tables);
+    io::stdout().lock().write_all(yaml.as_bytes())?;
+
+    let columns: Vec<&String> = tables.iter().flat_map(|(_, columns)| columns).collect();
+    let guessed = columns
+        .iter()
+        .filter(|column| detect_type_for_column(column).is_some())
+        .count();
+    info!(
+        "✅ Found {} tables with {} columns, {} of them likely PII",
+        tables.len(),
+        columns.len(),
+        guessed
+    );
+    info!("💡 Save the output as scrub-db.yaml and replace `skip` with the methods you want");
+
+    Ok(())
+}
+
+/// Collect table names and their columns, in the order they first appear
+///
+/// Columns come from `CREATE TABLE` statements and `INSERT` column lists.
+fn discover_tables<R: BufRead>(reader: R) -> Result<Vec<(String, Vec<String>)>> {
+    let mut tables: Vec<(String, Vec<String>)> = Vec::new();
+    let mut record = |table: String, columns: Vec<String>| {
+        let index = match tables.iter().position(|(name, _)| *name == table) {
+            Some(index) => index,
+            None => {
+                tables.push((table, Vec::new()));
+                tables.len() - 1
+            }
+        };
+        let known = &mut tables[index].1;
+        for column in columns {
+            if !known.iter().any(|name| name.eq_ignore_ascii_case(&column)) {
+                known.push(column);
+            }
+        }
+    };
+
+    for block in sql::blocks(lossy_lines(reader)) {
+        let block = block?;
+        let Some(statement) = block.statement() else {
+            continue;
+        };
+        if let Some((table, columns)) = sql::parse_create_table(statement) {
+            record(table, columns);
+        } else if let Some(insert) = sql::parse_insert(statement) {
+            record(insert.table, insert.columns);
+        }
+    }
+
+    Ok(tables)
+}
+
+/// Render a starter config for the discovered tables
+///
+/// Likely PII columns get a `skip` rule naming the suggested method, so
+/// nothing is anonymized until the user opts in. Other columns are listed as
+/// comments for reference.
+fn init_config(tables: &[(String, Vec<String>)]) -> String {
+    let mut yaml = String::new();
+    yaml.push_str("# Scrub-DB configuration generated by `scrub-db init`\n");
+    yaml.push_str("# Columns that look like PII are listed with `skip` and a suggested method.\n");
+    yaml.push_str("# Review each one and replace `skip` to anonymize it.\n\n");
+    yaml.push_str("preserve_relationships: true\n\n");
+    yaml.push_str("custom_rules:\n");
+
+    let mut guessed = 0;
+    for (table, columns) in tables {
+        yaml.push_str(&format!("\n  # {}\n", table));
+        for column in columns {
+            let key = format!("{}.{}", table, column);
+            match detect_type_for_column(column) {
+                Some(method) => {
+                    guessed += 1;
+                    yaml.push_str(&format!(
+                        "  {}: skip  # suggested: {}\n",
+                        yaml_key(&key),
+                        method
+                    ));
+                }
+                None => yaml.push_str(&format!("  # {}\n", key)),
+            }
+        }
+    }
+
+    // An empty mapping still has to parse when no column was guessed
+    if guessed == 0 {
+        yaml = yaml.replacen("custom_rules:\n", "custom_rules: {}\n", 1);
+    }
+    yaml
+}
+
+/// Quote a rule key for YAML unless it is a plain dotted identifier
+fn yaml_key(key: &str) -> String {
+    if key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        key.to_string()
+    } else {
+        format!("{:?}", key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lossy_lines_invalid_utf8() {
+        let input: &[u8] = b"INSERT INTO t VALUES ('caf\xe9');\r\nok\n\xff\xfe\nlast";
+        let lines: Vec<String> = lossy_lines(input).map(Result::unwrap).collect();
+
+        assert_eq!(
+            lines,
+            [
+                "INSERT INTO t VALUES ('caf\u{fffd}');",
+                "ok",
+                "\u{fffd}\u{fffd}",
+                "last"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interruptible_stops_at_line_boundary() {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let mut reader = Interruptible::new(
+            BufReader::with_capacity(4, "one\ntwo three\nfour\n".as_bytes()),
+            &INTERRUPTED,
+        );
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "one\n");
+
+        // Interrupted mid-line: the line is finished, then the input ends
+        let mut start = [0; 2];
+        reader.read_exact(&mut start).unwrap();
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "o three\n");
+    }
+
+    #[test]
+    fn test_progress_enabled() {
+        assert!(Progress::Auto.enabled(false, true));
+        assert!(!Progress::Auto.enabled(true, true));
+        assert!(!Progress::Auto.enabled(false, false));
+        assert!(Progress::Always.enabled(true, false));
+        assert!(!Progress::Never.enabled(false, true));
+
+        let cli = Cli::try_parse_from(["scrub-db", "--progress"]).unwrap();
+        assert_eq!(cli.progress, Progress::Always);
+        let cli = Cli::try_parse_from(["scrub-db", "--progress=never"]).unwrap();
+        assert_eq!(cli.progress, Progress::Never);
+        let cli = Cli::try_parse_from(["scrub-db", "--progress", "init"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Init)));
+        let cli = Cli::try_parse_from(["scrub-db"]).unwrap();
+        assert_eq!(cli.progress, Progress::Auto);
+    }
+
+    /// A processor with a single `users.email: fake_email` column rule
+    fn email_processor() -> Processor {
+        let mut column_rules = RuleSet::new();
+        column_rules.add("users.email", AnonymizationType::FakeEmail);
+        let mut processor = Processor::new(column_rules, Anonymizer::with_seed(1));
+        processor.detectors = Vec::new();
+        processor
+    }
+
+    #[test]
+    fn test_statement_keeps_null_and_empty_values() {
+        let mut processor = email_processor();
+
+        let statement = "INSERT INTO users (id, email) VALUES (1, NULL), (2, ''), (3, null);";
+        assert_eq!(processor.statement(statement), statement);
+
+        // A quoted 'NULL' is a string like any other
+        let quoted = processor.statement("INSERT INTO users (id, email) VALUES (4, 'NULL');");
+        assert!(!quoted.contains("'NULL'"));
+    }
+
+    #[test]
+    fn test_statement_skips_listed_tables() {
+        let mut processor = email_processor();
+        processor
+            .column_rules
+            .add("email", AnonymizationType::FakeEmail);
+        processor.value_rules = vec![(Regex::new("@").unwrap(), AnonymizationType::Hash)];
+        processor.skip_tables = vec!["countries".to_string()];
+
+        let statement =
+            "INSERT INTO public.countries (code, email) VALUES ('FR', 'info@gouv.fr');\n";
+        assert_eq!(processor.statement(statement), statement);
+        assert!(processor.rule_counts.is_empty());
+        assert!(!processor
+            .statement("INSERT INTO users (id, email) VALUES (1, 'info@gouv.fr');")
+            .contains("info@gouv.fr"));
+    }
+
+    #[test]
+    fn test_statement_auto_detect() {
+        let statement = "INSERT INTO customers (id, e_mail, ssn, notes) VALUES (1, 'jane@x.com', '123-45-6789', 'vip');";
+        let mut processor = email_processor();
+        assert_eq!(processor.statement(statement), statement);
+
+        processor.auto_detect = true;
+        processor
+            .column_rules
+            .add("customers.ssn", AnonymizationType::Skip);
+        let anonymized = processor.statement(statement);
+        let row = &sql::parse_insert(&anonymized).unwrap().rows[0];
+        assert_ne!(row[1].text, "jane@x.com");
+        // Explicit rules win over detection
+        assert_eq!(row[2].text, "123-45-6789");
+        assert_eq!(row[3].text, "vip");
+        assert_eq!(processor.rule_counts["e_mail"].values, 1);
+    }
+
+    #[test]
+    fn test_statement_template() {
+        let mut processor = email_processor();
+        processor
+            .column_rules
+            .add("users.first_name", AnonymizationType::FakeFirstName);
+        processor
+            .column_rules
+            .add("users.last_name", AnonymizationType::FakeLastName);
+        processor.column_rules.add(
+            "users.login",
+            AnonymizationType::Template {
+                format: "{first}.{last}@example.com".to_string(),
+                from: vec![
+                    "users.first_name".to_string(),
+                    "users.last_name".to_string(),
+                ],
+            },
+        );
+
+        // The template column comes first but is filled after the names
+        let anonymized = processor.statement(
+            "INSERT INTO users (login, id, first_name, last_name) VALUES ('john.smith@acme.com', 1, 'John', 'Smith'), ('anon@acme.com', 2, NULL, 'Doe');",
+        );
+        let rows = sql::parse_insert(&anonymized).unwrap().rows;
+        let (first, last) = (&rows[0][2].text, &rows[0][3].text);
+        assert_ne!(first, "John");
+        assert_eq!(rows[0][0].text, format!("{}.{}@example.com", first, last));
+        // A NULL name leaves its placeholder empty
+        assert_eq!(rows[1][0].text, format!(".{}@example.com", rows[1][3].text));
+
+        // An UPDATE fills it from the assigned names
+        let update = processor.statement(
+            "UPDATE users SET first_name = 'John', last_name = 'Smith', login = 'j@acme.com' WHERE id = 1;",
+        );
+        let update = sql::parse_update(&update).unwrap();
+        assert_eq!(
+            update.assignments[2].1.text,
+            format!("{}.{}@example.com", first, last)
+        );
+    }
+
+    #[test]
+    fn test_statement_json_payload() {
+        let mut processor = email_processor();
+        processor.column_rules.add(
+            "events.payload",
+            AnonymizationType::Json(vec![(
+                "user.email".to_string(),
+                AnonymizationType::FakeEmail,
+            )]),
+        );
+
+        let users = processor.statement("INSERT INTO users (id, email) VALUES (1, 'jane@x.com');");
+        let fake = sql::parse_insert(&users).unwrap().rows[0][1].text.clone();
+
+        // mysqldump escapes the quotes inside the literal with backslashes
+        let events = processor.statement(
+            r#"INSERT INTO events (id, payload) VALUES (7, '{\"user\":{\"email\":\"jane@x.com\",\"note\":\"say \\\"hi\\\"\"}}');"#,
+        );
+        let payload = &sql::parse_insert(&events).unwrap().rows[0][1].text;
+        let document: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(document["user"]["email"], fake.as_str());
+        assert_eq!(document["user"]["note"], r#"say "hi""#);
+    }
+
+    #[test]
+    fn test_statement_update_matches_insert() {
+        let mut processor = email_processor();
+        let insert = processor.statement("INSERT INTO users (id, email) VALUES (1, 'real@x.com');");
+        let fake = sql::parse_insert(&insert).unwrap().rows[0][1].text.clone();
+
+        let update = processor.statement(
+            "UPDATE users SET email = 'new@x.com', name = 'Al' WHERE email = 'real@x.com';",
+        );
+        assert_eq!(update.matches(&fake).count(), 1);
+        assert!(!update.contains("new@x.com"));
+        assert!(update.contains("name = 'Al'"));
+        assert_eq!(processor.rule_counts["users.email"].values, 3);
+    }
+
+    #[test]
+    fn test_statement_delete_matches_insert() {
+        let mut processor = email_processor();
+        let insert = processor.statement("INSERT INTO users (id, email) VALUES (1, 'real@x.com');");
+        let fake = sql::parse_insert(&insert).unwrap().rows[0][1].text.clone();
+
+        let delete = processor.statement("DELETE FROM users WHERE email='real@x.com';");
+        assert_eq!(delete, format!("DELETE FROM users WHERE email='{}';", fake));
+    }
+
+    #[test]
+    fn test_report_unused_rules() {
+        let mut processor = email_processor();
+        processor
+            .column_rules
+            .add("orders.ssn", AnonymizationType::MaskSSN);
+        processor.statement("INSERT INTO users (id, email) VALUES (1, 'john@acme.com');");
+
+        assert_eq!(Report::new(&processor, 1).unused_rules(), ["orders.ssn"]);
+    }
+
+    #[test]
+    fn test_samples_capped_per_rule() {
+        let mut processor = email_processor();
+        processor.sample_size = 2;
+        for id in 0..5 {
+            processor.statement(&format!(
+                "INSERT INTO users (id, email) VALUES ({}, 'user{}@acme.com');",
+                id, id
+            ));
+        }
+        // Workers' samples are merged under the same cap
+        let mut worker = processor.worker();
+        worker.statement("INSERT INTO users (id, email) VALUES (9, 'user0@acme.com');");
+        worker.statement("INSERT INTO users (id, email) VALUES (9, 'late@acme.com');");
+        processor.merge(worker);
+
+        let samples = &processor.samples["users.email"];
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].0, "us*** (14 chars)");
+        assert!(!samples[0].1.contains("user0"));
+    }
+
+    #[test]
+    fn test_process_sql_multiline_insert() {
+        let input = "-- users\n\
+            INSERT INTO users (id, email)\n\
+            VALUES\n\
+            \t(1, 'john@acme.com'),\n\
+            \t(2, 'jane;\n\
+            doe@acme.com');\n\
+            SELECT 1;\n";
+        let mut processor = email_processor();
+        let mut output = Vec::new();
+
+        let (lines, truncated) = process_sql(
+            Box::new(input.as_bytes()),
+            &mut output,
+            &mut processor,
+            None,
+        )
+        .unwrap();
+        assert!(!truncated);
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(lines, 7);
+        assert!(!output.contains("acme.com"));
+        // Every line keeps its layout, only the values inside it change
+        let layout: Vec<&str> = output
+            .lines()
+            .map(|line| &line[..line.len().min(4)])
+            .collect();
+        assert_eq!(layout, ["-- u", "INSE", "VALU", "\t(1,", "\t(2,", "SELE"]);
+        assert_eq!(processor.rule_counts["users.email"].values, 2);
+    }
+
+    #[test]
+    fn test_process_sql_limit_stops_at_statement_boundary() {
+        let input = "INSERT INTO users (id, email) VALUES (1, 'a@acme.com');\n\
+            INSERT INTO users (id, email) VALUES\n\
+            (2, 'b@acme.com'),\n\
+            (3, 'c@acme.com');\n\
+            INSERT INTO users (id, email) VALUES (4, 'd@acme.com');\n";
+        let run = |limit| {
+            let mut output = Vec::new();
+            let (lines, truncated) = process_sql(
+                Box::new(input.as_bytes()),
+                &mut output,
+                &mut email_processor(),
+                Some(limit),
+            )
+            .unwrap();
+            (lines, truncated, String::from_utf8(output).unwrap())
+        };
+
+        // The second statement straddles the limit and is finished whole
+        let (lines, truncated, output) = run(2);
+        assert_eq!((lines, truncated), (4, true));
+        assert_eq!(output.lines().count(), 4);
+        assert!(output.ends_with(");\n"));
+        assert!(!output.contains("acme.com"));
+
+        let (lines, truncated, _) = run(1);
+        assert_eq!((lines, truncated), (1, true));
+
+        // Reaching the limit exactly at the end of the input isn't a truncation
+        let (lines, truncated, _) = run(5);
+        assert_eq!((lines, truncated), (5, false));
+    }
+
+    #[test]
+    fn test_process_sql_bom_and_crlf() {
+        let input = "\u{feff}INSERT INTO users (id, email) VALUES (1, 'john@acme.com');\r\n\
+            INSERT INTO users (id, email)\r\n\
+            VALUES (2, 'jane@acme.com');\r\n";
+        let mut processor = email_processor();
+        let mut output = Vec::new();
+
+        let (lines, truncated) = process_sql(
+            Box::new(input.as_bytes()),
+            &mut output,
+            &mut processor,
+            None,
+        )
+        .unwrap();
+        assert!(!truncated);
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(lines, 3);
+        assert!(!output.contains("acme.com"));
+        // Line endings come out as plain newlines, without the byte order mark
+        assert!(output.starts_with("INSERT INTO users"));
+        assert!(!output.contains('\r'));
+        assert_eq!(output.lines().count(), 3);
+        assert_eq!(processor.rule_counts["users.email"].values, 2);
+    }
+
+    #[test]
+    fn test_process_sql_keeps_comments() {
+        let input = "-- users.email of jane@acme.com\n\
+            /*\n\
+            users.email: joe@acme.com\n\
+            */\n\
+            SELECT id FROM users WHERE users.email = 'jane@acme.com'; -- ask jane@acme.com\n";
+        let mut processor = email_processor();
+        processor.detectors = vec![Detector::Email];
+        let mut output = Vec::new();
+
+        process_sql(
+            Box::new(input.as_bytes()),
+            &mut output,
+            &mut processor,
+            None,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..4], input.lines().collect::<Vec<_>>()[..4]);
+        assert!(!lines[4].contains("= 'jane@acme.com'"));
+        assert!(lines[4].ends_with("; -- ask jane@acme.com"));
+        assert_eq!(processor.detector_lines, 1);
+    }
+
+    #[test]
+    fn test_process_sql_delimiter_section() {
+        let routine = "DELIMITER ;;\n\
+            CREATE TRIGGER users_audit AFTER INSERT ON users FOR EACH ROW\n\
+            BEGIN\n  \
+            INSERT INTO users (id, email) VALUES (NEW.id, 'audit@acme.com');\n\
+            END ;;\n\
+            DELIMITER ;\n";
+        let input = format!(
+            "{}INSERT INTO users (id, email) VALUES (1, 'john@acme.com');\n",
+            routine
+        );
+        let mut processor = email_processor();
+        let mut output = Vec::new();
+
+        process_sql(
+            Box::new(io::Cursor::new(input)),
+            &mut output,
+            &mut processor,
+            None,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.starts_with(routine));
+        assert!(!output[routine.len()..].contains("acme.com"));
+        assert_eq!(processor.rule_counts["users.email"].values, 1);
+    }
+
+    #[test]
+    fn test_process_sql_insert_under_delimiter() {
+        let input = "DELIMITER //\n\
+            INSERT INTO users (id, email) VALUES (1, 'real@corp.com')//\n\
+            DELIMITER ;\n";
+        for jobs in [1, 2] {
+            let mut processor = email_processor();
+            let mut output = Vec::new();
+            if jobs == 1 {
+                process_sql(
+                    Box::new(input.as_bytes()),
+                    &mut output,
+                    &mut processor,
+                    None,
+                )
+            } else {
+                process_sql_parallel(
+                    Box::new(input.as_bytes()),
+                    &mut output,
+                    &mut processor,
+                    jobs,
+                    None,
+                )
+            }
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            let lines: Vec<&str> = output.lines().collect();
+            assert_eq!(lines.len(), 3);
+            assert_eq!(lines[0], "DELIMITER //");
+            assert!(!lines[1].contains("real@corp.com"));
+            assert!(lines[1].ends_with("')//"));
+            assert_eq!(lines[2], "DELIMITER ;");
+        }
+    }
+
+    /// A sink keeping each statement it is given, like a database sink would
+    #[derive(Default)]
+    struct Statements(Vec<String>);
+
+    impl OutputSink for Statements {
+        fn write_statement(&mut self, sql: &str) -> io::Result<()> {
+            self.0.push(sql.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_process_sql_output_sink() {
+        let input = "-- users\n\
+            INSERT INTO users (id, email)\n\
+            VALUES (1, 'john@acme.com');\n\
+            INSERT INTO users (id, email) VALUES (2, 'jane@acme.com');\n";
+        for jobs in [1, 2] {
+            let mut sink = Statements::default();
+            let mut processor = email_processor();
+            if jobs == 1 {
+                process_sql(Box::new(input.as_bytes()), &mut sink, &mut processor, None)
+            } else {
+                process_sql_parallel(
+                    Box::new(input.as_bytes()),
+                    &mut sink,
+                    &mut processor,
+                    jobs,
+                    None,
+                )
+            }
+            .unwrap();
+
+            // One call per statement, multi-line ones included
+            assert_eq!(sink.0.len(), 3);
+            assert_eq!(sink.0[0], "-- users\n");
+            assert!(sink.0[1].starts_with("INSERT INTO users (id, email)\nVALUES (1, '"));
+            assert!(sink.0[2].ends_with("');\n"));
+            assert!(!sink.0.concat().contains("acme.com"));
+        }
+    }
+
+    #[test]
+    fn test_auto_detect_report_with_jobs() {
+        let input = "INSERT INTO customers (id, e_mail) VALUES (1, 'jane@x.com');\n\
+            INSERT INTO customers (id, e_mail) VALUES (2, 'john@x.com');\n";
+        for jobs in [1, 2] {
+            let mut processor = email_processor();
+            processor.auto_detect = true;
+            let mut output = Vec::new();
+            if jobs == 1 {
+                process_sql(
+                    Box::new(input.as_bytes()),
+                    &mut output,
+                    &mut processor,
+                    None,
+                )
+            } else {
+                process_sql_parallel(
+                    Box::new(input.as_bytes()),
+                    &mut output,
+                    &mut processor,
+                    jobs,
+                    None,
+                )
+            }
+            .unwrap();
+
+            let report = Report::new(&processor, 2);
+            assert_eq!(report.rules["e_mail"].values, 2, "jobs {}", jobs);
+            assert_eq!(report.unused_rules(), ["users.email"], "jobs {}", jobs);
+        }
+    }
+
+    #[test]
+    fn test_parse_config_formats() {
+        let yaml = "preserve_relationships: false\n\
+                    hash_length: 12\n\
+                    mask_char: X\n\
+                    custom_rules:\n  \
+                    users.email: fake_email\n  \
+                    accounts.iban: { method: mask, keep_first: 2, keep_last: 4 }\n  \
+                    suppliers.name: { method: fake_name, locale: ja, preserve: false }\n  \
+                    hash: [users.ssn, orders.card]\n\
+                    skip_tables: [audit_log]\n";
+        let toml = "preserve_relationships = false\n\
+                    hash_length = 12\n\
+                    mask_char = \"X\"\n\
+                    skip_tables = [\"audit_log\"]\n\
+                    [custom_rules]\n\
+                    \"users.email\" = \"fake_email\"\n\
+                    \"accounts.iban\" = { method = \"mask\", keep_first = 2, keep_last = 4 }\n\
+                    \"suppliers.name\" = { method = \"fake_name\", locale = \"ja\", preserve = false }\n\
+                    hash = [\"users.ssn\", \"orders.card\"]\n";
+        let json = r#"{
+            "preserve_relationships": false,
+            "hash_length": 12,
+            "mask_char": "X",
+            "custom_rules": {
+                "users.email": "fake_email",
+                "accounts.iban": { "method": "mask", "keep_first": 2, "keep_last": 4 },
+                "suppliers.name": { "method": "fake_name", "locale": "ja", "preserve": false },
+                "hash": ["users.ssn", "orders.card"]
+            },
+            "skip_tables": ["audit_log"]
+        }"#;
+
+        let from_yaml = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+        assert_eq!(from_yaml.custom_rules.len(), 5);
+        assert_eq!(
+            from_yaml.custom_rules["accounts.iban"].anon_type(),
+            Some(AnonymizationType::Mask {
+                keep_first: 2,
+                keep_last: 4
+            })
+        );
+        for config in [
+            parse_config(toml, ConfigFormat::Toml).unwrap(),
+            parse_config(json, ConfigFormat::Json).unwrap(),
+        ] {
+            assert_eq!(config.custom_rules, from_yaml.custom_rules);
+            assert_eq!(config.skip_tables, from_yaml.skip_tables);
+            assert!(!config.preserve_relationships);
+            assert_eq!(config.hash_length, Some(12));
+            assert_eq!(config.mask_char, 'X');
+        }
+    }
+
+    #[test]
+    fn test_config_format_from_path() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("scrub-db.toml")),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("conf/.scrub-db.JSON")),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("scrub-db.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("rules")),
+            ConfigFormat::Yaml
+        );
+    }
+
+    fn cache(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(original, fake)| (original.to_string(), fake.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_caches() {
+        let first = cache(&[("a@x.com", "f1@y.com"), ("b@x.com", "f2@y.com")]);
+        let second = cache(&[("b@x.com", "f2@y.com"), ("c@x.com", "f3@y.com")]);
+        let (merged, conflicts) = merge_caches(vec![first, second], MergeStrategy::Error);
+
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged["c@x.com"], "f3@y.com");
+    }
+
+    #[test]
+    fn test_merge_caches_conflicts() {
+        let caches = || {
+            vec![
+                cache(&[("a@x.com", "f1@y.com"), ("b@x.com", "f2@y.com")]),
+                cache(&[("a@x.com", "other@y.com"), ("b@x.com", "f2@y.com")]),
+                cache(&[("a@x.com", "third@y.com")]),
+            ]
+        };
+
+        let (merged, conflicts) = merge_caches(caches(), MergeStrategy::First);
+        assert_eq!(conflicts, ["a@x.com"]);
+        assert_eq!(merged["a@x.com"], "f1@y.com");
+
+        let (merged, conflicts) = merge_caches(caches(), MergeStrategy::Last);
+        assert_eq!(conflicts, ["a@x.com"]);
+        assert_eq!(merged["a@x.com"], "third@y.com");
+        assert_eq!(merged["b@x.com"], "f2@y.com");
+    }
+
+    #[test]
+    fn test_verify_flags_values_that_are_not_fakes() {
+        let mut anonymizer = Anonymizer::with_seed(1);
+        let email = anonymizer.anonymize("jane@acme.com", &AnonymizationType::FakeEmail, true);
+        let phone = anonymizer.anonymize("212-555-9876", &AnonymizationType::FakePhone, true);
+        let dump = format!(
+            "INSERT INTO users VALUES (1, '{}', '555-123-4567', '{}');\n\
+             INSERT INTO users VALUES (2, 'john@acme.com', '4111 1111 1111 1111');\n",
+            email, phone
+        );
+
+        let verification = verify(dump.as_bytes()).unwrap();
+        assert_eq!(verification.lines, 2);
+        let leaks: Vec<(usize, &str, &str)> = verification
+            .leaks
+            .iter()
+            .map(|f| (f.line, f.detector, f.preview.as_str()))
+            .collect();
+        assert_eq!(
+            leaks,
+            [
+                (1, "phone", "5**-***-****"),
+                (2, "email", "j***@****.***"),
+                (2, "credit_card", "4*** **** **** ****")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_init_config_from_dump() {
+        let dump = "CREATE TABLE users (\n  id INT PRIMARY KEY,\n  email VARCHAR(255),\n  created_at DATE\n);\n\
+            INSERT INTO users (id, email, phone) VALUES (1, 'a@b.com', '555');\n\
+            INSERT INTO `audit log` (id, note) VALUES (1, 'x');\n";
+        let tables = discover_tables(dump.as_bytes()).unwrap();
+        assert_eq!(
+            tables,
+            [
+                (
+                    "users".to_string(),
+                    vec![
+                        "id".into(),
+                        "email".into(),
+                        "created_at".into(),
+                        "phone".into()
+                    ]
+                ),
+                ("audit log".to_string(), vec!["id".into(), "note".into()]),
+            ]
+        );
+
+        let yaml = init_config(&tables);
+        assert!(yaml.contains("  users.email: skip  # suggested: fake_email\n"));
+        assert!(yaml.contains("  # users.id\n"));
+
+        // Nothing is transformed until the user opts in
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(config.custom_rules.len(), 2);
+        assert!(config
+            .custom_rules
+            .values()
+            .all(|rule| rule.method() == "skip"));
+
+        let empty: Config = serde_yaml::from_str(&init_config(&tables[1..])).unwrap();
+        assert!(empty.custom_rules.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+// Relationship cache
+// Maps originals (or `group\0original` keys) to their fakes. Unbounded by
+// default; with a capacity the least recently used entries are evicted, so
+// memory stays flat on huge dumps at the cost of consistency for the
+// evicted values.
+
+use ahash::AHashMap;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default)]
+pub(crate) struct FakeCache {
+    /// Fake and last-use tick for each key
+    entries: AHashMap<String, (String, u64)>,
+    /// Keys by last-use tick, oldest first; only kept while bounded
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    capacity: Option<usize>,
+    evictions: usize,
+}
+
+impl FakeCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `capacity` entries, evicting the least recently used
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        if self.capacity.is_none() {
+            // Existing entries have no recorded use, so they go first
+            for (key, (_, tick)) in self.entries.iter_mut() {
+                self.tick += 1;
+                *tick = self.tick;
+                self.recency.insert(self.tick, key.clone());
+            }
+        }
+        self.capacity = Some(capacity.max(1));
+        self.evict();
+    }
+
+    /// The fake cached for `key`, marking it as recently used
+    pub(crate) fn get(&mut self, key: &str) -> Option<String> {
+        let (fake, tick) = self.entries.get_mut(key)?;
+        if self.capacity.is_some() {
+            self.tick += 1;
+            let key = self.recency.remove(tick).expect("every entry has a tick");
+            *tick = self.tick;
+            self.recency.insert(self.tick, key);
+        }
+        Some(fake.clone())
+    }
+
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Cache `fake` for `key` unless it already has one, returning the cached fake
+    pub(crate) fn get_or_insert(&mut self, key: String, fake: String) -> String {
+        if let Some(cached) = self.get(&key) {
+            return cached;
+        }
+        self.insert(key, fake.clone());
+        fake
+    }
+
+    /// Cache `fake` for `key`, replacing any fake it had
+    pub(crate) fn insert(&mut self, key: String, fake: String) {
+        self.tick += 1;
+        if self.capacity.is_some() {
+            self.recency.insert(self.tick, key.clone());
+        }
+        if let Some((_, tick)) = self.entries.insert(key, (fake, self.tick)) {
+            self.recency.remove(&tick);
+        }
+        self.evict();
+    }
+
+    /// Drop every entry, keeping the capacity and the eviction count
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        let additional = match self.capacity {
+            Some(capacity) => additional.min(capacity.saturating_sub(self.entries.len())),
+            None => additional,
+        };
+        self.entries.reserve(additional);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.entries.iter().map(|(key, (fake, _))| (key, fake))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// How many entries have been evicted to stay within the capacity
+    pub(crate) fn evictions(&self) -> usize {
+        self.evictions
+    }
+
+    fn evict(&mut self) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        while self.entries.len() > capacity {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+            self.evictions += 1;
+        }
+    }
+}
+
+impl Extend<(String, String)> for FakeCache {
+    fn extend<I: IntoIterator<Item = (String, String)>>(&mut self, iter: I) {
+        for (key, fake) in iter {
+            self.insert(key, fake);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unbounded_cache_keeps_everything() {
+        let mut cache = FakeCache::new();
+        for i in 0..100 {
+            cache.insert(i.to_string(), format!("fake{}", i));
+        }
+        assert_eq!(cache.len(), 100);
+        assert_eq!(cache.evictions(), 0);
+        assert_eq!(cache.get("0").as_deref(), Some("fake0"));
+    }
+
+    #[test]
+    fn test_bounded_cache_evicts_least_recently_used() {
+        let mut cache = FakeCache::new();
+        cache.set_capacity(2);
+        cache.insert("a".to_string(), "1".to_string());
+        cache.insert("b".to_string(), "2".to_string());
+        // Using `a` makes `b` the oldest
+        assert_eq!(cache.get("a").as_deref(), Some("1"));
+        cache.insert("c".to_string(), "3".to_string());
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evictions(), 1);
+        assert!(!cache.contains_key("b"));
+        assert_eq!(cache.get_or_insert("a".to_string(), "x".to_string()), "1");
+        assert_eq!(cache.get_or_insert("b".to_string(), "4".to_string()), "4");
+        assert!(!cache.contains_key("c"));
+    }
+
+    #[test]
+    fn test_capacity_applies_to_existing_entries() {
+        let mut cache = FakeCache::new();
+        cache.extend((0..10).map(|i| (i.to_string(), i.to_string())));
+        cache.set_capacity(3);
+
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.evictions(), 7);
+    }
+}
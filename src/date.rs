@@ -0,0 +1,104 @@
+// Date parsing and arithmetic for date shifting
+// Only the formats commonly found in SQL dumps are understood:
+// `YYYY-MM-DD` and `YYYY-MM-DD HH:MM:SS`.
+
+/// Shift a date or timestamp string by a number of days
+///
+/// The time of day (and anything after it) is kept as-is. Returns `None` if
+/// the value isn't a recognized date.
+pub(crate) fn shift_days(value: &str, days: i64) -> Option<String> {
+    let (date, time) = split_date(value)?;
+    let (year, month, day) = parse_date(date)?;
+    let (year, month, day) = civil_from_days(days_from_civil(year, month, day) + days);
+    Some(format!("{:04}-{:02}-{:02}{}", year, month, day, time))
+}
+
+/// Split `YYYY-MM-DD HH:MM:SS` into the date and the ` HH:MM:SS` suffix
+fn split_date(value: &str) -> Option<(&str, &str)> {
+    if value.len() < 10 || !value.is_char_boundary(10) {
+        return None;
+    }
+    let (date, time) = value.split_at(10);
+    if time.is_empty() {
+        return Some((date, time));
+    }
+
+    let bytes = time.as_bytes();
+    let is_time = bytes.len() >= 9
+        && bytes[0] == b' '
+        && bytes[3] == b':'
+        && bytes[6] == b':'
+        && [1, 2, 4, 5, 7, 8]
+            .iter()
+            .all(|&i| bytes[i].is_ascii_digit());
+    is_time.then_some((date, time))
+}
+
+/// Parse and validate `YYYY-MM-DD`
+fn parse_date(date: &str) -> Option<(i64, u32, u32)> {
+    let bytes = date.as_bytes();
+    if !date.is_ascii() || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    let year: i64 = date[0..4].parse().ok()?;
+    let month: u32 = date[5..7].parse().ok()?;
+    let day: u32 = date[8..10].parse().ok()?;
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+
+    // Reject days past the end of the month by round-tripping
+    let round_trip = civil_from_days(days_from_civil(year, month, day));
+    (round_trip == (year, month, day)).then_some((year, month, day))
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Proleptic Gregorian date for a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift_days() {
+        assert_eq!(shift_days("2024-02-28", 2), Some("2024-03-01".to_string()));
+        assert_eq!(shift_days("2024-01-01", -1), Some("2023-12-31".to_string()));
+        assert_eq!(
+            shift_days("2023-12-31 23:59:59", 1),
+            Some("2024-01-01 23:59:59".to_string())
+        );
+    }
+
+    #[test]
+    fn test_shift_days_rejects_non_dates() {
+        assert_eq!(shift_days("2023-02-29", 1), None);
+        assert_eq!(shift_days("not a date", 1), None);
+        assert_eq!(shift_days("2024-01-01T10:00", 1), None);
+        assert_eq!(shift_days("é", 1), None);
+        assert_eq!(shift_days("20é4-01-01", 1), None);
+    }
+}
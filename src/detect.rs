@@ -0,0 +1,734 @@
+// PII detectors
+// Finds emails and phone numbers anywhere in a line of text, for input the
+// SQL parser doesn't understand, and guesses types from column names.
+
+use crate::persona::EMAIL_DOMAINS;
+use crate::{sql, AnonymizationType, Anonymizer};
+use rand::rngs::StdRng;
+use rand::Rng;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::sync::LazyLock;
+
+/// Emails found by the line detectors
+static EMAIL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}\b").unwrap());
+
+/// Phone numbers found by the line detectors
+static PHONE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d{3}[-.]?\d{3}[-.]?\d{4}\b").unwrap());
+
+/// Digits 4 to 8 of every fake phone number, putting it in the 555-0100 to
+/// 555-0199 range reserved for fiction
+const FAKE_PHONE_EXCHANGE: &str = "55501";
+
+/// Credit card numbers found by the line detectors
+static CREDIT_CARD_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d{4}[-\s]?\d{4}[-\s]?\d{4}[-\s]?\d{4}\b").unwrap());
+
+/// A built-in detector for PII anywhere in a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detector {
+    Email,
+    Phone,
+    CreditCard,
+}
+
+impl Detector {
+    /// Every built-in detector
+    pub const ALL: &'static [Detector] = &[Detector::Email, Detector::Phone, Detector::CreditCard];
+
+    /// Detectors that run unless `enabled_detectors` says otherwise
+    pub const DEFAULT: &'static [Detector] = Detector::ALL;
+
+    /// Parse a detector name from config (`"email"`, `"phone"`, `"credit_card"`)
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "email" => Some(Self::Email),
+            "phone" => Some(Self::Phone),
+            "credit_card" => Some(Self::CreditCard),
+            _ => None,
+        }
+    }
+
+    /// The config name of this detector
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Phone => "phone",
+            Self::CreditCard => "credit_card",
+        }
+    }
+
+    /// The pattern this detector looks for
+    pub fn regex(&self) -> &'static Regex {
+        match self {
+            Self::Email => &EMAIL_REGEX,
+            Self::Phone => &PHONE_REGEX,
+            Self::CreditCard => &CREDIT_CARD_REGEX,
+        }
+    }
+
+    /// Whether a found value has the shape of this crate's own fake output
+    ///
+    /// Fake emails use the reserved `example.*` domains, fake phone numbers
+    /// the reserved `555-01xx` range and fake credit cards start with `4242`.
+    pub fn is_known_fake(&self, value: &str) -> bool {
+        match self {
+            Self::Email => value.rsplit_once('@').is_some_and(|(_, domain)| {
+                EMAIL_DOMAINS
+                    .iter()
+                    .any(|fake| domain.eq_ignore_ascii_case(fake))
+            }),
+            Self::Phone => {
+                let digits: String = value.chars().filter(char::is_ascii_digit).collect();
+                digits.len() == 10 && digits[3..8] == *FAKE_PHONE_EXCHANGE
+            }
+            Self::CreditCard => value
+                .chars()
+                .filter(char::is_ascii_digit)
+                .collect::<String>()
+                .starts_with("4242"),
+        }
+    }
+
+    /// Whether a rule's method applies to the values this detector finds
+    fn handles(&self, anon_type: &AnonymizationType) -> bool {
+        match self {
+            Self::Email => matches!(anon_type, AnonymizationType::FakeEmail),
+            Self::Phone => matches!(anon_type, AnonymizationType::FakePhone),
+            Self::CreditCard => matches!(
+                anon_type,
+                AnonymizationType::FakeCreditCard | AnonymizationType::MaskCreditCard
+            ),
+        }
+    }
+}
+
+/// Move every number in `phone` the phone detector would find into the
+/// reserved `555-01xx` range, keeping its area code and separators
+///
+/// Fake phones go through this so `verify` can tell them from real ones.
+pub(crate) fn reserve_phone(phone: &str, rng: &mut StdRng) -> String {
+    PHONE_REGEX
+        .replace_all(phone, |found: &regex::Captures| {
+            let mut index = 0;
+            found[0]
+                .chars()
+                .map(|c| {
+                    if !c.is_ascii_digit() {
+                        return c;
+                    }
+                    index += 1;
+                    match index {
+                        1..=3 => c,
+                        4..=8 => char::from(FAKE_PHONE_EXCHANGE.as_bytes()[index - 4]),
+                        _ => char::from(rng.gen_range(b'0'..=b'9')),
+                    }
+                })
+                .collect::<String>()
+        })
+        .into_owned()
+}
+
+/// Mask a detected value for display, keeping only its first character
+///
+/// Letters and digits after the first become `*` while separators stay, so
+/// `john@example.com` previews as `j***@*******.***` without leaking PII.
+pub fn redact(value: &str) -> String {
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i > 0 && c.is_alphanumeric() { '*' } else { c })
+        .collect()
+}
+
+/// Anonymize emails, phone numbers and credit card numbers found anywhere in a line
+///
+/// Detected values are only replaced when a rule pattern matches somewhere in
+/// the line and that rule's method fits the detector (`FakeEmail` for emails,
+/// `FakePhone` for phones, `MaskCreditCard` or `FakeCreditCard` for cards).
+/// Each match is replaced at its own position exactly once, so the rest of
+/// the line is untouched and a fake is never replaced again by a later match;
+/// replacements inside a quoted SQL string are escaped for it. This is the
+/// whole-line fallback the CLI uses for lines outside parsed `INSERT`
+/// statements.
+pub fn anonymize_line(
+    line: &str,
+    rules: &[(Regex, AnonymizationType)],
+    anonymizer: &mut Anonymizer,
+    preserve_relationships: bool,
+) -> String {
+    anonymize_line_with(
+        line,
+        rules,
+        Detector::DEFAULT,
+        anonymizer,
+        preserve_relationships,
+    )
+}
+
+/// Like `anonymize_line`, running only the given detectors
+pub fn anonymize_line_with(
+    line: &str,
+    rules: &[(Regex, AnonymizationType)],
+    detectors: &[Detector],
+    anonymizer: &mut Anonymizer,
+    preserve_relationships: bool,
+) -> String {
+    // The first rule that matches anywhere in the line picks the method
+    let anon_type = rules
+        .iter()
+        .find(|(pattern, _)| pattern.is_match(line))
+        .map(|(_, t)| t)
+        .unwrap_or(&AnonymizationType::Skip);
+
+    // Each method belongs to one detector, so matches never overlap
+    let mut replacements = Vec::new();
+    for detector in detectors {
+        if !detector.handles(anon_type) {
+            continue;
+        }
+        for cap in detector.regex().find_iter(line) {
+            let fake = anonymizer.anonymize(cap.as_str(), anon_type, preserve_relationships);
+            // A fake such as `o'brien@example.com` must not end the literal it lands in
+            let fake = if sql::in_string_literal(line, cap.start()) {
+                sql::escape_literal(&fake)
+            } else {
+                fake
+            };
+            replacements.push((cap.range(), fake));
+        }
+    }
+
+    sql::replace_spans(line, replacements)
+}
+
+/// Anonymize every substring of a value matching a value rule pattern
+///
+/// Each rule pairs a user-supplied pattern with its method, for PII in free
+/// text that no column rule can target. When matches of different rules
+/// overlap, the one starting first wins, then the longest, then the earlier
+/// rule.
+pub fn anonymize_matches(
+    text: &str,
+    rules: &[(Regex, AnonymizationType)],
+    anonymizer: &mut Anonymizer,
+    preserve_relationships: bool,
+) -> String {
+    replace_matches(text, rules, anonymizer, preserve_relationships, false)
+}
+
+/// Like `anonymize_matches` for a raw SQL line, escaping replacements that
+/// land inside a quoted string
+pub fn anonymize_line_matches(
+    line: &str,
+    rules: &[(Regex, AnonymizationType)],
+    anonymizer: &mut Anonymizer,
+    preserve_relationships: bool,
+) -> String {
+    replace_matches(line, rules, anonymizer, preserve_relationships, true)
+}
+
+fn replace_matches(
+    text: &str,
+    rules: &[(Regex, AnonymizationType)],
+    anonymizer: &mut Anonymizer,
+    preserve_relationships: bool,
+    escape_literals: bool,
+) -> String {
+    let mut matches: Vec<(regex::Match, usize)> = rules
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (pattern, _))| pattern.find_iter(text).map(move |m| (m, index)))
+        .filter(|(m, _)| !m.is_empty())
+        .collect();
+    matches.sort_by_key(|(m, index)| (m.start(), Reverse(m.end()), *index));
+
+    let mut replacements = Vec::new();
+    let mut end = 0;
+    for (m, index) in matches {
+        if m.start() < end {
+            continue;
+        }
+        end = m.end();
+        let fake = anonymizer.anonymize(m.as_str(), &rules[index].1, preserve_relationships);
+        let fake = if escape_literals && sql::in_string_literal(text, m.start()) {
+            sql::escape_literal(&fake)
+        } else {
+            fake
+        };
+        replacements.push((m.range(), fake));
+    }
+
+    sql::replace_spans(text, replacements)
+}
+
+/// Guess the anonymization type for a column from its name
+///
+/// Matches common PII column names case-insensitively, with `-`, spaces and
+/// `_` treated alike: `email`, `mobile_phone`, `first_name`, `ssn`,
+/// `card_number`, `date_of_birth` and the like. Names match on whole words at
+/// the end, so `contact_email` is an email but `email_verified` and
+/// `password_updated_at` are not, and `is_`/`has_` flags never match. Returns
+/// `None` for columns that don't look like PII. `scrub-db init` suggests
+/// these types, and the CLI applies them to unruled columns when
+/// `auto_detect` is on.
+pub fn detect_type_for_column(column: &str) -> Option<AnonymizationType> {
+    let name = column.to_ascii_lowercase().replace(['-', ' '], "_");
+    let words: Vec<&str> = name.split('_').collect();
+    if matches!(words[0], "is" | "has") {
+        return None;
+    }
+    let ends = |parts: &[&str]| {
+        parts
+            .iter()
+            .any(|part| words.ends_with(&part.split('_').collect::<Vec<_>>()))
+    };
+    let is = |parts: &[&str]| parts.iter().any(|part| words.contains(part));
+
+    let method = if ends(&["email", "e_mail", "email_address"]) {
+        AnonymizationType::FakeEmail
+    } else if ends(&[
+        "phone",
+        "mobile",
+        "fax",
+        "tel",
+        "phone_number",
+        "mobile_number",
+    ]) {
+        AnonymizationType::FakePhone
+    } else if ends(&["ssn", "social_security", "social_security_number"]) {
+        AnonymizationType::MaskSSN
+    } else if ends(&["iban"]) {
+        AnonymizationType::MaskIban
+    } else if ends(&["credit_card", "card_number", "cc_number"]) {
+        AnonymizationType::MaskCreditCard
+    } else if ends(&["first_name", "firstname", "given_name"]) {
+        AnonymizationType::FakeFirstName
+    } else if ends(&["last_name", "lastname", "surname", "family_name"]) {
+        AnonymizationType::FakeLastName
+    } else if name == "name" || ends(&["full_name", "fullname", "display_name"]) {
+        AnonymizationType::FakeName
+    } else if ends(&["street", "street_address"]) {
+        AnonymizationType::FakeStreetAddress
+    } else if ends(&["address"]) && !is(&["ip"]) {
+        AnonymizationType::FakeAddress
+    } else if ends(&["city"]) {
+        AnonymizationType::FakeCity
+    } else if ends(&[
+        "postal",
+        "postal_code",
+        "postcode",
+        "zip",
+        "zipcode",
+        "zip_code",
+    ]) {
+        AnonymizationType::FakeZip
+    } else if ends(&[
+        "date_of_birth",
+        "birth_date",
+        "birthdate",
+        "birthday",
+        "dob",
+    ]) {
+        AnonymizationType::ShiftDate
+    } else if ends(&["password", "password_hash", "secret", "api_key", "token"]) {
+        AnonymizationType::Hash
+    } else {
+        return None;
+    };
+    Some(method)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_detect_type_for_column() {
+        let cases = [
+            ("email", Some(AnonymizationType::FakeEmail)),
+            ("Email", Some(AnonymizationType::FakeEmail)),
+            ("e_mail", Some(AnonymizationType::FakeEmail)),
+            ("contact-email", Some(AnonymizationType::FakeEmail)),
+            ("email_address", Some(AnonymizationType::FakeEmail)),
+            ("phone", Some(AnonymizationType::FakePhone)),
+            ("mobile", Some(AnonymizationType::FakePhone)),
+            ("mobile_phone", Some(AnonymizationType::FakePhone)),
+            ("work tel", Some(AnonymizationType::FakePhone)),
+            ("fax", Some(AnonymizationType::FakePhone)),
+            ("ssn", Some(AnonymizationType::MaskSSN)),
+            ("social_security_number", Some(AnonymizationType::MaskSSN)),
+            ("iban", Some(AnonymizationType::MaskIban)),
+            ("credit_card", Some(AnonymizationType::MaskCreditCard)),
+            ("card_number", Some(AnonymizationType::MaskCreditCard)),
+            ("cc_number", Some(AnonymizationType::MaskCreditCard)),
+            ("first_name", Some(AnonymizationType::FakeFirstName)),
+            ("firstName", Some(AnonymizationType::FakeFirstName)),
+            ("given_name", Some(AnonymizationType::FakeFirstName)),
+            ("last_name", Some(AnonymizationType::FakeLastName)),
+            ("surname", Some(AnonymizationType::FakeLastName)),
+            ("name", Some(AnonymizationType::FakeName)),
+            ("full_name", Some(AnonymizationType::FakeName)),
+            ("street", Some(AnonymizationType::FakeStreetAddress)),
+            ("address", Some(AnonymizationType::FakeAddress)),
+            ("billing_address", Some(AnonymizationType::FakeAddress)),
+            ("city", Some(AnonymizationType::FakeCity)),
+            ("zip", Some(AnonymizationType::FakeZip)),
+            ("postal_code", Some(AnonymizationType::FakeZip)),
+            ("date_of_birth", Some(AnonymizationType::ShiftDate)),
+            ("dob", Some(AnonymizationType::ShiftDate)),
+            ("password", Some(AnonymizationType::Hash)),
+            ("api_key", Some(AnonymizationType::Hash)),
+            ("reset_token", Some(AnonymizationType::Hash)),
+            ("ip_address", None),
+            ("ethnicity", None),
+            ("id", None),
+            ("company_name", None),
+            ("cityscape", None),
+            ("created_at", None),
+            ("email_verified", None),
+            ("email_count", None),
+            ("is_mobile", None),
+            ("password_updated_at", None),
+            ("mobile_app_version", None),
+        ];
+        for (column, expected) in cases {
+            assert_eq!(detect_type_for_column(column), expected, "{}", column);
+        }
+    }
+
+    /// The line detectors as they were before the regexes were hoisted,
+    /// compiling both patterns on every call
+    fn anonymize_line_recompiling(
+        line: &str,
+        rules: &[(Regex, AnonymizationType)],
+        anonymizer: &mut Anonymizer,
+        preserve_relationships: bool,
+    ) -> String {
+        let mut anonymized_line = line.to_string();
+        let detectors = [
+            (
+                Regex::new(r"\b[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}\b").unwrap(),
+                AnonymizationType::FakeEmail,
+            ),
+            (
+                Regex::new(r"\b\d{3}[-.]?\d{3}[-.]?\d{4}\b").unwrap(),
+                AnonymizationType::FakePhone,
+            ),
+        ];
+        for (regex, detected) in &detectors {
+            for cap in regex.find_iter(line) {
+                let anon_type = rules
+                    .iter()
+                    .find(|(pattern, _)| pattern.is_match(line))
+                    .map(|(_, t)| t)
+                    .unwrap_or(&AnonymizationType::Skip);
+                if anon_type == detected {
+                    let fake =
+                        anonymizer.anonymize(cap.as_str(), anon_type, preserve_relationships);
+                    anonymized_line = anonymized_line.replace(cap.as_str(), &fake);
+                }
+            }
+        }
+        anonymized_line
+    }
+
+    fn rules() -> Vec<(Regex, AnonymizationType)> {
+        vec![
+            (
+                Regex::new(r"(?i)\bemail\b").unwrap(),
+                AnonymizationType::FakeEmail,
+            ),
+            (
+                Regex::new(r"(?i)\bphone\b").unwrap(),
+                AnonymizationType::FakePhone,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_anonymize_line_multiple_matches() {
+        let mut anonymizer = Anonymizer::new();
+        let line = "-- email: john@example.com, jane@example.org, john@example.com";
+
+        let anonymized = anonymize_line(line, &rules(), &mut anonymizer, true);
+        assert!(!anonymized.contains("john@example.com"));
+        assert!(!anonymized.contains("jane@example.org"));
+
+        let john = anonymizer.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+        assert_eq!(anonymized.matches(john.as_str()).count(), 2);
+    }
+
+    #[test]
+    fn test_anonymize_line_no_matches() {
+        let mut anonymizer = Anonymizer::new();
+        let rules = rules();
+
+        let plain = "-- nothing to see here";
+        assert_eq!(anonymize_line(plain, &rules, &mut anonymizer, true), plain);
+
+        // Values are only replaced when a rule names the line
+        let unruled = "-- contact john@example.com";
+        assert_eq!(
+            anonymize_line(unruled, &rules, &mut anonymizer, true),
+            unruled
+        );
+        assert_eq!(anonymize_line(unruled, &[], &mut anonymizer, true), unruled);
+    }
+
+    #[test]
+    fn test_anonymize_line_with_detectors() {
+        let mut anonymizer = Anonymizer::new();
+        let email_line = "-- email: john@example.com";
+
+        let disabled = anonymize_line_with(email_line, &rules(), &[], &mut anonymizer, true);
+        assert_eq!(disabled, email_line);
+
+        let enabled = anonymize_line_with(
+            email_line,
+            &rules(),
+            &[Detector::Email],
+            &mut anonymizer,
+            true,
+        );
+        assert!(!enabled.contains("john@example.com"));
+
+        let card_rules = vec![(
+            Regex::new(r"(?i)\bcard\b").unwrap(),
+            AnonymizationType::MaskCreditCard,
+        )];
+        let card_line = "-- card 4532-1234-5678-9010";
+        assert_eq!(
+            anonymize_line_with(
+                card_line,
+                &card_rules,
+                &[Detector::Email, Detector::Phone],
+                &mut anonymizer,
+                true
+            ),
+            card_line
+        );
+        assert_eq!(
+            anonymize_line_with(
+                card_line,
+                &card_rules,
+                &[Detector::CreditCard],
+                &mut anonymizer,
+                true
+            ),
+            "-- card ****-****-****-9010"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_line_masks_card_in_insert() {
+        let mut anonymizer = Anonymizer::new();
+        let rules = vec![(
+            Regex::new(r"(?i)\bcredit_card\b").unwrap(),
+            AnonymizationType::MaskCreditCard,
+        )];
+
+        let line =
+            "INSERT INTO orders (id, credit_card, total) VALUES (7, '4532 1234 5678 9010', 19.99);";
+        assert_eq!(
+            anonymize_line(line, &rules, &mut anonymizer, true),
+            "INSERT INTO orders (id, credit_card, total) VALUES (7, '****-****-****-9010', 19.99);"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_line_escapes_quoted_replacements() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.import_cache(HashMap::from([(
+            "john@example.com".to_string(),
+            "o'brien@example.com".to_string(),
+        )]));
+
+        let line = "INSERT INTO users (id, email) VALUES (1, 'john@example.com');";
+        let anonymized = anonymize_line(line, &rules(), &mut anonymizer, true);
+        assert_eq!(
+            anonymized,
+            "INSERT INTO users (id, email) VALUES (1, 'o''brien@example.com');"
+        );
+
+        // The output is still one valid statement holding the fake value
+        let insert = sql::parse_insert(&anonymized).unwrap();
+        assert_eq!(insert.rows[0][1].text, "o'brien@example.com");
+
+        // Outside a string there is nothing to escape
+        assert_eq!(
+            anonymize_line(
+                "-- email: john@example.com",
+                &rules(),
+                &mut anonymizer,
+                true
+            ),
+            "-- email: o'brien@example.com"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_line_replaces_each_match_once() {
+        let mut anonymizer = Anonymizer::new();
+        // The fake for one address is the original of another
+        anonymizer.import_cache(HashMap::from([
+            ("a@example.com".to_string(), "b@example.com".to_string()),
+            ("b@example.com".to_string(), "c@example.com".to_string()),
+        ]));
+
+        let line = "INSERT INTO users (email) VALUES ('a@example.com'); -- was a@example.com, b@example.com";
+        assert_eq!(
+            anonymize_line(line, &rules(), &mut anonymizer, true),
+            "INSERT INTO users (email) VALUES ('b@example.com'); -- was b@example.com, c@example.com"
+        );
+
+        // Without relationship preservation every occurrence gets its own fake
+        let mut anonymizer = Anonymizer::with_seed(1);
+        let line = "-- email: john@example.com, again john@example.com";
+        let anonymized = anonymize_line(line, &rules(), &mut anonymizer, false);
+        assert!(!anonymized.contains("john@example.com"));
+        assert!(anonymized.starts_with("-- email: "));
+        assert!(anonymized.contains(", again "));
+    }
+
+    #[test]
+    fn test_anonymize_matches_custom_pattern() {
+        let mut anonymizer = Anonymizer::new();
+        let rules = vec![(
+            Regex::new(r"EMP-\d{5}").unwrap(),
+            AnonymizationType::MaskMiddle,
+        )];
+
+        assert_eq!(
+            anonymize_matches(
+                "Escalated by EMP-12345, cc EMP-67890 (not EMP-12)",
+                &rules,
+                &mut anonymizer,
+                true
+            ),
+            "Escalated by E*******5, cc E*******0 (not EMP-12)"
+        );
+        assert_eq!(
+            anonymize_matches("nothing here", &rules, &mut anonymizer, true),
+            "nothing here"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_matches_overlaps_and_quoting() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.import_cache(HashMap::from([(
+            "EMP-12345".to_string(),
+            "O'Neil".to_string(),
+        )]));
+        let rules = vec![
+            (Regex::new(r"\d{5}").unwrap(), AnonymizationType::MaskMiddle),
+            (
+                Regex::new(r"EMP-\d{5}").unwrap(),
+                AnonymizationType::Scramble,
+            ),
+        ];
+
+        // The match starting first wins over the one nested inside it
+        assert_eq!(
+            anonymize_matches("ticket EMP-12345", &rules, &mut anonymizer, true),
+            "ticket O'Neil"
+        );
+        assert_eq!(
+            anonymize_line_matches(
+                "UPDATE t SET note = 'by EMP-12345' WHERE id = 12345;",
+                &rules,
+                &mut anonymizer,
+                true
+            ),
+            "UPDATE t SET note = 'by O''Neil' WHERE id = 1***5;"
+        );
+    }
+
+    #[test]
+    fn test_detector_from_str() {
+        assert_eq!(Detector::from_str("email"), Some(Detector::Email));
+        assert_eq!(
+            Detector::from_str("Credit_Card"),
+            Some(Detector::CreditCard)
+        );
+        assert_eq!(Detector::from_str("ssn"), None);
+    }
+
+    #[test]
+    fn test_is_known_fake() {
+        let mut anonymizer = Anonymizer::with_seed(1);
+        let email = anonymizer.anonymize("john@acme.com", &AnonymizationType::FakeEmail, true);
+        let card = anonymizer.anonymize(
+            "5500-0000-0000-0004",
+            &AnonymizationType::FakeCreditCard,
+            true,
+        );
+
+        assert!(Detector::Email.is_known_fake(&email));
+        assert!(Detector::Email.is_known_fake("JOHN@EXAMPLE.ORG"));
+        assert!(!Detector::Email.is_known_fake("john@acme.com"));
+        assert!(!Detector::Email.is_known_fake("john@example.com.evil.io"));
+        assert!(Detector::CreditCard.is_known_fake(&card));
+        assert!(!Detector::CreditCard.is_known_fake("5500-0000-0000-0004"));
+        assert!(!Detector::Phone.is_known_fake("555-123-4567"));
+        assert!(Detector::Phone.is_known_fake("842-555-0115"));
+        assert!(!Detector::Phone.is_known_fake("842-555-0215"));
+    }
+
+    #[test]
+    fn test_fake_phones_are_known_fakes() {
+        let mut anonymizer = Anonymizer::with_seed(1);
+        for (locale, keep_format) in [
+            (crate::Locale::En, false),
+            (crate::Locale::En, true),
+            (crate::Locale::JaJp, false),
+            (crate::Locale::ZhCn, true),
+        ] {
+            anonymizer.set_locale(locale);
+            anonymizer.set_keep_phone_format(keep_format);
+            for i in 0..50 {
+                let original = format!("212-{:03}-{:04}", i, i * 37);
+                let phone = anonymizer.anonymize(&original, &AnonymizationType::FakePhone, false);
+                for found in PHONE_REGEX.find_iter(&phone) {
+                    assert!(Detector::Phone.is_known_fake(found.as_str()), "{}", phone);
+                }
+            }
+        }
+        let reserved = reserve_phone("842.257.1515 x2408", &mut StdRng::seed_from_u64(1));
+        assert!(reserved.starts_with("842.555.01"), "{}", reserved);
+        assert!(reserved.ends_with(" x2408"), "{}", reserved);
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("john@example.com"), "j***@*******.***");
+        assert_eq!(redact("555-123-4567"), "5**-***-****");
+        assert_eq!(redact(""), "");
+    }
+
+    #[test]
+    fn test_anonymize_line_matches_recompiling_version() {
+        let rules = rules();
+        let lines = [
+            "-- email: john@example.com, backup jane@example.org",
+            "-- phone 555-123-4567 or 555.987.6543",
+            "-- nothing to see here",
+            "UPDATE users SET email = 'john@example.com' WHERE id = 1;",
+        ];
+
+        let mut hoisted = Anonymizer::with_seed(7);
+        let mut recompiling = Anonymizer::with_seed(7);
+        for line in lines {
+            assert_eq!(
+                anonymize_line(line, &rules, &mut hoisted, true),
+                anonymize_line_recompiling(line, &rules, &mut recompiling, true)
+            );
+        }
+    }
+}
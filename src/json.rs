@@ -0,0 +1,165 @@
+// JSON and JSONL anonymization
+// Fields are addressed by dotted paths such as `user.contact.email`; array
+// elements add `[]` to the path, so `items[].sku` matches every item's sku.
+
+use crate::AnonymizeOutcome;
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+/// Anonymize newline-delimited JSON, one object per line
+///
+/// `anonymize_field` is called with the dotted path and text of every string
+/// and number in each object; returning `Some` replaces it, with `null` if
+/// the outcome is NULL. Blank lines are passed through, and a byte order mark
+/// before the first line is dropped. Returns the number of JSON lines
+/// processed.
+pub fn anonymize<R, W, F>(reader: R, mut writer: W, mut anonymize_field: F) -> io::Result<usize>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&str, &str) -> Option<AnonymizeOutcome>,
+{
+    let mut count = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = if index == 0 {
+            crate::strip_bom(line?)
+        } else {
+            line?
+        };
+        if line.trim().is_empty() {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+
+        let mut value: Value = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", index + 1, e),
+            )
+        })?;
+        walk(
+            &mut value,
+            &mut String::new(),
+            &mut |path: &str, text: &str| {
+                let outcome = anonymize_field(path, text)?;
+                Some((!outcome.is_null).then_some(outcome.value))
+            },
+        );
+        writeln!(writer, "{}", value)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Anonymize the strings and numbers of a JSON value in place
+pub fn anonymize_value<F>(value: &mut Value, anonymize_field: &mut F)
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    walk(value, &mut String::new(), &mut |path: &str, text: &str| {
+        anonymize_field(path, text).map(Some)
+    });
+}
+
+/// Replace the strings and numbers `anonymize_field` returns `Some` for, with
+/// `null` where it returns `Some(None)`
+fn walk<F>(value: &mut Value, path: &mut String, anonymize_field: &mut F)
+where
+    F: FnMut(&str, &str) -> Option<Option<String>>,
+{
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                let len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+                walk(child, path, anonymize_field);
+                path.truncate(len);
+            }
+        }
+        Value::Array(items) => {
+            let len = path.len();
+            path.push_str("[]");
+            for item in items {
+                walk(item, path, anonymize_field);
+            }
+            path.truncate(len);
+        }
+        Value::String(text) => match anonymize_field(path, text) {
+            Some(Some(replacement)) => *text = replacement,
+            Some(None) => *value = Value::Null,
+            None => {}
+        },
+        Value::Number(number) => match anonymize_field(path, &number.to_string()) {
+            // Keep numbers numeric when the replacement still is one
+            Some(Some(replacement)) => {
+                *value = match replacement.parse() {
+                    Ok(number) => Value::Number(number),
+                    Err(_) => Value::String(replacement),
+                };
+            }
+            Some(None) => *value = Value::Null,
+            None => {}
+        },
+        Value::Bool(_) | Value::Null => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replaced(value: String, is_null: bool) -> AnonymizeOutcome {
+        AnonymizeOutcome {
+            value,
+            was_cached: false,
+            changed: true,
+            is_null,
+        }
+    }
+
+    #[test]
+    fn test_anonymize_nested_paths() {
+        let input = r#"{"user":{"name":"John","contact":{"email":"john@example.com"}},"items":[{"sku":"A1","qty":2},{"sku":"B2","qty":1}],"ok":true}"#;
+        let mut output = Vec::new();
+
+        let count = anonymize(input.as_bytes(), &mut output, |path, value| match path {
+            "user.contact.email" => Some(replaced("fake@example.com".to_string(), false)),
+            "items[].sku" => Some(replaced(value.to_lowercase(), false)),
+            "user.name" => Some(replaced("NULL".to_string(), true)),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"user\":{\"name\":null,\"contact\":{\"email\":\"fake@example.com\"}},\"items\":[{\"sku\":\"a1\",\"qty\":2},{\"sku\":\"b2\",\"qty\":1}],\"ok\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_numbers_and_invalid_lines() {
+        let mut value: Value = serde_json::from_str(r#"{"age":42,"zip":12345}"#).unwrap();
+        anonymize_value(&mut value, &mut |path: &str, _: &str| match path {
+            "age" => Some("40".to_string()),
+            "zip" => Some("ABC".to_string()),
+            _ => None,
+        });
+        assert_eq!(value.to_string(), r#"{"age":40,"zip":"ABC"}"#);
+
+        let mut output = Vec::new();
+        anonymize(
+            "\u{feff}{\"age\":42}\r\n{}\r\n".as_bytes(),
+            &mut output,
+            |_, _| None,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"age\":42}\n{}\n");
+
+        let error = anonymize("{}\nnot json\n".as_bytes(), Vec::new(), |_, _| None).unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"));
+    }
+}
@@ -2,203 +2,3992 @@
 // This is the free, open-source "engine" for database anonymization.
 // It provides the fundamental anonymization methods but requires manual configuration.
 
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use cache::FakeCache;
+use fake::faker::company::en::*;
 use fake::faker::internet::en::*;
-use fake::faker::name::en::*;
-use fake::faker::phone_number::en::*;
 use fake::Fake;
+use hmac::{Hmac, Mac};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+
+mod cache;
+pub mod csv;
+mod date;
+mod detect;
+mod distinct;
+pub mod json;
+mod locale;
+mod persona;
+pub mod pg_copy;
+mod processor;
+mod rules;
+mod sink;
+pub mod sql;
+mod stream;
+
+use detect::reserve_phone;
+pub use detect::{
+    anonymize_line, anonymize_line_matches, anonymize_line_with, anonymize_matches,
+    detect_type_for_column, redact, Detector,
+};
+pub use distinct::DistinctCount;
+use locale::localized;
+pub use locale::Locale;
+pub use persona::Persona;
+pub use processor::{Processor, RuleCount};
+pub use rules::{line_regex, table_matches, Rule, RuleSet};
+pub use sink::{FileSink, OutputSink, StdoutSink};
+pub use stream::AnonymizingReader;
 
 /// Configuration for anonymization rules
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    #[serde(default = "default_true")]
+    /// Anonymize columns without a rule whose names look like PII, with the
+    /// type `detect_type_for_column` guesses
+    #[serde(default)]
     pub auto_detect: bool,
 
-    #[serde(default)]
-    pub custom_rules: HashMap<String, String>,
+    /// Column keys mapped to rules; a method may also map to a list of
+    /// columns, which is expanded into one rule per column
+    #[serde(default, deserialize_with = "deserialize_custom_rules")]
+    pub custom_rules: HashMap<String, RuleConfig>,
 
     #[serde(default = "default_true")]
     pub preserve_relationships: bool,
+
+    /// Cap on the relationship cache; past it the least recently used fakes
+    /// are evicted, and those values get new fakes if they come back
+    #[serde(default)]
+    pub max_cache_entries: Option<usize>,
+
+    /// 32-byte key for the `tokenize` method, as 64 hex chars or base64
+    #[serde(default)]
+    pub encryption_key: Option<String>,
+
+    /// Maximum number of days `shift_date` moves a date in either direction
+    #[serde(default = "default_date_shift_max_days")]
+    pub date_shift_max_days: u32,
+
+    /// Maximum percentage `perturb` moves a number in either direction
+    #[serde(default = "default_perturb_percent")]
+    pub perturb_percent: f64,
+
+    /// Locale for fake names, phones and addresses (e.g. "en", "fr", "ja")
+    #[serde(default = "default_locale")]
+    pub locale: String,
+
+    /// Keep the original domain in `fake_email`, replacing only the local part
+    #[serde(default)]
+    pub fake_email_keep_domain: bool,
+
+    /// Treat emails differing only in case as one address in `fake_email`,
+    /// and emit the fakes in lowercase
+    #[serde(default)]
+    pub normalize_email_keys: bool,
+
+    /// Mirror the original's digit grouping and separators in `fake_phone`
+    #[serde(default)]
+    pub fake_phone_keep_format: bool,
+
+    /// IPv4 range in CIDR notation (e.g. "10.0.0.0/8") `fake_ip` draws
+    /// addresses from; unset draws from the whole public-looking unicast space
+    #[serde(default)]
+    pub fake_ip_range: Option<String>,
+
+    /// Values `fake_gender` picks from
+    #[serde(default = "default_gender_values")]
+    pub gender_values: Vec<String>,
+
+    /// Salt prepended to values before `hash`, so hashes can't be correlated across dumps
+    #[serde(default)]
+    pub hash_salt: Option<String>,
+
+    /// Truncate `hash` output to this many hex chars (at least `MIN_HASH_LENGTH`)
+    #[serde(default)]
+    pub hash_length: Option<usize>,
+
+    /// Fit `hash` output to each original's length, so hashes fit the same
+    /// columns; takes precedence over `hash_length`
+    #[serde(default)]
+    pub hash_preserve_length: bool,
+
+    /// Secret key for the `hmac` method; without it nobody can recompute the
+    /// hashes of known values
+    #[serde(default)]
+    pub hmac_key: Option<String>,
+
+    /// Derive fake values from a hash of `hash_salt` and the original, so the
+    /// same input gets the same fake on any machine without a cache file
+    #[serde(default)]
+    pub deterministic: bool,
+
+    /// Character the `mask_*` methods replace hidden characters with
+    #[serde(default = "default_mask_char")]
+    pub mask_char: char,
+
+    /// Token the `redact` method replaces every value with
+    #[serde(default = "default_redact_label")]
+    pub redact_label: String,
+
+    /// Regex patterns mapped to methods; every match inside a value without a
+    /// column rule, or inside a line outside parsed statements, is anonymized
+    #[serde(default)]
+    pub value_rules: HashMap<String, String>,
+
+    /// Built-in line detectors to run (`email`, `phone`, `credit_card`);
+    /// unset runs all of them
+    #[serde(default)]
+    pub enabled_detectors: Option<Vec<String>>,
+
+    /// Tables (`table` or `schema.table`) whose rows are written unchanged,
+    /// e.g. lookup tables that hold no PII
+    #[serde(default)]
+    pub skip_tables: Vec<String>,
+
+    /// Fail the run if a `custom_rules` entry matched no values, which
+    /// usually means the config is stale
+    #[serde(default)]
+    pub require_all_rules_match: bool,
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_date_shift_max_days() -> u32 {
+    30
+}
+
+fn default_perturb_percent() -> f64 {
+    10.0
+}
+
+fn default_locale() -> String {
+    "en".to_string()
+}
+
+fn default_gender_values() -> Vec<String> {
+    ["M", "F", "X"].map(String::from).to_vec()
+}
+
+fn default_mask_char() -> char {
+    '*'
+}
+
+fn default_redact_label() -> String {
+    "[REDACTED]".to_string()
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
-            auto_detect: false, // Free version doesn't auto-detect
+            auto_detect: false, // Only explicit rules unless opted in
             custom_rules: HashMap::new(),
             preserve_relationships: true,
+            max_cache_entries: None,
+            encryption_key: None,
+            date_shift_max_days: default_date_shift_max_days(),
+            perturb_percent: default_perturb_percent(),
+            locale: default_locale(),
+            fake_email_keep_domain: false,
+            normalize_email_keys: false,
+            fake_phone_keep_format: false,
+            fake_ip_range: None,
+            gender_values: default_gender_values(),
+            hash_salt: None,
+            hash_length: None,
+            hash_preserve_length: false,
+            hmac_key: None,
+            deterministic: false,
+            mask_char: default_mask_char(),
+            redact_label: default_redact_label(),
+            value_rules: HashMap::new(),
+            enabled_detectors: None,
+            skip_tables: Vec::new(),
+            require_all_rules_match: false,
+        }
+    }
+}
+
+/// A `custom_rules` entry: a bare method name or a map with per-rule options
+///
+/// ```yaml
+/// custom_rules:
+///   users.email: fake_email
+///   users.phone: { method: fake_phone, preserve: false }
+///   users.backup_email: { method: fake_email, group: contact_email }
+///   users.first_name: { method: fake_first_name, persona: [first_name, last_name] }
+///   users.password_hash: { method: constant, value: "$2b$12$..." }
+///   accounts.code: { method: custom, generator: account_code }
+///   staff.employee_id: { method: pattern, regex: "E\\d{6}" }
+///   events.payload: { method: json, paths: { user.email: fake_email } }
+///   accounts.iban: { method: mask, keep_first: 2, keep_last: 4 }
+///   suppliers.name: { method: fake_name, locale: ja }
+///   users.email: { method: template, format: "{first}.{last}@example.com", from: [first_name, last_name] }
+///   users.country: { method: categorical, values: { US: 0.5, DE: 0.3, FR: 0.2 } }
+/// ```
+// Rules are read once per config, so the unboxed map form costs nothing
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RuleConfig {
+    Method(String),
+    Detailed {
+        method: String,
+        /// Overrides the global `preserve_relationships` for this rule
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preserve: Option<bool>,
+        /// Consistency group whose rules share fake values
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Columns whose values, joined by a space, key a shared `Persona`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        persona: Option<Vec<String>>,
+        /// Overrides the global `locale` for this rule's fakes
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        locale: Option<String>,
+        /// The literal every value is replaced with, for the `constant` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<String>,
+        /// The registered `Generator` to use, for the `custom` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        generator: Option<String>,
+        /// The regex fakes must match, for the `pattern` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        regex: Option<String>,
+        /// Dotted paths in the document mapped to methods, for the `json` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        paths: Option<BTreeMap<String, String>>,
+        /// Leading characters left unmasked, for the `mask` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        keep_first: Option<usize>,
+        /// Trailing characters left unmasked, for the `mask` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        keep_last: Option<usize>,
+        /// Text with `{placeholder}`s, for the `template` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<String>,
+        /// Columns of the same row filling the placeholders in order, for the
+        /// `template` method
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<Vec<String>>,
+        /// Values and their relative weights, for the `categorical` method
+        #[serde(
+            default,
+            deserialize_with = "deserialize_weights",
+            skip_serializing_if = "Option::is_none"
+        )]
+        values: Option<BTreeMap<String, f64>>,
+    },
+}
+
+impl RuleConfig {
+    /// The anonymization method name, e.g. `"fake_email"`
+    pub fn method(&self) -> &str {
+        match self {
+            Self::Method(method) | Self::Detailed { method, .. } => method,
+        }
+    }
+
+    /// The anonymization type this rule applies, if its method is known
+    ///
+    /// `constant` carries its literal in `value`, `custom` its generator
+    /// name in `generator`, `pattern` its regex in `regex` and `json` its
+    /// paths in `paths`, so they need the detailed form and have no meaning
+    /// without those; `template` needs both `format` and `from`, and
+    /// `categorical` its weighted `values`. A `regex` fakes can't be
+    /// generated from, a path with an unknown method, a `format` with more
+    /// placeholders than `from` has columns, or weights that are negative or
+    /// add up to nothing count as unknown.
+    pub fn anon_type(&self) -> Option<AnonymizationType> {
+        match self {
+            Self::Detailed {
+                method,
+                value: Some(value),
+                ..
+            } if method.eq_ignore_ascii_case("constant") => {
+                Some(AnonymizationType::Constant(value.clone()))
+            }
+            Self::Detailed {
+                method,
+                generator: Some(generator),
+                ..
+            } if method.eq_ignore_ascii_case("custom") => {
+                Some(AnonymizationType::Custom(generator.clone()))
+            }
+            Self::Detailed {
+                method,
+                regex: Some(regex),
+                ..
+            } if method.eq_ignore_ascii_case("pattern") => compile_pattern(regex)
+                .ok()
+                .map(|_| AnonymizationType::Pattern(regex.clone())),
+            Self::Detailed {
+                method,
+                keep_first,
+                keep_last,
+                ..
+            } if method.eq_ignore_ascii_case("mask") => Some(AnonymizationType::Mask {
+                keep_first: keep_first.unwrap_or_default(),
+                keep_last: keep_last.unwrap_or_default(),
+            }),
+            Self::Detailed {
+                method,
+                paths: Some(paths),
+                ..
+            } if method.eq_ignore_ascii_case("json") => paths
+                .iter()
+                .map(|(path, method)| Some((path.clone(), AnonymizationType::from_str(method)?)))
+                .collect::<Option<_>>()
+                .map(AnonymizationType::Json),
+            Self::Detailed {
+                method,
+                format: Some(format),
+                from: Some(from),
+                ..
+            } if method.eq_ignore_ascii_case("template") => (template_placeholders(format).len()
+                <= from.len())
+            .then(|| AnonymizationType::Template {
+                format: format.clone(),
+                from: from.clone(),
+            }),
+            Self::Detailed {
+                method,
+                values: Some(values),
+                ..
+            } if method.eq_ignore_ascii_case("categorical") => {
+                categorical_weights(values).map(AnonymizationType::Categorical)
+            }
+            _ => AnonymizationType::from_str(self.method()),
+        }
+    }
+
+    /// The per-rule `preserve_relationships` override, if any
+    pub fn preserve(&self) -> Option<bool> {
+        match self {
+            Self::Method(_) => None,
+            Self::Detailed { preserve, .. } => *preserve,
+        }
+    }
+
+    /// The consistency group this rule belongs to, if any
+    pub fn group(&self) -> Option<&str> {
+        match self {
+            Self::Method(_) => None,
+            Self::Detailed { group, .. } => group.as_deref(),
+        }
+    }
+
+    /// The per-rule `locale` override as written, if any
+    pub fn locale(&self) -> Option<&str> {
+        match self {
+            Self::Method(_) => None,
+            Self::Detailed { locale, .. } => locale.as_deref(),
+        }
+    }
+
+    /// The columns keying this rule's persona, empty when it has none
+    pub fn persona(&self) -> &[String] {
+        match self {
+            Self::Detailed {
+                persona: Some(columns),
+                ..
+            } => columns,
+            _ => &[],
+        }
+    }
+}
+
+/// A `custom_rules` entry as written: a column's rule, or a method's columns
+#[allow(clippy::large_enum_variant)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RuleEntry {
+    Columns(Vec<String>),
+    Rule(RuleConfig),
+}
+
+/// Read `custom_rules`, expanding `method: [column, ...]` entries
+///
+/// ```yaml
+/// custom_rules:
+///   fake_email: [users.email, contacts.email]
+///   users.phone: fake_phone
+/// ```
+fn deserialize_custom_rules<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, RuleConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let entries = HashMap::<String, RuleEntry>::deserialize(deserializer)?;
+    let mut rules = HashMap::with_capacity(entries.len());
+    let mut listed = Vec::new();
+    for (key, entry) in entries {
+        match entry {
+            RuleEntry::Rule(rule) => {
+                rules.insert(key, rule);
+            }
+            RuleEntry::Columns(columns) => {
+                listed.extend(columns.into_iter().map(|column| (column, key.clone())))
+            }
+        }
+    }
+    for (column, method) in listed {
+        if rules
+            .insert(column.clone(), RuleConfig::Method(method))
+            .is_some()
+        {
+            return Err(serde::de::Error::custom(format!(
+                "custom_rules: {} has more than one rule",
+                column
+            )));
+        }
+    }
+    Ok(rules)
+}
+
+impl From<&str> for RuleConfig {
+    fn from(method: &str) -> Self {
+        Self::Method(method.to_string())
+    }
+}
+
+/// The rule applying an anonymization type, with no per-rule options
+///
+/// `Json` paths are written with their methods' names, so only paths using
+/// plain methods survive a round trip.
+impl From<&AnonymizationType> for RuleConfig {
+    fn from(anon_type: &AnonymizationType) -> Self {
+        let (mut value, mut generator, mut regex, mut paths) = (None, None, None, None);
+        let (mut keep_first, mut keep_last) = (None, None);
+        let (mut format, mut from, mut values) = (None, None, None);
+        match anon_type {
+            AnonymizationType::Constant(constant) => value = Some(constant.clone()),
+            AnonymizationType::Custom(name) => generator = Some(name.clone()),
+            AnonymizationType::Pattern(pattern) => regex = Some(pattern.clone()),
+            AnonymizationType::Json(fields) => {
+                paths = Some(
+                    fields
+                        .iter()
+                        .map(|(path, anon_type)| (path.clone(), anon_type.as_str().to_string()))
+                        .collect(),
+                )
+            }
+            AnonymizationType::Mask {
+                keep_first: first,
+                keep_last: last,
+            } if *first > 0 || *last > 0 => {
+                keep_first = Some(*first);
+                keep_last = Some(*last);
+            }
+            AnonymizationType::Template {
+                format: text,
+                from: columns,
+            } => {
+                format = Some(text.clone());
+                from = Some(columns.clone());
+            }
+            AnonymizationType::Categorical(choices) => {
+                values = Some(
+                    choices
+                        .iter()
+                        .map(|(value, weight)| (value.clone(), f64::from(*weight) / WEIGHT_SCALE))
+                        .collect(),
+                )
+            }
+            _ => return Self::Method(anon_type.as_str().to_string()),
+        }
+        Self::Detailed {
+            method: anon_type.as_str().to_string(),
+            preserve: None,
+            group: None,
+            persona: None,
+            locale: None,
+            value,
+            generator,
+            regex,
+            paths,
+            keep_first,
+            keep_last,
+            format,
+            from,
+            values,
+        }
+    }
+}
+
+/// Read `categorical` weights by way of `serde_json::Value`
+///
+/// With serde_json's `arbitrary_precision` the untagged `RuleConfig` buffers
+/// JSON numbers in a form only `Value` reads back as a number.
+fn deserialize_weights<'de, D>(deserializer: D) -> Result<Option<BTreeMap<String, f64>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(values) = Option::<BTreeMap<String, serde_json::Value>>::deserialize(deserializer)?
+    else {
+        return Ok(None);
+    };
+    values
+        .into_iter()
+        .map(|(value, weight)| match weight.as_f64() {
+            Some(weight) => Ok((value, weight)),
+            None => Err(serde::de::Error::custom(format!(
+                "categorical weight of {:?} is not a number",
+                value
+            ))),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// `Categorical` weights are stored as parts of this, so the type stays `Eq`
+const WEIGHT_SCALE: f64 = 1_000_000.0;
+
+/// Scale a `categorical` rule's weights to parts of `WEIGHT_SCALE`
+///
+/// Returns `None` for weights that are negative or not finite, or that add
+/// up to nothing.
+fn categorical_weights(values: &BTreeMap<String, f64>) -> Option<Vec<(String, u32)>> {
+    if values
+        .values()
+        .any(|weight| !weight.is_finite() || *weight < 0.0)
+    {
+        return None;
+    }
+    let total: f64 = values.values().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let choices: Vec<(String, u32)> = values
+        .iter()
+        .map(|(value, weight)| {
+            (
+                value.clone(),
+                (weight / total * WEIGHT_SCALE).round() as u32,
+            )
+        })
+        .collect();
+    choices
+        .iter()
+        .any(|(_, weight)| *weight > 0)
+        .then_some(choices)
+}
+
+/// The choice `n` lands on when `choices` are laid end to end by weight
+fn weighted_choice(choices: &[(String, u32)], n: u64) -> Option<&str> {
+    let total: u64 = choices.iter().map(|(_, weight)| u64::from(*weight)).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut target = n % total;
+    for (value, weight) in choices {
+        let weight = u64::from(*weight);
+        if target < weight {
+            return Some(value);
+        }
+        target -= weight;
+    }
+    None
+}
+
+impl Config {
+    /// Check that every custom rule names a known anonymization method
+    ///
+    /// Returns the keys of the offending rules, sorted, so a typo like
+    /// `fak_email` is reported instead of silently leaving data exposed.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut invalid: Vec<String> = self
+            .custom_rules
+            .iter()
+            .filter(|(_, rule)| rule.anon_type().is_none())
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            invalid.sort();
+            Err(invalid)
+        }
+    }
+}
+
+/// `${VAR}` references interpolated by `interpolate_env`
+static ENV_VAR_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap());
+
+/// Replace `${VAR}` references in config text with environment variables
+///
+/// Runs on the raw YAML before it is parsed, so secrets such as
+/// `encryption_key: ${SCRUB_KEY}` can come from CI instead of the file.
+/// Full-line comments are left alone. Returns the name of the first variable
+/// that isn't set.
+pub fn interpolate_env(yaml: &str) -> Result<String, String> {
+    interpolate_with(yaml, |name| std::env::var(name).ok())
+}
+
+fn interpolate_with<F>(yaml: &str, lookup: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut interpolated = String::with_capacity(yaml.len());
+    for line in yaml.split_inclusive('\n') {
+        if line.trim_start().starts_with('#') {
+            interpolated.push_str(line);
+            continue;
+        }
+        let mut last = 0;
+        for cap in ENV_VAR_REGEX.captures_iter(line) {
+            let (whole, name) = (cap.get(0).unwrap(), &cap[1]);
+            let value = lookup(name).ok_or_else(|| name.to_string())?;
+            interpolated.push_str(&line[last..whole.start()]);
+            interpolated.push_str(&value);
+            last = whole.end();
         }
+        interpolated.push_str(&line[last..]);
+    }
+    Ok(interpolated)
+}
+
+/// Shortest `hash` output allowed; shorter digests collide too easily
+///
+/// Truncating to `n` hex chars keeps `4 * n` bits, so collisions become
+/// likely around `2^(2n)` distinct values (about 65k values at 8 chars).
+pub const MIN_HASH_LENGTH: usize = 8;
+
+/// Parse an IPv4 range such as `10.0.0.0/8` into its network and prefix length
+///
+/// Host bits set in the address are cleared, so `10.1.2.3/8` is `10.0.0.0/8`.
+/// A bare address is a single-address range.
+pub fn parse_ipv4_range(range: &str) -> Option<(Ipv4Addr, u8)> {
+    let range = range.trim();
+    let (address, prefix_len) = match range.split_once('/') {
+        Some((address, prefix_len)) => (address, prefix_len.parse::<u8>().ok()?),
+        None => (range, 32),
+    };
+    if prefix_len > 32 {
+        return None;
     }
+    let address: Ipv4Addr = address.parse().ok()?;
+    let mask = u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0);
+    Some((Ipv4Addr::from(u32::from(address) & mask), prefix_len))
+}
+
+/// Decode a 32-byte encryption key given as 64 hex chars or base64
+pub fn decode_key(encoded: &str) -> Option<[u8; 32]> {
+    let encoded = encoded.trim();
+    let bytes = if encoded.len() == 64 && encoded.chars().all(|c| c.is_ascii_hexdigit()) {
+        (0..64)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?
+    } else {
+        BASE64.decode(encoded).ok()?
+    };
+    bytes.try_into().ok()
 }
 
 /// Types of anonymization methods available
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AnonymizationType {
     FakeEmail,
     FakeName,
+    FakeFirstName,
+    FakeLastName,
     FakePhone,
     FakeAddress,
+    FakeStreetAddress,
+    FakeCity,
+    FakeState,
+    FakeZip,
+    FakeCompany,
+    FakeCreditCard,
+    FakeUuid,
+    /// A random IPv4, within the range set with `set_fake_ip_range` if any
+    FakeIp,
+    /// One of the values set with `set_gender_values`, `M`, `F` or `X` by default
+    FakeGender,
     MaskCreditCard,
+    MaskEmail,
     MaskSSN,
+    MaskIban,
+    MaskMiddle,
+    /// Zero the host part of an IP address: the last octet of an IPv4 and
+    /// the lower 80 bits of an IPv6
+    MaskIp,
+    /// Mask all but the first `keep_first` and last `keep_last` characters,
+    /// set with those keys in the rule
+    Mask {
+        keep_first: usize,
+        keep_last: usize,
+    },
+    ShiftDate,
+    Perturb,
+    Scramble,
     Hash,
+    /// HMAC-SHA256 keyed with `set_hmac_key`
+    Hmac,
+    Tokenize,
+    Nullify,
+    Redact,
+    /// Replace every value with the same literal, set with `value` in the rule
+    Constant(String),
+    /// Generate with the `Generator` registered under this name
+    Custom(String),
+    /// Generate a random string matching this regex, set with `regex` in the rule
+    Pattern(String),
+    /// Parse the value as JSON and anonymize these dotted paths in it, set
+    /// with `paths` in the rule
+    Json(Vec<(String, AnonymizationType)>),
+    /// Fill `format`'s placeholders with the anonymized values of the `from`
+    /// columns in the same row, set with `format` and `from` in the rule
+    Template {
+        format: String,
+        from: Vec<String>,
+    },
+    /// Pick one of these values, more often the heavier its weight, the same
+    /// one for every occurrence of an original; set with `values` in the
+    /// rule, weights in millionths
+    Categorical(Vec<(String, u32)>),
     Skip,
 }
 
 impl AnonymizationType {
     /// Parse anonymization type from string (from config file)
+    ///
+    /// Use `AnonymizationType::try_from` to learn why a string didn't parse.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
-        match s.to_lowercase().as_str() {
-            "fake_email" | "email" => Some(Self::FakeEmail),
-            "fake_name" | "name" => Some(Self::FakeName),
-            "fake_phone" | "phone" => Some(Self::FakePhone),
-            "fake_address" | "address" => Some(Self::FakeAddress),
-            "mask_credit_card" | "credit_card" => Some(Self::MaskCreditCard),
-            "mask_ssn" | "ssn" => Some(Self::MaskSSN),
-            "hash" => Some(Self::Hash),
-            "skip" => Some(Self::Skip),
-            _ => None,
+        Self::try_from(s).ok()
+    }
+
+    /// Every anonymization type, in declaration order
+    ///
+    /// `Constant`, `Custom`, `Pattern`, `Json`, `Template` and `Categorical`
+    /// are left out since they need a value from the config.
+    pub const ALL: &'static [AnonymizationType] = &[
+        Self::FakeEmail,
+        Self::FakeName,
+        Self::FakeFirstName,
+        Self::FakeLastName,
+        Self::FakePhone,
+        Self::FakeAddress,
+        Self::FakeStreetAddress,
+        Self::FakeCity,
+        Self::FakeState,
+        Self::FakeZip,
+        Self::FakeCompany,
+        Self::FakeCreditCard,
+        Self::FakeUuid,
+        Self::FakeIp,
+        Self::FakeGender,
+        Self::MaskCreditCard,
+        Self::MaskEmail,
+        Self::MaskSSN,
+        Self::MaskIban,
+        Self::MaskMiddle,
+        Self::MaskIp,
+        Self::Mask {
+            keep_first: 0,
+            keep_last: 0,
+        },
+        Self::ShiftDate,
+        Self::Perturb,
+        Self::Scramble,
+        Self::Hash,
+        Self::Hmac,
+        Self::Tokenize,
+        Self::Nullify,
+        Self::Redact,
+        Self::Skip,
+    ];
+
+    /// The canonical config name, e.g. `"fake_email"`; `from_str` parses it back
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FakeEmail => "fake_email",
+            Self::FakeName => "fake_name",
+            Self::FakeFirstName => "fake_first_name",
+            Self::FakeLastName => "fake_last_name",
+            Self::FakePhone => "fake_phone",
+            Self::FakeAddress => "fake_address",
+            Self::FakeStreetAddress => "fake_street_address",
+            Self::FakeCity => "fake_city",
+            Self::FakeState => "fake_state",
+            Self::FakeZip => "fake_zip",
+            Self::FakeCompany => "fake_company",
+            Self::FakeCreditCard => "fake_credit_card",
+            Self::FakeUuid => "fake_uuid",
+            Self::FakeIp => "fake_ip",
+            Self::FakeGender => "fake_gender",
+            Self::MaskCreditCard => "mask_credit_card",
+            Self::MaskEmail => "mask_email",
+            Self::MaskSSN => "mask_ssn",
+            Self::MaskIban => "mask_iban",
+            Self::MaskMiddle => "mask_middle",
+            Self::MaskIp => "mask_ip",
+            Self::Mask { .. } => "mask",
+            Self::ShiftDate => "shift_date",
+            Self::Perturb => "perturb",
+            Self::Scramble => "scramble",
+            Self::Hash => "hash",
+            Self::Hmac => "hmac",
+            Self::Tokenize => "tokenize",
+            Self::Nullify => "nullify",
+            Self::Redact => "redact",
+            Self::Constant(_) => "constant",
+            Self::Custom(_) => "custom",
+            Self::Pattern(_) => "pattern",
+            Self::Json(_) => "json",
+            Self::Template { .. } => "template",
+            Self::Categorical(_) => "categorical",
+            Self::Skip => "skip",
         }
     }
 }
 
-/// The core anonymization engine
-pub struct Anonymizer {
-    hash_cache: HashMap<String, String>,
+impl fmt::Display for AnonymizationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
-impl Anonymizer {
-    pub fn new() -> Self {
-        Self {
-            hash_cache: HashMap::new(),
+/// Accepts the canonical names in `ALL` and their aliases, case-insensitively
+impl TryFrom<&str> for AnonymizationType {
+    type Error = ParseAnonTypeError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "fake_email" | "email" => Ok(Self::FakeEmail),
+            "fake_name" | "name" => Ok(Self::FakeName),
+            "fake_first_name" | "first_name" => Ok(Self::FakeFirstName),
+            "fake_last_name" | "last_name" => Ok(Self::FakeLastName),
+            "fake_phone" | "phone" => Ok(Self::FakePhone),
+            "fake_address" | "address" => Ok(Self::FakeAddress),
+            "fake_street_address" | "street_address" | "street" => Ok(Self::FakeStreetAddress),
+            "fake_city" | "city" => Ok(Self::FakeCity),
+            "fake_state" | "state" => Ok(Self::FakeState),
+            "fake_zip" | "zip" | "zip_code" | "postal_code" => Ok(Self::FakeZip),
+            "fake_company" | "company" => Ok(Self::FakeCompany),
+            "fake_credit_card" => Ok(Self::FakeCreditCard),
+            "fake_uuid" | "uuid" => Ok(Self::FakeUuid),
+            "fake_ip" => Ok(Self::FakeIp),
+            "fake_gender" | "gender" => Ok(Self::FakeGender),
+            "mask_credit_card" | "credit_card" => Ok(Self::MaskCreditCard),
+            "mask_email" => Ok(Self::MaskEmail),
+            "mask_ssn" | "ssn" => Ok(Self::MaskSSN),
+            "mask_iban" | "iban" => Ok(Self::MaskIban),
+            "mask_middle" => Ok(Self::MaskMiddle),
+            "mask_ip" | "ip" => Ok(Self::MaskIp),
+            "mask" => Ok(Self::Mask {
+                keep_first: 0,
+                keep_last: 0,
+            }),
+            "shift_date" | "date" => Ok(Self::ShiftDate),
+            "perturb" | "noise" => Ok(Self::Perturb),
+            "scramble" => Ok(Self::Scramble),
+            "hash" => Ok(Self::Hash),
+            "hmac" => Ok(Self::Hmac),
+            "tokenize" | "token" => Ok(Self::Tokenize),
+            "nullify" | "null" => Ok(Self::Nullify),
+            "redact" | "redacted" => Ok(Self::Redact),
+            "skip" => Ok(Self::Skip),
+            _ => Err(ParseAnonTypeError {
+                input: s.to_string(),
+            }),
         }
     }
+}
 
-    /// Anonymize a value based on the anonymization type
-    pub fn anonymize(
-        &mut self,
-        value: &str,
-        anon_type: &AnonymizationType,
-        preserve_relationships: bool,
-    ) -> String {
-        match anon_type {
-            AnonymizationType::FakeEmail => {
-                if preserve_relationships {
-                    self.get_or_generate(value, || SafeEmail().fake())
-                } else {
-                    SafeEmail().fake()
-                }
-            }
+/// A string that isn't the name of an anonymization method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAnonTypeError {
+    /// The string that failed to parse
+    pub input: String,
+}
 
-            AnonymizationType::FakeName => {
-                if preserve_relationships {
-                    self.get_or_generate(value, || Name().fake())
-                } else {
-                    Name().fake()
-                }
-            }
+impl ParseAnonTypeError {
+    /// The canonical names that would have parsed
+    ///
+    /// Methods that need options, such as `constant`, are not included since
+    /// they can't be given as a bare name.
+    pub fn expected(&self) -> impl Iterator<Item = &'static str> {
+        AnonymizationType::ALL.iter().map(AnonymizationType::as_str)
+    }
+}
 
-            AnonymizationType::FakePhone => {
-                if preserve_relationships {
-                    self.get_or_generate(value, || PhoneNumber().fake())
-                } else {
-                    PhoneNumber().fake()
-                }
+impl fmt::Display for ParseAnonTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown anonymization method {:?}, expected one of: ",
+            self.input
+        )?;
+        for (i, name) in self.expected().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
             }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
 
-            AnonymizationType::FakeAddress => {
-                if preserve_relationships {
-                    self.get_or_generate(value, || format!("{} Main St", (100..9999).fake::<i32>()))
-                } else {
-                    format!("{} Main St", (100..9999).fake::<i32>())
-                }
-            }
+impl std::error::Error for ParseAnonTypeError {}
 
-            AnonymizationType::MaskCreditCard => {
-                let len = value.len();
-                if len > 4 {
-                    format!("****-****-****-{}", &value[len - 4..])
-                } else {
-                    "****".to_string()
-                }
-            }
+/// Serialized like a `custom_rules` entry: the method name, or for types
+/// carrying a value, a map with `method` and that value's key
+impl Serialize for AnonymizationType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        RuleConfig::from(self).serialize(serializer)
+    }
+}
 
-            AnonymizationType::MaskSSN => "***-**-****".to_string(),
+impl<'de> Deserialize<'de> for AnonymizationType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let rule = RuleConfig::deserialize(deserializer)?;
+        rule.anon_type().ok_or_else(|| {
+            serde::de::Error::custom(format!("unknown anonymization method {:?}", rule.method()))
+        })
+    }
+}
 
-            AnonymizationType::Hash => {
-                let mut hasher = Sha256::new();
-                hasher.update(value.as_bytes());
-                format!("{:x}", hasher.finalize())
-            }
+/// A fake value generator for formats the built-in types can't express
+///
+/// Register one with `Anonymizer::register` and reference it from a rule as
+/// `{ method: custom, generator: <name> }`. Output goes through the
+/// relationship cache like any other fake, so a generator only has to map
+/// one original to one fake. Generators are shared between forks, which may
+/// run on other threads.
+pub trait Generator: Send + Sync {
+    fn generate(&self, original: &str) -> String;
+}
 
-            AnonymizationType::Skip => value.to_string(),
+/// The core anonymization engine
+pub struct Anonymizer {
+    /// Relationship cache, shared with anonymizers created by `fork`
+    hash_cache: Arc<Mutex<FakeCache>>,
+    /// Personas shared by all forks, keyed by `persona_for` keys
+    personas: Arc<Mutex<HashMap<String, Persona>>>,
+    /// This fork's copies of the personas it has used, so they can be borrowed
+    local_personas: HashMap<String, Persona>,
+    rng: StdRng,
+    seed: Option<u64>,
+    key: Option<[u8; 32]>,
+    shift_secret: u64,
+    date_shift_max_days: u32,
+    perturb_percent: f64,
+    locale: Locale,
+    keep_email_domain: bool,
+    normalize_email_keys: bool,
+    keep_phone_format: bool,
+    /// Network and prefix length `FakeIp` draws from
+    fake_ip_range: Option<(Ipv4Addr, u8)>,
+    /// Values `FakeGender` picks from
+    gender_values: Vec<String>,
+    hash_salt: Option<String>,
+    hash_length: Option<usize>,
+    hash_preserve_length: bool,
+    hmac_key: Option<Vec<u8>>,
+    /// Seed each fake from `hash_salt` and the original, see `set_deterministic`
+    deterministic: bool,
+    mask_char: char,
+    redact_label: String,
+    /// Generators for `Custom` types, by registered name
+    generators: HashMap<String, Arc<dyn Generator>>,
+    /// Compiled `Pattern` regexes, by pattern
+    patterns: HashMap<String, Arc<rand_regex::Regex>>,
+    /// Consistency group of the value being anonymized, see `anonymize_grouped`
+    group: Option<String>,
+    /// Row values filling a `Template`, see `anonymize_template`
+    template_values: Option<Vec<String>>,
+    /// Set by `get_or_generate` when the last value came from the cache
+    cache_hit: bool,
+    /// Invocations and distinct originals per type, see `stats`
+    stats: HashMap<AnonymizationType, TypeCount>,
+}
+
+/// How often an anonymization type was used, as reported by `Anonymizer::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeStats {
+    /// Values anonymized with the type, including repeats
+    pub invocations: usize,
+    /// Distinct original values the type saw, estimated once there are many
+    pub distinct: usize,
+}
+
+/// One replacement from the relationship cache, as written by `--mapping-out`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct MappingEntry {
+    /// SHA-256 of the original value, so the mapping holds no raw PII
+    pub original_hash: String,
+    /// The fake value the original was replaced with
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TypeCount {
+    invocations: usize,
+    values: DistinctCount,
+}
+
+/// What happened when a value was anonymized
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnonymizeOutcome {
+    /// The anonymized value
+    pub value: String,
+    /// The value was served from the relationship cache
+    pub was_cached: bool,
+    /// The value differs from the original
+    pub changed: bool,
+    /// The value is SQL `NULL` and must be written without quotes
+    pub is_null: bool,
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        let mut rng = StdRng::from_entropy();
+        let shift_secret = rng.gen();
+        Self::with_rng(rng, shift_secret)
+    }
+
+    fn with_rng(rng: StdRng, shift_secret: u64) -> Self {
+        Self {
+            hash_cache: Arc::new(Mutex::new(FakeCache::new())),
+            personas: Arc::new(Mutex::new(HashMap::new())),
+            local_personas: HashMap::new(),
+            shift_secret,
+            rng,
+            seed: None,
+            key: None,
+            date_shift_max_days: default_date_shift_max_days(),
+            perturb_percent: default_perturb_percent(),
+            locale: Locale::En,
+            keep_email_domain: false,
+            normalize_email_keys: false,
+            keep_phone_format: false,
+            fake_ip_range: None,
+            gender_values: default_gender_values(),
+            hash_salt: None,
+            hash_length: None,
+            hash_preserve_length: false,
+            hmac_key: None,
+            deterministic: false,
+            mask_char: default_mask_char(),
+            redact_label: default_redact_label(),
+            generators: HashMap::new(),
+            patterns: HashMap::new(),
+            group: None,
+            template_values: None,
+            cache_hit: false,
+            stats: HashMap::new(),
         }
     }
 
-    /// Get cached value or generate new one (for relationship preservation)
-    fn get_or_generate<F>(&mut self, original: &str, generator: F) -> String
-    where
-        F: FnOnce() -> String,
-    {
-        self.hash_cache
-            .entry(original.to_string())
-            .or_insert_with(generator)
-            .clone()
+    /// Create an anonymizer whose fake data is reproducible.
+    ///
+    /// Fake values are generated from an RNG derived from the seed and the
+    /// original value, so the same seed plus the same input always yields the
+    /// same anonymized value, even across process restarts.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..Self::with_rng(StdRng::seed_from_u64(seed), seed)
+        }
     }
-}
 
-impl Default for Anonymizer {
-    fn default() -> Self {
-        Self::new()
+    /// Create an anonymizer that generates names, phones and addresses for a locale
+    pub fn with_locale(locale: Locale) -> Self {
+        Self {
+            locale,
+            ..Self::new()
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Set the locale used for fake names, phones and addresses
+    pub fn set_locale(&mut self, locale: Locale) {
+        self.locale = locale;
+    }
 
-    #[test]
-    fn test_anonymizer_relationship_preservation() {
-        let mut anonymizer = Anonymizer::new();
+    /// Create an anonymizer that can tokenize and detokenize with the given key
+    pub fn with_key(key: [u8; 32]) -> Self {
+        let mut anonymizer = Self::new();
+        anonymizer.set_key(key);
+        anonymizer
+    }
 
-        let email1 = anonymizer.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
-        let email2 = anonymizer.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+    /// Set the AES-256 key used by `Tokenize`
+    pub fn set_key(&mut self, key: [u8; 32]) {
+        self.key = Some(key);
+    }
 
-        assert_eq!(email1, email2);
+    /// Set the maximum number of days `ShiftDate` moves a date
+    pub fn set_date_shift_max_days(&mut self, days: u32) {
+        self.date_shift_max_days = days;
     }
 
-    #[test]
-    fn test_anonymizer_mask_credit_card() {
-        let mut anonymizer = Anonymizer::new();
-        let masked = anonymizer.anonymize(
-            "4532-1234-5678-9010",
-            &AnonymizationType::MaskCreditCard,
-            false,
-        );
-        assert_eq!(masked, "****-****-****-9010");
+    /// Set the maximum percentage `Perturb` moves a number, clamped to `0..=100`
+    pub fn set_perturb_percent(&mut self, percent: f64) {
+        self.perturb_percent = percent.clamp(0.0, 100.0);
     }
 
-    #[test]
-    fn test_anonymizer_mask_ssn() {
-        let mut anonymizer = Anonymizer::new();
-        let masked = anonymizer.anonymize("123-45-6789", &AnonymizationType::MaskSSN, false);
-        assert_eq!(masked, "***-**-****");
+    /// Keep the original domain when generating fake emails
+    pub fn set_keep_email_domain(&mut self, keep: bool) {
+        self.keep_email_domain = keep;
     }
 
-    #[test]
-    fn test_config_default() {
-        let config = Config::default();
-        assert!(!config.auto_detect); // Free version
-        assert!(config.preserve_relationships);
-        assert_eq!(config.custom_rules.len(), 0);
+    /// Key fake emails by the lowercased original and lowercase the fakes, so
+    /// `John@x.com` and `john@x.com` map to the same fake
+    pub fn set_normalize_email_keys(&mut self, normalize: bool) {
+        self.normalize_email_keys = normalize;
     }
 
-    #[test]
+    /// Make fake phone numbers follow the digit grouping and separators of the original
+    pub fn set_keep_phone_format(&mut self, keep: bool) {
+        self.keep_phone_format = keep;
+    }
+
+    /// Draw `FakeIp` addresses from `network/prefix_len`, e.g. from
+    /// `parse_ipv4_range`
+    ///
+    /// Ranges with room for them skip the network and broadcast addresses.
+    pub fn set_fake_ip_range(&mut self, network: Ipv4Addr, prefix_len: u8) {
+        let prefix_len = prefix_len.min(32);
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(prefix_len))
+            .unwrap_or(0);
+        self.fake_ip_range = Some((Ipv4Addr::from(u32::from(network) & mask), prefix_len));
+    }
+
+    /// Set the values `FakeGender` picks from; an empty list is ignored
+    pub fn set_gender_values(&mut self, values: Vec<String>) {
+        if !values.is_empty() {
+            self.gender_values = values;
+        }
+    }
+
+    /// Set the salt prepended to values before `Hash`
+    pub fn set_hash_salt(&mut self, salt: impl Into<String>) {
+        self.hash_salt = Some(salt.into());
+    }
+
+    /// Truncate `Hash` output to `length` hex chars, clamped to `MIN_HASH_LENGTH..=64`
+    pub fn set_hash_length(&mut self, length: usize) {
+        self.hash_length = Some(length.clamp(MIN_HASH_LENGTH, 64));
+    }
+
+    /// Make `Hash` and `Hmac` output as many hex chars as the original has
+    /// characters, overriding `set_hash_length`
+    ///
+    /// Digests are extended deterministically for originals longer than 64
+    /// characters. Short originals get short hashes, which collide sooner.
+    pub fn set_hash_preserve_length(&mut self, preserve: bool) {
+        self.hash_preserve_length = preserve;
+    }
+
+    /// Set the secret key used by `Hmac`
+    pub fn set_hmac_key(&mut self, key: impl AsRef<[u8]>) {
+        self.hmac_key = Some(key.as_ref().to_vec());
+    }
+
+    /// Derive every fake from a SHA-256 of the hash salt and the original
+    ///
+    /// Like `with_seed`, but keyed by the salt set with `set_hash_salt`, so
+    /// anyone with the same salt gets the same fakes without sharing a cache
+    /// file. Takes precedence over a seed.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Set the character the `Mask*` types hide characters with
+    pub fn set_mask_char(&mut self, mask_char: char) {
+        self.mask_char = mask_char;
+    }
+
+    /// Set the token `Redact` replaces values with
+    pub fn set_redact_label(&mut self, label: impl Into<String>) {
+        self.redact_label = label.into();
+    }
+
+    /// Register a generator for `AnonymizationType::Custom(name)`
+    ///
+    /// Registering a name again replaces its generator.
+    pub fn register(&mut self, name: &str, generator: Box<dyn Generator>) {
+        self.generators
+            .insert(name.to_string(), Arc::from(generator));
+    }
+
+    /// Create an anonymizer for another thread that shares this one's cache
+    ///
+    /// Settings are copied and the relationship cache is shared, so identical
+    /// values map to identical fakes across all forks. Stats start empty; fold
+    /// them back with `merge_stats`.
+    pub fn fork(&self) -> Self {
+        Self {
+            hash_cache: Arc::clone(&self.hash_cache),
+            personas: Arc::clone(&self.personas),
+            local_personas: HashMap::new(),
+            rng: StdRng::from_entropy(),
+            seed: self.seed,
+            key: self.key,
+            shift_secret: self.shift_secret,
+            date_shift_max_days: self.date_shift_max_days,
+            perturb_percent: self.perturb_percent,
+            locale: self.locale,
+            keep_email_domain: self.keep_email_domain,
+            normalize_email_keys: self.normalize_email_keys,
+            keep_phone_format: self.keep_phone_format,
+            fake_ip_range: self.fake_ip_range,
+            gender_values: self.gender_values.clone(),
+            hash_salt: self.hash_salt.clone(),
+            hash_length: self.hash_length,
+            hash_preserve_length: self.hash_preserve_length,
+            hmac_key: self.hmac_key.clone(),
+            deterministic: self.deterministic,
+            mask_char: self.mask_char,
+            redact_label: self.redact_label.clone(),
+            generators: self.generators.clone(),
+            patterns: self.patterns.clone(),
+            group: None,
+            template_values: None,
+            cache_hit: false,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Export the relationship cache (original value -> fake value)
+    ///
+    /// Serialize this between runs and feed it back through `import_cache` so
+    /// the same original maps to the same fake across separate files.
+    pub fn export_cache(&self) -> HashMap<String, String> {
+        self.cache()
+            .iter()
+            .map(|(original, fake)| (original.clone(), fake.clone()))
+            .collect()
+    }
+
+    /// Import a previously exported relationship cache
+    ///
+    /// Imported entries take precedence over values already in the cache.
+    pub fn import_cache(&mut self, map: HashMap<String, String>) {
+        self.cache().extend(map);
+    }
+
+    /// Forget every cached fake and persona, so values seen from now on get
+    /// new fakes
+    ///
+    /// Call this between unrelated datasets anonymized with one instance, so
+    /// the same original doesn't map to the same fake across them and the
+    /// cache doesn't keep growing. The key, salt, locale and other settings
+    /// are kept, as are `stats`. Forks share the cache, so it is cleared for
+    /// them too. Seeded and deterministic anonymizers derive fakes from the
+    /// original, so they still give it the same fake afterwards.
+    pub fn clear_cache(&mut self) {
+        self.cache().clear();
+        self.shared_personas().clear();
+        self.local_personas.clear();
+    }
+
+    /// Export the relationship cache as an audit mapping
+    ///
+    /// Originals are hashed with SHA-256 and entries left unchanged are
+    /// skipped. Entries are sorted so the same cache always gives the same
+    /// mapping.
+    pub fn export_mapping(&self) -> Vec<MappingEntry> {
+        let mut mapping: Vec<MappingEntry> = self
+            .export_cache()
+            .into_iter()
+            .filter_map(|(key, replacement)| {
+                // Grouped entries are keyed as `group\0original`
+                let original = key.split_once('\0').map_or(key.as_str(), |(_, o)| o);
+                (original != replacement).then(|| MappingEntry {
+                    original_hash: format!("{:x}", Sha256::digest(original.as_bytes())),
+                    replacement,
+                })
+            })
+            .collect();
+        mapping.sort();
+        mapping
+    }
+
+    /// How many values each anonymization type was invoked for, and how many
+    /// of them were distinct
+    ///
+    /// Every call is counted, including `Skip`.
+    pub fn stats(&self) -> HashMap<AnonymizationType, TypeStats> {
+        self.stats
+            .iter()
+            .map(|(anon_type, count)| {
+                let stats = TypeStats {
+                    invocations: count.invocations,
+                    distinct: count.values.count(),
+                };
+                (anon_type.clone(), stats)
+            })
+            .collect()
+    }
+
+    /// Add another anonymizer's stats, typically a fork's, to this one's
+    pub fn merge_stats(&mut self, other: &Anonymizer) {
+        for (anon_type, count) in &other.stats {
+            let total = self.stats.entry(anon_type.clone()).or_default();
+            total.invocations += count.invocations;
+            total.values.merge(&count.values);
+        }
+    }
+
+    fn record(&mut self, value: &str, anon_type: &AnonymizationType) {
+        let count = self.stats.entry(anon_type.clone()).or_default();
+        count.invocations += 1;
+        count.values.insert(value);
+    }
+
+    /// Cap the relationship cache at `max` entries, shared by every fork
+    ///
+    /// Past the cap the least recently used entries are evicted, so a value
+    /// seen again after its entry was evicted gets a new fake.
+    pub fn set_max_cache_entries(&mut self, max: usize) {
+        self.cache().set_capacity(max);
+    }
+
+    /// Number of entries in the relationship cache
+    pub fn cache_len(&self) -> usize {
+        self.cache().len()
+    }
+
+    /// How many cache entries have been evicted by `set_max_cache_entries`
+    pub fn cache_evictions(&self) -> usize {
+        self.cache().evictions()
+    }
+
+    /// Whether a fake value for this original is already in the cache
+    pub fn is_cached(&self, original: &str) -> bool {
+        self.cache().contains_key(original)
+    }
+
+    /// Anonymize a value based on the anonymization type
+    ///
+    /// Any string is accepted: malformed input comes back masked, hashed or
+    /// unchanged as the type allows, but never panics.
+    pub fn anonymize(
+        &mut self,
+        value: &str,
+        anon_type: &AnonymizationType,
+        preserve_relationships: bool,
+    ) -> String {
+        self.anonymize_detailed(value, anon_type, preserve_relationships)
+            .value
+    }
+
+    /// Anonymize many values at once, in order
+    ///
+    /// Equivalent to calling `anonymize` for each value, but the relationship
+    /// cache is grown once up front instead of rehashing as it fills.
+    pub fn anonymize_batch(
+        &mut self,
+        values: &[(&str, AnonymizationType)],
+        preserve_relationships: bool,
+    ) -> Vec<String> {
+        if preserve_relationships {
+            self.cache().reserve(values.len());
+        }
+        values
+            .iter()
+            .map(|(value, anon_type)| self.anonymize(value, anon_type, preserve_relationships))
+            .collect()
+    }
+
+    /// Anonymize a value and report whether it was cached or changed
+    pub fn anonymize_detailed(
+        &mut self,
+        value: &str,
+        anon_type: &AnonymizationType,
+        preserve_relationships: bool,
+    ) -> AnonymizeOutcome {
+        self.anonymize_grouped(value, anon_type, preserve_relationships, None)
+    }
+
+    /// Anonymize a value within a consistency group
+    ///
+    /// Rules in the same group share one cache namespace: the first fake
+    /// generated for a value in the group is reused by every rule in it, even
+    /// across methods. Values outside any group share the default namespace.
+    ///
+    /// Empty values are returned unchanged whatever the type, so a blank
+    /// column never gains a fake value.
+    pub fn anonymize_grouped(
+        &mut self,
+        value: &str,
+        anon_type: &AnonymizationType,
+        preserve_relationships: bool,
+        group: Option<&str>,
+    ) -> AnonymizeOutcome {
+        if value.is_empty() {
+            return AnonymizeOutcome {
+                value: String::new(),
+                was_cached: false,
+                changed: false,
+                is_null: false,
+            };
+        }
+        self.record(value, anon_type);
+        self.cache_hit = false;
+        self.group = group.map(str::to_string);
+        let anonymized = self.transform(value, anon_type, preserve_relationships);
+        AnonymizeOutcome {
+            changed: anonymized != value,
+            was_cached: self.cache_hit,
+            is_null: *anon_type == AnonymizationType::Nullify,
+            value: anonymized,
+        }
+    }
+
+    /// Like `anonymize_grouped`, but generating names, phones and addresses
+    /// for `locale` instead of the anonymizer's own when it is given
+    ///
+    /// The relationship cache is shared across locales, so a value already
+    /// faked under another locale keeps that fake; put the rule in a group to
+    /// give it fakes of its own.
+    pub fn anonymize_localized(
+        &mut self,
+        value: &str,
+        anon_type: &AnonymizationType,
+        preserve_relationships: bool,
+        group: Option<&str>,
+        locale: Option<Locale>,
+    ) -> AnonymizeOutcome {
+        let global = self.locale;
+        self.locale = locale.unwrap_or(global);
+        let outcome = self.anonymize_grouped(value, anon_type, preserve_relationships, group);
+        self.locale = global;
+        outcome
+    }
+
+    /// Anonymize a value with a `Template`, filling its placeholders from
+    /// `from_values`, the already anonymized values of its `from` columns
+    ///
+    /// Placeholders take the values in order of first appearance; missing
+    /// ones are left empty. Any other type is anonymized as by
+    /// `anonymize_grouped`, as is a template without values, which falls back
+    /// to `Hash`.
+    pub fn anonymize_template(
+        &mut self,
+        value: &str,
+        anon_type: &AnonymizationType,
+        preserve_relationships: bool,
+        group: Option<&str>,
+        from_values: &[&str],
+    ) -> AnonymizeOutcome {
+        self.template_values = Some(from_values.iter().map(|v| v.to_string()).collect());
+        let outcome = self.anonymize_grouped(value, anon_type, preserve_relationships, group);
+        self.template_values = None;
+        outcome
+    }
+
+    fn transform(
+        &mut self,
+        value: &str,
+        anon_type: &AnonymizationType,
+        preserve_relationships: bool,
+    ) -> String {
+        match anon_type {
+            AnonymizationType::FakeEmail => {
+                let normalize = self.normalize_email_keys;
+                let key = if normalize {
+                    value.to_lowercase()
+                } else {
+                    value.to_string()
+                };
+                let domain = key
+                    .rsplit_once('@')
+                    .filter(|_| self.keep_email_domain)
+                    .map(|(_, domain)| domain);
+                let locale = self.locale;
+                self.fake(&key, preserve_relationships, |rng| {
+                    let email =
+                        draw_fake(value, anon_type, locale, rng).expect("drawn from the RNG");
+                    let email = match (domain, email.split_once('@')) {
+                        (Some(domain), Some((local, _))) => format!("{}@{}", local, domain),
+                        _ => email,
+                    };
+                    if normalize {
+                        email.to_lowercase()
+                    } else {
+                        email
+                    }
+                })
+            }
+
+            AnonymizationType::FakePhone => {
+                let locale = self.locale;
+                let keep_format = self.keep_phone_format;
+                self.fake(value, preserve_relationships, |rng| {
+                    let phone =
+                        draw_fake(value, anon_type, locale, rng).expect("drawn from the RNG");
+                    if keep_format {
+                        let phone = fill_digits(value, &phone, rng);
+                        reserve_phone(&phone, rng)
+                    } else {
+                        phone
+                    }
+                })
+            }
+
+            AnonymizationType::FakeName
+            | AnonymizationType::FakeFirstName
+            | AnonymizationType::FakeLastName
+            | AnonymizationType::FakeAddress
+            | AnonymizationType::FakeStreetAddress
+            | AnonymizationType::FakeCity
+            | AnonymizationType::FakeState
+            | AnonymizationType::FakeZip
+            | AnonymizationType::FakeCompany
+            | AnonymizationType::FakeCreditCard
+            | AnonymizationType::FakeUuid
+            | AnonymizationType::Scramble => {
+                let locale = self.locale;
+                self.fake(value, preserve_relationships, |rng| {
+                    draw_fake(value, anon_type, locale, rng).expect("drawn from the RNG")
+                })
+            }
+
+            // Cached like any fake, so each original keeps its value
+            AnonymizationType::FakeGender => {
+                let values = self.gender_values.clone();
+                self.fake(value, preserve_relationships, |rng| {
+                    values[rng.gen_range(0..values.len())].clone()
+                })
+            }
+
+            AnonymizationType::FakeIp => {
+                let range = self.fake_ip_range;
+                self.fake(value, preserve_relationships, |rng| {
+                    fake_ipv4(range, rng).to_string()
+                })
+            }
+
+            AnonymizationType::MaskCreditCard => {
+                let group = self.mask(4);
+                if value.chars().count() > 4 {
+                    // By character, since the last 4 bytes may split one
+                    let mut last4: Vec<char> = value.chars().rev().take(4).collect();
+                    last4.reverse();
+                    format!("{group}-{group}-{group}-{}", String::from_iter(last4))
+                } else {
+                    group
+                }
+            }
+
+            // Keep the domain so addresses can still be grouped by it
+            AnonymizationType::MaskEmail => match value.rsplit_once('@') {
+                Some((_, domain)) => format!("{}@{}", self.mask(4), domain),
+                None => self.mask(4),
+            },
+
+            AnonymizationType::MaskSSN => {
+                format!("{}-{}-{}", self.mask(3), self.mask(2), self.mask(4))
+            }
+
+            AnonymizationType::MaskIban => {
+                // Keep the country code and check digits, mask the BBAN
+                if value.chars().count() < 6 {
+                    self.mask(4)
+                } else {
+                    value
+                        .chars()
+                        .enumerate()
+                        .map(|(i, c)| if i < 4 || c == ' ' { c } else { self.mask_char })
+                        .collect()
+                }
+            }
+
+            // Keep the first and last character so the record stays recognizable
+            AnonymizationType::MaskMiddle => {
+                let len = value.chars().count();
+                value
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        if len > 2 && (i == 0 || i == len - 1) {
+                            c
+                        } else {
+                            self.mask_char
+                        }
+                    })
+                    .collect()
+            }
+
+            // Keep the network prefix so traffic can still be grouped by it
+            AnonymizationType::MaskIp => mask_ip(value).unwrap_or_else(|| value.to_string()),
+
+            // A value too short to hide anything once the kept characters are
+            // shown is masked in full
+            AnonymizationType::Mask {
+                keep_first,
+                keep_last,
+            } => {
+                let len = value.chars().count();
+                let reveal = len > keep_first.saturating_add(*keep_last);
+                value
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        if reveal && (i < *keep_first || i >= len - keep_last) {
+                            c
+                        } else {
+                            self.mask_char
+                        }
+                    })
+                    .collect()
+            }
+
+            // Without an entity key every date shifts by the same offset
+            AnonymizationType::ShiftDate => self.shift_by_entity(value, ""),
+
+            // Derived from the value itself, so it is consistent without the cache
+            AnonymizationType::Perturb => self.perturb(value),
+
+            AnonymizationType::Hash => {
+                let mut hasher = Sha256::new();
+                if let Some(salt) = &self.hash_salt {
+                    hasher.update(salt.as_bytes());
+                }
+                hasher.update(value.as_bytes());
+                self.fit_digest(format!("{:x}", hasher.finalize()), value)
+            }
+
+            AnonymizationType::Hmac => match &self.hmac_key {
+                Some(key) => {
+                    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+                        .expect("HMAC accepts keys of any length");
+                    mac.update(value.as_bytes());
+                    self.fit_digest(format!("{:x}", mac.finalize().into_bytes()), value)
+                }
+                // Still one-way, just not keyed
+                None => self.transform(value, &AnonymizationType::Hash, false),
+            },
+
+            AnonymizationType::Tokenize => match self.key {
+                Some(key) => tokenize(&key, value),
+                // Without a key there is no way back, so never emit the value in the clear
+                None => self.transform(value, &AnonymizationType::Hash, false),
+            },
+
+            AnonymizationType::Nullify => "NULL".to_string(),
+
+            // Unlike `Nullify` the column keeps a (quoted) string
+            AnonymizationType::Redact => self.redact_label.clone(),
+
+            // Identical for every input, so there is nothing to cache
+            AnonymizationType::Constant(constant) => constant.clone(),
+
+            AnonymizationType::Custom(name) => match self.generators.get(name).cloned() {
+                Some(generator) => {
+                    self.fake(value, preserve_relationships, |_| generator.generate(value))
+                }
+                // An unregistered generator must not leave the value in the clear
+                None => self.transform(value, &AnonymizationType::Hash, false),
+            },
+
+            // Only the listed paths change; the rest of the document is kept
+            AnonymizationType::Json(paths) => {
+                let Ok(mut document) = serde_json::from_str::<serde_json::Value>(value) else {
+                    return value.to_string();
+                };
+                json::anonymize_value(&mut document, &mut |path: &str, text: &str| {
+                    let (_, anon_type) = paths.iter().find(|(key, _)| key == path)?;
+                    Some(self.transform(text, anon_type, preserve_relationships))
+                });
+                document.to_string()
+            }
+
+            AnonymizationType::Pattern(pattern) => match self.pattern(pattern) {
+                Some(regex) => self.fake(value, preserve_relationships, |rng| {
+                    let bytes: Vec<u8> = rng.sample(&*regex);
+                    String::from_utf8_lossy(&bytes).into_owned()
+                }),
+                // Likewise for a regex that can't be generated from
+                None => self.transform(value, &AnonymizationType::Hash, false),
+            },
+
+            // Built from fakes already cached for their own columns, so the
+            // result is as consistent as they are and needs no cache entry
+            AnonymizationType::Template { format, .. } => match self.template_values.take() {
+                Some(values) => render_template(format, &values),
+                // Without the row there is nothing to fill it with
+                None => self.transform(value, &AnonymizationType::Hash, false),
+            },
+
+            // Derived from the original rather than cached, like `Perturb`,
+            // so it is the same in every fork and without preservation
+            AnonymizationType::Categorical(choices) => {
+                let mut hasher = Sha256::new();
+                hasher.update(self.shift_secret.to_le_bytes());
+                hasher.update(b"categorical\0");
+                if let Some(group) = &self.group {
+                    hasher.update(group.as_bytes());
+                    hasher.update(b"\0");
+                }
+                hasher.update(value.as_bytes());
+                let digest = hasher.finalize();
+                let n = u64::from_le_bytes(digest[..8].try_into().unwrap());
+                match weighted_choice(choices, n) {
+                    Some(choice) => choice.to_string(),
+                    None => self.transform(value, &AnonymizationType::Hash, false),
+                }
+            }
+
+            AnonymizationType::Skip => value.to_string(),
+        }
+    }
+
+    /// The persona for a relationship key, generating it on first use
+    ///
+    /// Every call with the same key, in this anonymizer or any fork, returns
+    /// the same persona, so a full name and the first and last name columns
+    /// keyed by it describe one fake person.
+    pub fn persona_for(&mut self, key: &str) -> &Persona {
+        if !self.local_personas.contains_key(key) {
+            let shared = self.shared_personas().get(key).cloned();
+            let persona = match shared {
+                Some(persona) => persona,
+                None => {
+                    let locale = self.locale;
+                    let generated = self.generate(key, |rng| Persona::generate(locale, rng));
+                    // As with the value cache, a persona another fork stored first wins
+                    self.shared_personas()
+                        .entry(key.to_string())
+                        .or_insert(generated)
+                        .clone()
+                }
+            };
+            self.local_personas.insert(key.to_string(), persona);
+        }
+        &self.local_personas[key]
+    }
+
+    /// Anonymize a value with the matching field of the persona for `key`
+    ///
+    /// Returns `None` for methods a persona has no field for, such as `hash`,
+    /// and for empty values, which `anonymize_grouped` leaves unchanged.
+    pub fn anonymize_persona(
+        &mut self,
+        value: &str,
+        anon_type: &AnonymizationType,
+        key: &str,
+    ) -> Option<AnonymizeOutcome> {
+        if value.is_empty() {
+            return None;
+        }
+        let was_cached =
+            self.local_personas.contains_key(key) || self.shared_personas().contains_key(key);
+        let fake = self.persona_for(key).field(anon_type)?.to_string();
+        self.record(value, anon_type);
+        Some(AnonymizeOutcome {
+            changed: fake != value,
+            was_cached,
+            is_null: false,
+            value: fake,
+        })
+    }
+
+    /// Shift a `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS` value by a per-entity offset
+    ///
+    /// The offset is derived from `entity_key` (e.g. a row's primary key), so
+    /// all dates belonging to the same entity move together and the intervals
+    /// between them are preserved. Values that don't parse as dates are
+    /// returned unchanged.
+    pub fn shift_date(&mut self, value: &str, entity_key: &str) -> String {
+        self.record(value, &AnonymizationType::ShiftDate);
+        self.shift_by_entity(value, entity_key)
+    }
+
+    fn shift_by_entity(&self, value: &str, entity_key: &str) -> String {
+        let max = i64::from(self.date_shift_max_days);
+        if max == 0 {
+            return value.to_string();
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.shift_secret.to_le_bytes());
+        hasher.update(entity_key.as_bytes());
+        let digest = hasher.finalize();
+        let n = u64::from_le_bytes(digest[..8].try_into().unwrap()) % (2 * max as u64);
+
+        // Map onto [-max, -1] and [1, max] so dates always move
+        let offset = n as i64 - max;
+        let offset = if offset >= 0 { offset + 1 } else { offset };
+
+        date::shift_days(value, offset).unwrap_or_else(|| value.to_string())
+    }
+
+    /// Move a number by up to `perturb_percent` of its value
+    ///
+    /// The offset is derived from the value, so equal numbers always move the
+    /// same way. The original's decimal places are kept, and the offset is
+    /// truncated toward zero so rounding never leaves the bound. Values that
+    /// aren't plain decimal numbers are returned unchanged.
+    fn perturb(&self, value: &str) -> String {
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty()
+            || digits.ends_with('.')
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return value.to_string();
+        }
+        let Ok(magnitude) = format!("{}{}", whole, fraction).parse::<i128>() else {
+            return value.to_string();
+        };
+        let scaled = if negative { -magnitude } else { magnitude };
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.shift_secret.to_le_bytes());
+        hasher.update(b"perturb\0");
+        hasher.update(value.as_bytes());
+        let digest = hasher.finalize();
+        // Uniform in [-1, 1]
+        let unit = u64::from_le_bytes(digest[..8].try_into().unwrap()) as f64 / u64::MAX as f64;
+        let factor = (unit * 2.0 - 1.0) * self.perturb_percent / 100.0;
+        // Saturating, since an offset near i128::MAX can't be added back
+        let perturbed = scaled.saturating_add((scaled as f64 * factor).trunc() as i128);
+
+        let sign = if perturbed < 0 { "-" } else { "" };
+        let digits = format!(
+            "{:0width$}",
+            perturbed.unsigned_abs(),
+            width = fraction.len() + 1
+        );
+        if fraction.is_empty() {
+            format!("{}{}", sign, digits)
+        } else {
+            let (whole, fraction) = digits.split_at(digits.len() - fraction.len());
+            format!("{}{}.{}", sign, whole, fraction)
+        }
+    }
+
+    /// Recover the original value from a token produced by `Tokenize`
+    ///
+    /// Returns `None` if no key is set, the token is malformed, or it was
+    /// produced with a different key.
+    pub fn detokenize(&self, token: &str) -> Option<String> {
+        let key = self.key.as_ref()?;
+        let bytes = BASE64.decode(token).ok()?;
+        if bytes.len() < 12 {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(12);
+        let cipher = Aes256Gcm::new(key.into());
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+
+    /// The compiled generator for a `Pattern` regex, compiling it on first use
+    fn pattern(&mut self, pattern: &str) -> Option<Arc<rand_regex::Regex>> {
+        if !self.patterns.contains_key(pattern) {
+            self.patterns.insert(
+                pattern.to_string(),
+                Arc::new(compile_pattern(pattern).ok()?),
+            );
+        }
+        self.patterns.get(pattern).cloned()
+    }
+
+    /// Cut a 64-char hex digest to the original's length or `hash_length`
+    ///
+    /// For longer originals the digest is extended with the hash of its last
+    /// 64 chars, round after round, so the padding is as deterministic as
+    /// the digest itself.
+    fn fit_digest(&self, mut digest: String, original: &str) -> String {
+        let length = if self.hash_preserve_length {
+            original.chars().count()
+        } else {
+            match self.hash_length {
+                Some(length) => length,
+                None => return digest,
+            }
+        };
+        while digest.len() < length {
+            let tail = &digest[digest.len() - 64..];
+            let next = format!("{:x}", Sha256::digest(tail.as_bytes()));
+            digest.push_str(&next);
+        }
+        digest.truncate(length);
+        digest
+    }
+
+    /// `len` copies of the mask character
+    fn mask(&self, len: usize) -> String {
+        std::iter::repeat_n(self.mask_char, len).collect()
+    }
+
+    /// Generate a fake value, going through the cache when relationships are preserved
+    fn fake<F>(&mut self, original: &str, preserve_relationships: bool, generator: F) -> String
+    where
+        F: FnOnce(&mut StdRng) -> String,
+    {
+        if preserve_relationships {
+            self.get_or_generate(original, generator)
+        } else {
+            self.generate(original, generator)
+        }
+    }
+
+    /// Get cached value or generate new one (for relationship preservation)
+    fn get_or_generate<F>(&mut self, original: &str, generator: F) -> String
+    where
+        F: FnOnce(&mut StdRng) -> String,
+    {
+        let key = match &self.group {
+            Some(group) => format!("{}\0{}", group, original),
+            None => original.to_string(),
+        };
+        let cached = self.cache().get(&key);
+        if let Some(cached) = cached {
+            self.cache_hit = true;
+            return cached;
+        }
+        // Generate without holding the lock; if another fork cached this
+        // original in the meantime, its value wins so the mapping stays unique
+        let generated = self.generate(original, generator);
+        self.cache().get_or_insert(key, generated)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, FakeCache> {
+        // A panic elsewhere can't leave the map half-updated, so ignore poisoning
+        self.hash_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn shared_personas(&self) -> MutexGuard<'_, HashMap<String, Persona>> {
+        self.personas
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Run a generator with the RNG for this value
+    ///
+    /// Seeded and deterministic anonymizers derive a fresh RNG from the seed
+    /// or salt and the original value, so output does not depend on the order
+    /// values are seen in.
+    fn generate<T, F>(&mut self, original: &str, generator: F) -> T
+    where
+        F: FnOnce(&mut StdRng) -> T,
+    {
+        if self.deterministic {
+            let salt = self.hash_salt.as_deref().unwrap_or_default();
+            return generator(&mut value_rng(salt.as_bytes(), original));
+        }
+        match self.seed {
+            Some(seed) => generator(&mut value_rng(&seed.to_le_bytes(), original)),
+            None => generator(&mut self.rng),
+        }
+    }
+}
+
+/// Longest run generated for an unbounded repetition such as `\d+`
+const PATTERN_MAX_REPEAT: u32 = 16;
+
+/// Compile a `pattern` regex into a generator
+///
+/// A leading `^` and trailing `$` are dropped, since every generated value
+/// matches in full anyway; other anchors and word boundaries are errors.
+fn compile_pattern(pattern: &str) -> Result<rand_regex::Regex, rand_regex::Error> {
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = match pattern.strip_suffix('$') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => pattern,
+    };
+    rand_regex::Regex::compile(pattern, PATTERN_MAX_REPEAT)
+}
+
+/// The distinct `{placeholder}` names in a template format, in order of
+/// first appearance
+fn template_placeholders(format: &str) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    let mut rest = format;
+    while let Some((_, after)) = rest.split_once('{') {
+        rest = match after.split_once('}') {
+            Some((name, tail)) if !name.contains('{') => {
+                if !name.is_empty() && !names.contains(&name) {
+                    names.push(name);
+                }
+                tail
+            }
+            // An unmatched brace; a placeholder may still follow it
+            _ => after,
+        };
+    }
+    names
+}
+
+/// Fill a template format's placeholders with `values`, assigned to the
+/// placeholder names in order of first appearance
+///
+/// Text outside placeholders, including unmatched braces, is kept as is.
+fn render_template(format: &str, values: &[String]) -> String {
+    let names = template_placeholders(format);
+    let mut rendered = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some((before, after)) = rest.split_once('{') {
+        rendered.push_str(before);
+        match after
+            .split_once('}')
+            .and_then(|(name, tail)| Some((names.iter().position(|n| *n == name)?, tail)))
+        {
+            Some((index, tail)) => {
+                rendered.push_str(values.get(index).map(String::as_str).unwrap_or_default());
+                rest = tail;
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// An IP address with its host part zeroed, or `None` if it isn't one
+///
+/// IPv4 keeps its /24 and IPv6 its /48, the prefixes analytics usually
+/// group by. IPv4-mapped IPv6 addresses are masked like the IPv4 inside.
+fn mask_ip(value: &str) -> Option<String> {
+    let mask_v4 = |ip: Ipv4Addr| {
+        let [a, b, c, _] = ip.octets();
+        Ipv4Addr::new(a, b, c, 0)
+    };
+    match value.parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip) => Some(mask_v4(ip).to_string()),
+        IpAddr::V6(ip) => Some(match ip.to_ipv4_mapped() {
+            Some(mapped) => mask_v4(mapped).to_ipv6_mapped().to_string(),
+            None => Ipv6Addr::from(u128::from(ip) & !((1u128 << 80) - 1)).to_string(),
+        }),
+    }
+}
+
+/// The RNG for one original, derived from a seed or salt and the value
+///
+/// Output drawn from it depends only on those, not on what came before.
+fn value_rng(secret: &[u8], original: &str) -> StdRng {
+    let mut hasher = Sha256::new();
+    hasher.update(secret);
+    hasher.update(original.as_bytes());
+    StdRng::from_seed(hasher.finalize().into())
+}
+
+/// Draw a fake for the types generated from an RNG alone
+///
+/// Touches no cache or anonymizer state, so `anonymize_value` can call it
+/// directly. Emails and phones come out plain, before the keep-domain and
+/// keep-format settings apply. Returns `None` for the other types.
+fn draw_fake(
+    value: &str,
+    anon_type: &AnonymizationType,
+    locale: Locale,
+    rng: &mut StdRng,
+) -> Option<String> {
+    let fake = match anon_type {
+        AnonymizationType::FakeEmail => SafeEmail().fake_with_rng(rng),
+        AnonymizationType::FakeName => localized!(locale, name::raw::Name, rng),
+        AnonymizationType::FakeFirstName => localized!(locale, name::raw::FirstName, rng),
+        AnonymizationType::FakeLastName => localized!(locale, name::raw::LastName, rng),
+        AnonymizationType::FakePhone => {
+            let phone = localized!(locale, phone_number::raw::PhoneNumber, rng);
+            reserve_phone(&phone, rng)
+        }
+        AnonymizationType::FakeAddress => {
+            let street = fake_street_address(locale, rng);
+            let city = localized!(locale, address::raw::CityName, rng);
+            let state = localized!(locale, address::raw::StateAbbr, rng);
+            let zip = localized!(locale, address::raw::ZipCode, rng);
+            format!("{}, {}, {} {}", street, city, state, zip)
+        }
+        AnonymizationType::FakeStreetAddress => fake_street_address(locale, rng),
+        AnonymizationType::FakeCity => localized!(locale, address::raw::CityName, rng),
+        AnonymizationType::FakeState => localized!(locale, address::raw::StateAbbr, rng),
+        AnonymizationType::FakeZip => localized!(locale, address::raw::ZipCode, rng),
+        AnonymizationType::FakeCompany => CompanyName().fake_with_rng(rng),
+        AnonymizationType::FakeCreditCard => fake_credit_card(value, rng),
+        AnonymizationType::FakeUuid => fake_uuid(rng),
+        AnonymizationType::Scramble => scramble(value, rng),
+        _ => return None,
+    };
+    Some(fake)
+}
+
+/// Generate a street address line such as "123 Maple Street"
+fn fake_street_address(locale: Locale, rng: &mut StdRng) -> String {
+    let number = localized!(locale, address::raw::BuildingNumber, rng);
+    let street = localized!(locale, address::raw::StreetName, rng);
+    format!("{} {}", number, street)
+}
+
+/// Generate a Luhn-valid 16-digit test card number in the 4242 IIN range
+///
+/// If the original has 16 digits its separators (dashes, spaces) are kept.
+fn fake_credit_card(original: &str, rng: &mut StdRng) -> String {
+    let mut digits: Vec<u32> = vec![4, 2, 4, 2];
+    digits.extend((0..11).map(|_| rng.gen_range(0..10)));
+    digits.push(luhn_check_digit(&digits));
+
+    let mut generated = digits.iter().map(|d| char::from_digit(*d, 10).unwrap());
+    if original.chars().filter(char::is_ascii_digit).count() == 16 {
+        original
+            .chars()
+            .map(|c| {
+                if c.is_ascii_digit() {
+                    generated.next().unwrap()
+                } else {
+                    c
+                }
+            })
+            .collect()
+    } else {
+        generated.collect()
+    }
+}
+
+/// Replace the digits of `template` with those of `digits`, keeping its layout
+///
+/// Separators and grouping come from `template`, so `(555) 123-4567` stays
+/// `(XXX) XXX-XXXX`. Runs out of `digits` are filled with random ones.
+fn fill_digits(template: &str, digits: &str, rng: &mut StdRng) -> String {
+    let mut digits = digits.chars().filter(char::is_ascii_digit);
+    template
+        .chars()
+        .map(|c| {
+            if c.is_ascii_digit() {
+                digits
+                    .next()
+                    .unwrap_or_else(|| char::from(rng.gen_range(b'0'..=b'9')))
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Generate a random IPv4, in `range` if given
+///
+/// Without a range the address is public-looking unicast: no `0.x`,
+/// loopback, multicast or reserved first octet, and no `.0` or `.255` host.
+fn fake_ipv4(range: Option<(Ipv4Addr, u8)>, rng: &mut StdRng) -> Ipv4Addr {
+    match range {
+        Some((network, prefix_len)) => {
+            let hosts = u32::MAX >> prefix_len.min(31);
+            let host = match prefix_len {
+                32 => 0,
+                // Skip the network and broadcast addresses where there are others
+                0..=30 => rng.gen_range(1..hosts),
+                _ => rng.gen_range(0..=hosts),
+            };
+            Ipv4Addr::from(u32::from(network) | host)
+        }
+        None => {
+            let first = match rng.gen_range(1..=222u8) {
+                first if first >= 127 => first + 1,
+                first => first,
+            };
+            Ipv4Addr::new(first, rng.gen(), rng.gen(), rng.gen_range(1..=254))
+        }
+    }
+}
+
+/// Generate a random version 4 UUID such as `1b4e28ba-2fa1-4d2e-8fb6-5e7c1d4a9f20`
+fn fake_uuid(rng: &mut StdRng) -> String {
+    let mut bytes: [u8; 16] = rng.gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Replace letters and digits with random ones, keeping length and layout
+///
+/// Case, spaces and punctuation stay in place so the result fits the same
+/// column width and still looks like text.
+fn scramble(original: &str, rng: &mut StdRng) -> String {
+    original
+        .chars()
+        .map(|c| {
+            if c.is_ascii_digit() {
+                char::from(rng.gen_range(b'0'..=b'9'))
+            } else if c.is_uppercase() {
+                char::from(rng.gen_range(b'A'..=b'Z'))
+            } else if c.is_alphabetic() {
+                char::from(rng.gen_range(b'a'..=b'z'))
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Check digit that makes `digits` followed by it pass the Luhn check
+fn luhn_check_digit(digits: &[u32]) -> u32 {
+    // Double every second digit, starting from the one next to the check digit
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2, d * 2) {
+            (0, doubled) if doubled > 9 => doubled - 9,
+            (0, doubled) => doubled,
+            _ => d,
+        })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+/// Whether a card number (separators allowed) passes the Luhn checksum
+pub fn luhn_valid(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    match digits.split_last() {
+        Some((&check, rest)) if !rest.is_empty() => luhn_check_digit(rest) == check,
+        _ => false,
+    }
+}
+
+/// Remove the UTF-8 byte order mark Windows tools start files with
+///
+/// Readers call this on the first line of their input, so the mark never
+/// sticks to the first statement or field.
+pub(crate) fn strip_bom(line: String) -> String {
+    match line.strip_prefix('\u{feff}') {
+        Some(rest) => rest.to_string(),
+        None => line,
+    }
+}
+
+/// Deterministically encrypt a value with AES-256-GCM
+///
+/// The nonce is derived from the key and the value, so the same input always
+/// produces the same token. This intentionally reveals equality between
+/// values, which is what keeps joins on tokenized columns intact.
+fn tokenize(key: &[u8; 32], value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    hasher.update(value.as_bytes());
+    let digest = hasher.finalize();
+    let nonce = Nonce::from_slice(&digest[..12]);
+
+    let cipher = Aes256Gcm::new(key.into());
+    let ciphertext = cipher
+        .encrypt(nonce, value.as_bytes())
+        .expect("AES-GCM encryption of an in-memory buffer cannot fail");
+
+    let mut token = nonce.to_vec();
+    token.extend_from_slice(&ciphertext);
+    BASE64.encode(token)
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Anonymize a single value without an `Anonymizer`
+///
+/// The result is a pure function of the value, type and seed, so this can be
+/// called from any number of threads without sharing or locking anything.
+/// The tradeoff is that there is no relationship cache: values only stay
+/// consistent across calls because the same input always gives the same
+/// output, not because earlier mappings are remembered. That means no
+/// consistency groups, personas or imported caches, and two inputs may share
+/// a fake. Use `Anonymizer::anonymize` when those matter.
+pub fn anonymize_value(value: &str, anon_type: &AnonymizationType, seed: u64) -> String {
+    if !value.is_empty() {
+        let mut rng = value_rng(&seed.to_le_bytes(), value);
+        if let Some(fake) = draw_fake(value, anon_type, Locale::En, &mut rng) {
+            return fake;
+        }
+    }
+    // Masks, hashes and the other types without an RNG use the default settings
+    Anonymizer::with_seed(seed).anonymize(value, anon_type, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_anonymizer_relationship_preservation() {
+        let mut anonymizer = Anonymizer::new();
+
+        let email1 = anonymizer.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+        let email2 = anonymizer.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+
+        assert_eq!(email1, email2);
+    }
+
+    #[test]
+    fn test_anonymizer_with_seed_is_reproducible() {
+        let mut first = Anonymizer::with_seed(42);
+        let mut second = Anonymizer::with_seed(42);
+
+        // Different call order must not change the output for a given value
+        let name1 = first.anonymize("Jane Doe", &AnonymizationType::FakeName, false);
+        let email1 = first.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+        let email2 = second.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+        let name2 = second.anonymize("Jane Doe", &AnonymizationType::FakeName, false);
+
+        assert_eq!(email1, email2);
+        assert_eq!(name1, name2);
+
+        let mut other = Anonymizer::with_seed(7);
+        let email3 = other.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+        assert_ne!(email1, email3);
+    }
+
+    #[test]
+    fn test_anonymizer_keeps_empty_values() {
+        let mut anonymizer = Anonymizer::new();
+        for anon_type in AnonymizationType::ALL
+            .iter()
+            .cloned()
+            .chain([AnonymizationType::Constant("XYZ".to_string())])
+        {
+            let outcome = anonymizer.anonymize_detailed("", &anon_type, true);
+            assert_eq!(outcome.value, "", "{}", anon_type);
+            assert!(!outcome.changed);
+            assert!(!outcome.is_null);
+        }
+        assert_eq!(
+            anonymizer.anonymize_persona("", &AnonymizationType::FakeEmail, "1"),
+            None
+        );
+        assert!(anonymizer.stats().is_empty());
+    }
+
+    /// Account codes like `ACC-1234` with the digits reversed
+    struct AccountCode;
+
+    impl Generator for AccountCode {
+        fn generate(&self, original: &str) -> String {
+            let digits: String = original
+                .chars()
+                .filter(char::is_ascii_digit)
+                .rev()
+                .collect();
+            format!("ACC-{}", digits)
+        }
+    }
+
+    #[test]
+    fn test_anonymizer_custom_generator() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  accounts.code: { method: custom, generator: account_code }\n",
+        )
+        .unwrap();
+        let custom = config.custom_rules["accounts.code"].anon_type().unwrap();
+        assert_eq!(
+            custom,
+            AnonymizationType::Custom("account_code".to_string())
+        );
+
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.register("account_code", Box::new(AccountCode));
+        assert_eq!(anonymizer.anonymize("ACC-1234", &custom, true), "ACC-4321");
+        assert!(anonymizer.is_cached("ACC-1234"));
+        assert_eq!(
+            anonymizer.fork().anonymize("ACC-5678", &custom, false),
+            "ACC-8765"
+        );
+
+        // Without a registered generator the value is hashed rather than kept
+        let unregistered = Anonymizer::new().anonymize("ACC-1234", &custom, true);
+        assert_eq!(
+            unregistered,
+            Anonymizer::new().anonymize("ACC-1234", &AnonymizationType::Hash, true)
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_pattern() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  staff.employee_id: { method: pattern, regex: '^E\\d{6}$' }\n",
+        )
+        .unwrap();
+        let pattern = config.custom_rules["staff.employee_id"]
+            .anon_type()
+            .unwrap();
+        assert_eq!(pattern, AnonymizationType::Pattern(r"^E\d{6}$".to_string()));
+
+        let matcher = Regex::new(r"^E\d{6}$").unwrap();
+        let mut anonymizer = Anonymizer::new();
+        for original in ["E000001", "E000002", "E123456"] {
+            let fake = anonymizer.anonymize(original, &pattern, true);
+            assert!(matcher.is_match(&fake), "{fake}");
+            assert_eq!(anonymizer.anonymize(original, &pattern, true), fake);
+        }
+
+        let multibyte = AnonymizationType::Pattern("[a-zé]{3}-[0-9]+".to_string());
+        let fake = anonymizer.anonymize("abc-1", &multibyte, false);
+        assert!(Regex::new("^[a-zé]{3}-[0-9]+$").unwrap().is_match(&fake));
+    }
+
+    #[test]
+    fn test_anonymizer_json_paths() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  events.payload: { method: json, paths: { user.email: fake_email, 'tags[]': hash } }\n  events.bad: { method: json, paths: { a: nope } }\n",
+        )
+        .unwrap();
+        let json = config.custom_rules["events.payload"].anon_type().unwrap();
+        assert_eq!(config.validate(), Err(vec!["events.bad".to_string()]));
+
+        let mut anonymizer = Anonymizer::with_seed(1);
+        let payload = r#"{"user":{"email":"jane@x.com","plan":"pro"},"tags":["a"],"n":1}"#;
+        let anonymized = anonymizer.anonymize(payload, &json, true);
+        let document: serde_json::Value = serde_json::from_str(&anonymized).unwrap();
+
+        // The embedded email maps to the same fake as the column holding it
+        assert_eq!(
+            document["user"]["email"],
+            anonymizer.anonymize("jane@x.com", &AnonymizationType::FakeEmail, true)
+        );
+        assert_eq!(document["user"]["plan"], "pro");
+        assert_eq!(document["n"], 1);
+        assert_ne!(document["tags"][0], "a");
+
+        assert_eq!(anonymizer.anonymize("not json", &json, true), "not json");
+    }
+
+    #[test]
+    fn test_anonymizer_template() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  users.email: { method: template, format: '{first}.{last}@example.com', from: [users.first_name, users.last_name] }\n  users.short: { method: template, format: '{a}{b}', from: [a] }\n  users.bare: template\n",
+        )
+        .unwrap();
+        let template = config.custom_rules["users.email"].anon_type().unwrap();
+        assert_eq!(
+            config.validate(),
+            Err(vec!["users.bare".to_string(), "users.short".to_string()])
+        );
+
+        let mut anonymizer = Anonymizer::with_seed(1);
+        let outcome = anonymizer.anonymize_template(
+            "john.smith@acme.com",
+            &template,
+            true,
+            None,
+            &["Jane", "Doe"],
+        );
+        assert_eq!(outcome.value, "Jane.Doe@example.com");
+        assert!(outcome.changed);
+
+        // Without the row's values it is hashed, never left in the clear
+        let hashed = anonymizer.anonymize("john.smith@acme.com", &template, true);
+        assert_eq!(
+            hashed,
+            anonymizer.anonymize("john.smith@acme.com", &AnonymizationType::Hash, true)
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_categorical() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  users.country: { method: categorical, values: { US: 0.5, DE: 0.3, FR: 0.2 } }\n  users.negative: { method: categorical, values: { US: 1, DE: -1 } }\n  users.zero: { method: categorical, values: { US: 0 } }\n  users.bare: categorical\n",
+        )
+        .unwrap();
+        let categorical = config.custom_rules["users.country"].anon_type().unwrap();
+        assert_eq!(
+            categorical,
+            AnonymizationType::Categorical(vec![
+                ("DE".to_string(), 300_000),
+                ("FR".to_string(), 200_000),
+                ("US".to_string(), 500_000),
+            ])
+        );
+        assert_eq!(
+            config.validate(),
+            Err(vec![
+                "users.bare".to_string(),
+                "users.negative".to_string(),
+                "users.zero".to_string()
+            ])
+        );
+
+        let mut anonymizer = Anonymizer::new();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for i in 0..1000 {
+            let original = format!("country {}", i);
+            let value = anonymizer.anonymize(&original, &categorical, false);
+            assert!(["US", "DE", "FR"].contains(&value.as_str()), "{}", value);
+            // The same original always gets the same value, cached or not
+            assert_eq!(anonymizer.anonymize(&original, &categorical, true), value);
+            *counts.entry(value).or_default() += 1;
+        }
+        assert!(counts["US"] > counts["DE"] && counts["DE"] > counts["FR"]);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let values = ["a".to_string(), "b".to_string()];
+        assert_eq!(render_template("{x}-{y}-{x}", &values), "a-b-a");
+        assert_eq!(render_template("{x} {y} {z}", &values), "a b ");
+        assert_eq!(render_template("{} { {x}", &values), "{} { a");
+        assert_eq!(template_placeholders("{x}{y}{x}{"), ["x", "y"]);
+    }
+
+    #[test]
+    fn test_pattern_rule_needs_a_usable_regex() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  a.missing: pattern\n  a.invalid: { method: pattern, regex: '(' }\n  a.anchored: { method: pattern, regex: '\\bx' }\n  a.ok: { method: pattern, regex: '[A-Z]{3}' }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate(),
+            Err(vec![
+                "a.anchored".to_string(),
+                "a.invalid".to_string(),
+                "a.missing".to_string()
+            ])
+        );
+
+        // Library callers skipping validation get a hash, never the original
+        let invalid = AnonymizationType::Pattern("(".to_string());
+        assert_eq!(
+            Anonymizer::new().anonymize("secret", &invalid, true),
+            Anonymizer::new().anonymize("secret", &AnonymizationType::Hash, true)
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_deterministic() {
+        let anonymizer = |salt: &str| {
+            let mut anonymizer = Anonymizer::new();
+            anonymizer.set_hash_salt(salt);
+            anonymizer.set_deterministic(true);
+            anonymizer
+        };
+        let (mut first, mut second) = (anonymizer("pepper"), anonymizer("pepper"));
+
+        for anon_type in [
+            AnonymizationType::FakeName,
+            AnonymizationType::FakeEmail,
+            AnonymizationType::FakePhone,
+        ] {
+            for preserve in [true, false] {
+                assert_eq!(
+                    first.anonymize("John Smith", &anon_type, preserve),
+                    second.anonymize("John Smith", &anon_type, preserve)
+                );
+            }
+        }
+        assert_ne!(
+            first.anonymize("Jane Doe", &AnonymizationType::FakeEmail, true),
+            anonymizer("salt").anonymize("Jane Doe", &AnonymizationType::FakeEmail, true)
+        );
+    }
+
+    #[test]
+    fn test_anonymize_value_is_deterministic() {
+        let expected = anonymize_value("John Smith", &AnonymizationType::FakeName, 7);
+        let results: Vec<String> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| anonymize_value("John Smith", &AnonymizationType::FakeName, 7))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert!(results.iter().all(|result| *result == expected));
+        assert_ne!(expected, "John Smith");
+        assert_eq!(
+            expected,
+            Anonymizer::with_seed(7).anonymize("John Smith", &AnonymizationType::FakeName, true)
+        );
+        assert_ne!(
+            anonymize_value("2024-01-15", &AnonymizationType::ShiftDate, 7),
+            "2024-01-15"
+        );
+        // Drawn without an anonymizer, but the same fakes a seeded one gives
+        for anon_type in [
+            AnonymizationType::FakeEmail,
+            AnonymizationType::FakePhone,
+            AnonymizationType::FakeAddress,
+            AnonymizationType::FakeCreditCard,
+            AnonymizationType::Scramble,
+            AnonymizationType::Hash,
+        ] {
+            assert_eq!(
+                anonymize_value("4242 4242 4242 4242", &anon_type, 7),
+                Anonymizer::with_seed(7).anonymize("4242 4242 4242 4242", &anon_type, false),
+                "{}",
+                anon_type
+            );
+        }
+        assert_eq!(anonymize_value("", &AnonymizationType::FakeName, 7), "");
+    }
+
+    #[test]
+    fn test_anonymizer_constant() {
+        let mut anonymizer = Anonymizer::new();
+        let constant = AnonymizationType::Constant("XYZ".to_string());
+
+        for preserve in [true, false] {
+            assert_eq!(anonymizer.anonymize("hunter2", &constant, preserve), "XYZ");
+            assert_eq!(anonymizer.anonymize("letmein", &constant, preserve), "XYZ");
+        }
+        assert!(!anonymizer.is_cached("hunter2"));
+        assert_eq!(constant.as_str(), "constant");
+    }
+
+    #[test]
+    fn test_anonymizer_anonymize_detailed() {
+        let mut anonymizer = Anonymizer::new();
+
+        let first =
+            anonymizer.anonymize_detailed("john@example.com", &AnonymizationType::FakeEmail, true);
+        assert!(!first.was_cached);
+        assert!(first.changed);
+
+        let second =
+            anonymizer.anonymize_detailed("john@example.com", &AnonymizationType::FakeEmail, true);
+        assert!(second.was_cached);
+        assert_eq!(second.value, first.value);
+
+        let skipped = anonymizer.anonymize_detailed("keep", &AnonymizationType::Skip, true);
+        assert!(!skipped.was_cached);
+        assert!(!skipped.changed);
+        assert_eq!(skipped.value, "keep");
+        assert!(!skipped.is_null);
+
+        let nulled = anonymizer.anonymize_detailed("notes", &AnonymizationType::Nullify, true);
+        assert!(nulled.is_null);
+        assert_eq!(nulled.value, "NULL");
+    }
+
+    #[test]
+    fn test_anonymizer_consistency_groups() {
+        let mut anonymizer = Anonymizer::new();
+        let email = "john@example.com";
+
+        let primary = anonymizer.anonymize_grouped(
+            email,
+            &AnonymizationType::FakeEmail,
+            true,
+            Some("contact"),
+        );
+        let backup = anonymizer.anonymize_grouped(
+            email,
+            &AnonymizationType::FakeName,
+            true,
+            Some("contact"),
+        );
+        assert_eq!(backup.value, primary.value);
+        assert!(backup.was_cached);
+
+        // Other groups and the default namespace are independent
+        let other =
+            anonymizer.anonymize_grouped(email, &AnonymizationType::FakeName, true, Some("audit"));
+        assert!(!other.was_cached);
+        assert!(!anonymizer.is_cached(email));
+    }
+
+    #[test]
+    fn test_anonymizer_cache_export_import() {
+        let mut first = Anonymizer::new();
+        let email = first.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+
+        let cache = first.export_cache();
+        assert_eq!(cache.get("john@example.com"), Some(&email));
+
+        let mut second = Anonymizer::new();
+        assert!(!second.is_cached("john@example.com"));
+        second.import_cache(cache);
+        assert!(second.is_cached("john@example.com"));
+        assert_eq!(
+            second.anonymize("john@example.com", &AnonymizationType::FakeEmail, true),
+            email
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_clear_cache() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.set_hash_salt("pepper");
+        let hash = anonymizer.anonymize("secret", &AnonymizationType::Hash, true);
+        let originals: Vec<String> = (0..5).map(|i| format!("user{}@example.com", i)).collect();
+        let fakes: Vec<String> = originals
+            .iter()
+            .map(|email| anonymizer.anonymize(email, &AnonymizationType::FakeEmail, true))
+            .collect();
+        anonymizer.persona_for("John Smith");
+
+        anonymizer.clear_cache();
+        assert_eq!(anonymizer.cache_len(), 0);
+        assert!(!anonymizer.is_cached(&originals[0]));
+
+        let outcome =
+            anonymizer.anonymize_grouped(&originals[0], &AnonymizationType::FakeEmail, true, None);
+        assert!(!outcome.was_cached);
+        // Fresh fakes rather than the ones from before the clear
+        let refaked: Vec<String> = originals
+            .iter()
+            .map(|email| anonymizer.anonymize(email, &AnonymizationType::FakeEmail, true))
+            .collect();
+        assert_ne!(refaked, fakes);
+        // The salt survives
+        assert_eq!(
+            anonymizer.anonymize("secret", &AnonymizationType::Hash, true),
+            hash
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_export_mapping() {
+        let mut anonymizer = Anonymizer::new();
+        let email = anonymizer.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+        anonymizer.import_cache(HashMap::from([("kept".to_string(), "kept".to_string())]));
+
+        let mapping = anonymizer.export_mapping();
+        assert_eq!(
+            mapping,
+            vec![MappingEntry {
+                original_hash: format!("{:x}", Sha256::digest(b"john@example.com")),
+                replacement: email,
+            }]
+        );
+        assert!(!serde_json::to_string(&mapping)
+            .unwrap()
+            .contains("john@example.com"));
+    }
+
+    #[test]
+    fn test_anonymizer_fake_email_domain() {
+        let mut anonymizer = Anonymizer::new();
+        let replaced = anonymizer.anonymize("a@acme.com", &AnonymizationType::FakeEmail, true);
+        assert!(!replaced.ends_with("@acme.com"));
+
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.set_keep_email_domain(true);
+        let kept = anonymizer.anonymize("a@acme.com", &AnonymizationType::FakeEmail, true);
+        assert!(kept.ends_with("@acme.com"));
+        assert_ne!(kept, "a@acme.com");
+        assert_eq!(
+            anonymizer.anonymize("a@acme.com", &AnonymizationType::FakeEmail, true),
+            kept
+        );
+
+        // Without a domain to keep, a whole fake address is generated
+        let no_domain = anonymizer.anonymize("not-an-email", &AnonymizationType::FakeEmail, true);
+        assert!(no_domain.contains('@'));
+    }
+
+    #[test]
+    fn test_anonymizer_normalize_email_keys() {
+        let mut anonymizer = Anonymizer::new();
+        let mixed = anonymizer.anonymize("John@X.com", &AnonymizationType::FakeEmail, true);
+        let lower = anonymizer.anonymize("john@x.com", &AnonymizationType::FakeEmail, true);
+        assert_ne!(mixed, lower);
+
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.set_normalize_email_keys(true);
+        anonymizer.set_keep_email_domain(true);
+        let mixed = anonymizer.anonymize("John@X.com", &AnonymizationType::FakeEmail, true);
+        let lower = anonymizer.anonymize("john@x.com", &AnonymizationType::FakeEmail, true);
+        let upper = anonymizer.anonymize("JOHN@X.COM", &AnonymizationType::FakeEmail, true);
+        assert_eq!(mixed, lower);
+        assert_eq!(upper, lower);
+        assert_eq!(lower, lower.to_lowercase());
+        assert!(lower.ends_with("@x.com"));
+
+        // Seeded output doesn't depend on which case variant came first
+        let mut seeded = Anonymizer::with_seed(5);
+        seeded.set_normalize_email_keys(true);
+        let unpreserved = seeded.anonymize("JOHN@x.com", &AnonymizationType::FakeEmail, false);
+        assert_eq!(
+            unpreserved,
+            seeded.anonymize("john@x.com", &AnonymizationType::FakeEmail, false)
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_anonymize_batch() {
+        let values = [
+            ("john@example.com", AnonymizationType::FakeEmail),
+            ("123-45-6789", AnonymizationType::MaskSSN),
+            ("john@example.com", AnonymizationType::FakeEmail),
+            ("keep me", AnonymizationType::Skip),
+        ];
+
+        let mut batch = Anonymizer::with_seed(9);
+        let anonymized = batch.anonymize_batch(&values, true);
+        assert_eq!(anonymized.len(), 4);
+        assert_eq!(anonymized[0], anonymized[2]);
+        assert_eq!(anonymized[1], "***-**-****");
+        assert_eq!(anonymized[3], "keep me");
+
+        // Same output as anonymizing one value at a time
+        let mut single = Anonymizer::with_seed(9);
+        let one_by_one: Vec<String> = values
+            .iter()
+            .map(|(value, anon_type)| single.anonymize(value, anon_type, true))
+            .collect();
+        assert_eq!(anonymized, one_by_one);
+    }
+
+    #[test]
+    fn test_anonymizer_fork_shares_cache() {
+        let mut anonymizer = Anonymizer::new();
+        let email = anonymizer.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+
+        let forks: Vec<Anonymizer> = (0..4).map(|_| anonymizer.fork()).collect();
+        let handles: Vec<_> = forks
+            .into_iter()
+            .map(|mut fork| {
+                std::thread::spawn(move || {
+                    let known =
+                        fork.anonymize("john@example.com", &AnonymizationType::FakeEmail, true);
+                    let new =
+                        fork.anonymize("jane@example.com", &AnonymizationType::FakeEmail, true);
+                    (known, new)
+                })
+            })
+            .collect();
+        let results: Vec<(String, String)> =
+            handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        for (known, new) in &results {
+            assert_eq!(known, &email);
+            assert_eq!(new, &results[0].1);
+        }
+        assert!(anonymizer.is_cached("jane@example.com"));
+    }
+
+    #[test]
+    fn test_anonymizer_fake_phone_keep_format() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.set_keep_phone_format(true);
+
+        for original in [
+            "(555) 123-4567",
+            "555.123.4567",
+            "+1 555 123 4567",
+            "5551234567",
+        ] {
+            let phone = anonymizer.anonymize(original, &AnonymizationType::FakePhone, true);
+            assert_ne!(phone, original);
+            assert_eq!(phone.len(), original.len());
+            for (o, p) in original.chars().zip(phone.chars()) {
+                assert_eq!(o.is_ascii_digit(), p.is_ascii_digit());
+                if !o.is_ascii_digit() {
+                    assert_eq!(o, p);
+                }
+            }
+            assert_eq!(
+                anonymizer.anonymize(original, &AnonymizationType::FakePhone, true),
+                phone
+            );
+        }
+    }
+
+    #[test]
+    fn test_anonymizer_first_and_last_name() {
+        let mut anonymizer = Anonymizer::new();
+
+        let first1 = anonymizer.anonymize("John", &AnonymizationType::FakeFirstName, true);
+        let first2 = anonymizer.anonymize("John", &AnonymizationType::FakeFirstName, true);
+        assert_eq!(first1, first2);
+        assert!(!first1.contains(' '));
+
+        let last = anonymizer.anonymize("Doe", &AnonymizationType::FakeLastName, true);
+        assert!(!last.is_empty());
+    }
+
+    #[test]
+    fn test_anonymizer_persona_for() {
+        let mut anonymizer = Anonymizer::with_seed(3);
+        let persona = anonymizer.persona_for("John Smith").clone();
+
+        // The full name splits into the first and last name of the same persona
+        assert_eq!(
+            persona.name,
+            format!("{} {}", persona.first_name, persona.last_name)
+        );
+        assert_eq!(anonymizer.persona_for("John Smith"), &persona);
+        assert_ne!(anonymizer.persona_for("Jane Doe"), &persona);
+
+        let first = anonymizer
+            .anonymize_persona("John", &AnonymizationType::FakeFirstName, "John Smith")
+            .unwrap();
+        assert_eq!(first.value, persona.first_name);
+        assert!(first.was_cached);
+        assert_eq!(
+            anonymizer.anonymize_persona("x", &AnonymizationType::Hash, "John Smith"),
+            None
+        );
+
+        // Forks share personas
+        let mut fork = anonymizer.fork();
+        assert_eq!(fork.persona_for("John Smith"), &persona);
+    }
+
+    #[test]
+    fn test_anonymizer_rule_locales() {
+        let mut anonymizer = Anonymizer::with_seed(42);
+        let customer = anonymizer
+            .anonymize_localized(
+                "Anna Schmidt",
+                &AnonymizationType::FakeName,
+                true,
+                None,
+                None,
+            )
+            .value;
+        let supplier = anonymizer
+            .anonymize_localized(
+                "Kenji Sato",
+                &AnonymizationType::FakeName,
+                true,
+                None,
+                Some(Locale::JaJp),
+            )
+            .value;
+
+        assert!(customer.is_ascii());
+        assert!(!supplier.is_ascii());
+        // The override lasts for the one call
+        let next = anonymizer.anonymize("Max Mustermann", &AnonymizationType::FakeName, true);
+        assert!(next.is_ascii());
+    }
+
+    #[test]
+    fn test_anonymizer_with_locale() {
+        let mut anonymizer = Anonymizer::with_locale(Locale::JaJp);
+        let name = anonymizer.anonymize("John Smith", &AnonymizationType::FakeName, false);
+
+        // Japanese names are not ASCII
+        assert!(!name.is_ascii());
+    }
+
+    #[test]
+    fn test_anonymizer_address_components() {
+        let mut anonymizer = Anonymizer::new();
+
+        let address = anonymizer.anonymize(
+            "1 Real Road, Springfield, IL 62701",
+            &AnonymizationType::FakeAddress,
+            true,
+        );
+        assert_eq!(address.matches(", ").count(), 2);
+
+        let street =
+            anonymizer.anonymize("1 Real Road", &AnonymizationType::FakeStreetAddress, true);
+        assert!(street.starts_with(|c: char| c.is_ascii_digit()));
+        assert_eq!(
+            anonymizer.anonymize("1 Real Road", &AnonymizationType::FakeStreetAddress, true),
+            street
+        );
+
+        let state = anonymizer.anonymize("IL", &AnonymizationType::FakeState, false);
+        assert_eq!(state.len(), 2);
+
+        let city = anonymizer.anonymize("Springfield", &AnonymizationType::FakeCity, false);
+        let zip = anonymizer.anonymize("62701", &AnonymizationType::FakeZip, false);
+        assert!(!city.is_empty());
+        assert!(zip.chars().any(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_anonymizer_fake_company() {
+        let mut anonymizer = Anonymizer::new();
+
+        let company1 = anonymizer.anonymize("Acme Corp", &AnonymizationType::FakeCompany, true);
+        let company2 = anonymizer.anonymize("Acme Corp", &AnonymizationType::FakeCompany, true);
+        assert_eq!(company1, company2);
+        assert_ne!(company1, "Acme Corp");
+    }
+
+    #[test]
+    fn test_anonymizer_fake_credit_card() {
+        let mut anonymizer = Anonymizer::new();
+
+        let card = anonymizer.anonymize(
+            "4532-1234-5678-9010",
+            &AnonymizationType::FakeCreditCard,
+            true,
+        );
+        assert!(card.starts_with("4242-"));
+        assert_eq!(card.len(), 19);
+        assert_eq!(card.matches('-').count(), 3);
+        assert!(luhn_valid(&card));
+
+        let again = anonymizer.anonymize(
+            "4532-1234-5678-9010",
+            &AnonymizationType::FakeCreditCard,
+            true,
+        );
+        assert_eq!(card, again);
+
+        let spaced = anonymizer.anonymize(
+            "4532 1234 5678 9011",
+            &AnonymizationType::FakeCreditCard,
+            false,
+        );
+        assert_eq!(spaced.matches(' ').count(), 3);
+        assert!(luhn_valid(&spaced));
+    }
+
+    #[test]
+    fn test_anonymizer_scramble() {
+        let mut anonymizer = Anonymizer::new();
+        let note = "Called John Smith, re: order #4521.";
+
+        let scrambled = anonymizer.anonymize(note, &AnonymizationType::Scramble, true);
+        assert_ne!(scrambled, note);
+        assert_eq!(scrambled.chars().count(), note.chars().count());
+        for (original, replaced) in note.chars().zip(scrambled.chars()) {
+            assert_eq!(original.is_alphanumeric(), replaced.is_alphanumeric());
+            if !original.is_alphanumeric() {
+                assert_eq!(original, replaced);
+            }
+        }
+
+        let again = anonymizer.anonymize(note, &AnonymizationType::Scramble, true);
+        assert_eq!(scrambled, again);
+
+        let accented = anonymizer.anonymize("Zoë", &AnonymizationType::Scramble, false);
+        assert_eq!(accented.chars().count(), 3);
+    }
+
+    #[test]
+    fn test_anonymizer_fake_uuid() {
+        let mut anonymizer = Anonymizer::new();
+        let original = "550e8400-e29b-41d4-a716-446655440000";
+
+        let uuid = anonymizer.anonymize(original, &AnonymizationType::FakeUuid, true);
+        assert_ne!(uuid, original);
+        assert_eq!(uuid.len(), 36);
+        let groups: Vec<&str> = uuid.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('4'));
+        assert!(matches!(
+            groups[3].chars().next(),
+            Some('8' | '9' | 'a' | 'b')
+        ));
+
+        // Foreign keys pointing at the same id still join
+        let again = anonymizer.anonymize(original, &AnonymizationType::FakeUuid, true);
+        assert_eq!(uuid, again);
+
+        let from_text = anonymizer.anonymize("not-a-uuid", &AnonymizationType::FakeUuid, true);
+        assert_eq!(from_text.len(), 36);
+    }
+
+    #[test]
+    fn test_luhn_valid() {
+        assert!(luhn_valid("4242424242424242"));
+        assert!(luhn_valid("4111-1111-1111-1111"));
+        assert!(!luhn_valid("4111-1111-1111-1112"));
+        assert!(!luhn_valid("5"));
+    }
+
+    #[test]
+    fn test_anonymizer_mask_credit_card() {
+        let mut anonymizer = Anonymizer::new();
+        let masked = anonymizer.anonymize(
+            "4532-1234-5678-9010",
+            &AnonymizationType::MaskCreditCard,
+            false,
+        );
+        assert_eq!(masked, "****-****-****-9010");
+    }
+
+    #[test]
+    fn test_anonymizer_mask_credit_card_multibyte_tail() {
+        let mut anonymizer = Anonymizer::new();
+        // The last 4 bytes start inside the `é`
+        let masked = anonymizer.anonymize("1234é567", &AnonymizationType::MaskCreditCard, false);
+        assert_eq!(masked, "****-****-****-é567");
+        let masked = anonymizer.anonymize("１２３４５", &AnonymizationType::MaskCreditCard, false);
+        assert_eq!(masked, "****-****-****-２３４５");
+        assert_eq!(
+            anonymizer.anonymize("12é", &AnonymizationType::MaskCreditCard, false),
+            "****"
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_mask_email() {
+        let mut anonymizer = Anonymizer::new();
+
+        let masked = anonymizer.anonymize("john.doe@acme.com", &AnonymizationType::MaskEmail, true);
+        assert_eq!(masked, "****@acme.com");
+
+        let tagged = anonymizer.anonymize("a+b@x.com", &AnonymizationType::MaskEmail, true);
+        assert_eq!(tagged, "****@x.com");
+
+        let invalid = anonymizer.anonymize("not-an-email", &AnonymizationType::MaskEmail, true);
+        assert_eq!(invalid, "****");
+
+        anonymizer.set_mask_char('#');
+        let masked = anonymizer.anonymize("john.doe@acme.com", &AnonymizationType::MaskEmail, true);
+        assert_eq!(masked, "####@acme.com");
+    }
+
+    #[test]
+    fn test_anonymizer_mask_ssn() {
+        let mut anonymizer = Anonymizer::new();
+        let masked = anonymizer.anonymize("123-45-6789", &AnonymizationType::MaskSSN, false);
+        assert_eq!(masked, "***-**-****");
+    }
+
+    #[test]
+    fn test_mask_char() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.set_mask_char('X');
+        let masked = anonymizer.anonymize(
+            "4532-1234-5678-9010",
+            &AnonymizationType::MaskCreditCard,
+            false,
+        );
+        assert_eq!(masked, "XXXX-XXXX-XXXX-9010");
+        let masked = anonymizer.anonymize("123-45-6789", &AnonymizationType::MaskSSN, false);
+        assert_eq!(masked, "XXX-XX-XXXX");
+    }
+
+    #[test]
+    fn test_anonymizer_mask_keep() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  users.pin: { method: mask, keep_last: 2 }\n  users.note: mask\n",
+        )
+        .unwrap();
+        let keep_last = config.custom_rules["users.pin"].anon_type().unwrap();
+        assert_eq!(
+            keep_last,
+            AnonymizationType::Mask {
+                keep_first: 0,
+                keep_last: 2
+            }
+        );
+
+        let mut anonymizer = Anonymizer::new();
+        assert_eq!(anonymizer.anonymize("1234", &keep_last, true), "**34");
+        assert_eq!(
+            anonymizer.anonymize("long account number", &keep_last, true),
+            "*****************er"
+        );
+        // Nothing would be hidden, so everything is
+        assert_eq!(anonymizer.anonymize("12", &keep_last, true), "**");
+        assert_eq!(anonymizer.anonymize("7", &keep_last, true), "*");
+
+        let both = AnonymizationType::Mask {
+            keep_first: 2,
+            keep_last: 2,
+        };
+        assert_eq!(
+            anonymizer.anonymize("DE89370400", &both, true),
+            "DE******00"
+        );
+        assert_eq!(anonymizer.anonymize("DE891", &both, true), "DE*91");
+        assert_eq!(anonymizer.anonymize("DE89", &both, true), "****");
+        assert_eq!(
+            anonymizer.anonymize("Zoë Müller", &both, true),
+            "Zo******er"
+        );
+
+        // Credit card style, and a plain `mask` hides everything
+        let card = AnonymizationType::Mask {
+            keep_first: 0,
+            keep_last: 4,
+        };
+        assert_eq!(
+            anonymizer.anonymize("4532123456789010", &card, true),
+            "************9010"
+        );
+        let all = config.custom_rules["users.note"].anon_type().unwrap();
+        anonymizer.set_mask_char('X');
+        assert_eq!(anonymizer.anonymize("secret", &all, true), "XXXXXX");
+    }
+
+    #[test]
+    fn test_anonymizer_redact() {
+        let mut anonymizer = Anonymizer::new();
+        let redacted =
+            anonymizer.anonymize_detailed("jane@x.com", &AnonymizationType::Redact, true);
+        assert_eq!(redacted.value, "[REDACTED]");
+        assert!(redacted.changed);
+        assert!(!redacted.is_null);
+
+        anonymizer.set_redact_label("<removed>");
+        assert_eq!(
+            anonymizer.anonymize("555-1234", &AnonymizationType::Redact, false),
+            "<removed>"
+        );
+        assert_eq!(
+            AnonymizationType::from_str("redact"),
+            Some(AnonymizationType::Redact)
+        );
+        assert_eq!(Config::default().redact_label, "[REDACTED]");
+    }
+
+    #[test]
+    fn test_anonymizer_hash_salt() {
+        let mut plain = Anonymizer::new();
+        let unsalted = plain.anonymize("secret", &AnonymizationType::Hash, false);
+        assert_eq!(
+            unsalted,
+            "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+        );
+
+        let mut first = Anonymizer::new();
+        first.set_hash_salt("dump-a");
+        let mut second = Anonymizer::new();
+        second.set_hash_salt("dump-b");
+
+        let hash_a = first.anonymize("secret", &AnonymizationType::Hash, false);
+        let hash_b = second.anonymize("secret", &AnonymizationType::Hash, false);
+        assert_ne!(hash_a, hash_b);
+        assert_ne!(hash_a, unsalted);
+        assert_eq!(
+            hash_a,
+            first.anonymize("secret", &AnonymizationType::Hash, false)
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_max_cache_entries() {
+        let mut anonymizer = Anonymizer::with_seed(3);
+        anonymizer.set_max_cache_entries(2);
+        let mut fork = anonymizer.fork();
+
+        let first = anonymizer.anonymize("a@x.com", &AnonymizationType::FakeEmail, true);
+        fork.anonymize("b@x.com", &AnonymizationType::FakeEmail, true);
+        assert_eq!(
+            anonymizer.anonymize("a@x.com", &AnonymizationType::FakeEmail, true),
+            first
+        );
+        // The fork shares the cap, so `b@x.com` is the least recently used
+        fork.anonymize("c@x.com", &AnonymizationType::FakeEmail, true);
+
+        assert_eq!(anonymizer.cache_len(), 2);
+        assert_eq!(anonymizer.cache_evictions(), 1);
+        assert!(anonymizer.is_cached("a@x.com"));
+        assert!(!anonymizer.is_cached("b@x.com"));
+    }
+
+    /// Random strings mixing ASCII, separators, multibyte characters and any
+    /// other Unicode scalar, plus inputs near the edges of each method
+    fn arbitrary_strings() -> Vec<String> {
+        const CHARS: &[char] = &[
+            'a', 'Z', '0', '9', '-', '.', ' ', '@', '\'', '\\', 'é', 'ß', '€', '日', '🦀',
+            '\u{301}', '\u{feff}', '\0',
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut strings: Vec<String> = (0..300)
+            .map(|_| {
+                let len = rng.gen_range(0..12);
+                (0..len)
+                    .map(|_| match rng.gen_bool(0.2) {
+                        true => rng.gen::<char>(),
+                        false => *CHARS.choose(&mut rng).unwrap(),
+                    })
+                    .collect()
+            })
+            .collect();
+        strings.extend(
+            [
+                "1é234",
+                "€€€€€",
+                "1234🦀",
+                "-",
+                "-.5",
+                "1.",
+                "2024-02-30 10:00:00",
+                "9999-12-31",
+                "{\"a\": [1, {\"b\": \"é\"}]}",
+                "AAAAAAAAAAAAAAAA",
+            ]
+            .map(str::to_string),
+        );
+        strings.extend((0..20).map(|k| (i128::MAX - k).to_string()));
+        strings.extend((0..20).map(|k| format!("-{}", i128::MAX - k)));
+        strings
+    }
+
+    #[test]
+    fn test_anonymize_never_panics() {
+        let mut anonymizer = Anonymizer::with_seed(5);
+        anonymizer.set_key([7; 32]);
+        anonymizer.set_hmac_key("key");
+        anonymizer.set_perturb_percent(100.0);
+        let mut types = AnonymizationType::ALL.to_vec();
+        types.extend([
+            AnonymizationType::Mask {
+                keep_first: 2,
+                keep_last: 3,
+            },
+            AnonymizationType::Mask {
+                keep_first: usize::MAX,
+                keep_last: usize::MAX,
+            },
+            AnonymizationType::Constant("x".to_string()),
+            AnonymizationType::Custom("unregistered".to_string()),
+            AnonymizationType::Pattern("[a-z]{3}\\d+".to_string()),
+            AnonymizationType::Json(vec![
+                ("a[]".to_string(), AnonymizationType::MaskCreditCard),
+                ("a[].b".to_string(), AnonymizationType::Perturb),
+            ]),
+        ]);
+
+        for value in arbitrary_strings() {
+            for anon_type in &types {
+                anonymizer.anonymize(&value, anon_type, true);
+                anonymizer.anonymize(&value, anon_type, false);
+            }
+            anonymizer.shift_date(&value, &value);
+            anonymizer.detokenize(&value);
+        }
+    }
+
+    #[test]
+    fn test_anonymizer_hmac() {
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.set_hmac_key("key");
+        assert_eq!(
+            anonymizer.anonymize(
+                "The quick brown fox jumps over the lazy dog",
+                &AnonymizationType::Hmac,
+                false
+            ),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+
+        let mut first = Anonymizer::new();
+        first.set_hmac_key("key-a");
+        let mut second = Anonymizer::new();
+        second.set_hmac_key("key-b");
+
+        let mac = first.anonymize("secret", &AnonymizationType::Hmac, false);
+        assert_eq!(mac.len(), 64);
+        assert_eq!(
+            mac,
+            first.anonymize("secret", &AnonymizationType::Hmac, false)
+        );
+        let mut again = Anonymizer::with_seed(7);
+        again.set_hmac_key("key-a");
+        assert_eq!(
+            mac,
+            again.anonymize("secret", &AnonymizationType::Hmac, false)
+        );
+        assert_ne!(
+            mac,
+            second.anonymize("secret", &AnonymizationType::Hmac, false)
+        );
+
+        first.set_hash_length(16);
+        assert_eq!(
+            first.anonymize("secret", &AnonymizationType::Hmac, false),
+            mac[..16]
+        );
+
+        // Without a key it is a plain hash, never the value itself
+        let mut unkeyed = Anonymizer::new();
+        assert_eq!(
+            unkeyed.anonymize("secret", &AnonymizationType::Hmac, false),
+            unkeyed.anonymize("secret", &AnonymizationType::Hash, false)
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_hash_preserve_length() {
+        let mut anonymizer = Anonymizer::new();
+        let full = anonymizer.anonymize("secret", &AnonymizationType::Hash, false);
+        anonymizer.set_hash_length(16);
+        anonymizer.set_hash_preserve_length(true);
+
+        // Short inputs are cut to their length, winning over hash_length
+        let short = anonymizer.anonymize("secret", &AnonymizationType::Hash, false);
+        assert_eq!(short, full[..6]);
+        assert_eq!(
+            anonymizer
+                .anonymize("día", &AnonymizationType::Hash, false)
+                .len(),
+            3
+        );
+
+        // Long inputs are padded past the 64-char digest, the same way every time
+        let long_value = "x".repeat(150);
+        let long = anonymizer.anonymize(&long_value, &AnonymizationType::Hash, false);
+        assert_eq!(long.len(), 150);
+        assert!(long.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            long,
+            anonymizer.anonymize(&long_value, &AnonymizationType::Hash, false)
+        );
+
+        anonymizer.set_hmac_key("key");
+        assert_eq!(
+            anonymizer
+                .anonymize(&long_value, &AnonymizationType::Hmac, false)
+                .len(),
+            150
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_hash_length() {
+        let mut anonymizer = Anonymizer::new();
+        let full = anonymizer.anonymize("secret", &AnonymizationType::Hash, false);
+
+        anonymizer.set_hash_length(16);
+        let short = anonymizer.anonymize("secret", &AnonymizationType::Hash, false);
+        assert_eq!(short.len(), 16);
+        assert!(full.starts_with(&short));
+
+        anonymizer.set_hash_length(2);
+        let clamped = anonymizer.anonymize("secret", &AnonymizationType::Hash, false);
+        assert_eq!(clamped.len(), MIN_HASH_LENGTH);
+
+        anonymizer.set_hash_length(100);
+        assert_eq!(
+            anonymizer.anonymize("secret", &AnonymizationType::Hash, false),
+            full
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_tokenize_round_trip() {
+        let mut anonymizer = Anonymizer::with_key([7u8; 32]);
+
+        let token1 = anonymizer.anonymize("john@example.com", &AnonymizationType::Tokenize, false);
+        let token2 = anonymizer.anonymize("john@example.com", &AnonymizationType::Tokenize, false);
+        assert_eq!(token1, token2);
+        assert_ne!(token1, "john@example.com");
+        assert_eq!(
+            anonymizer.detokenize(&token1),
+            Some("john@example.com".to_string())
+        );
+
+        let other = Anonymizer::with_key([8u8; 32]);
+        assert_eq!(other.detokenize(&token1), None);
+    }
+
+    #[test]
+    fn test_anonymizer_fake_ip() {
+        let mut anonymizer = Anonymizer::with_seed(1);
+        let ip = anonymizer.anonymize("192.168.1.55", &AnonymizationType::FakeIp, true);
+        assert!(ip.parse::<Ipv4Addr>().is_ok());
+        assert_ne!(ip, "192.168.1.55");
+        assert_eq!(
+            anonymizer.anonymize("192.168.1.55", &AnonymizationType::FakeIp, true),
+            ip
+        );
+
+        let (network, prefix_len) = parse_ipv4_range("10.20.0.0/16").unwrap();
+        anonymizer.set_fake_ip_range(network, prefix_len);
+        for i in 0..200 {
+            let original = format!("172.16.0.{}", i);
+            let ip: Ipv4Addr = anonymizer
+                .anonymize(&original, &AnonymizationType::FakeIp, false)
+                .parse()
+                .unwrap();
+            let [a, b, c, d] = ip.octets();
+            assert_eq!((a, b), (10, 20));
+            assert!((c, d) != (0, 0) && (c, d) != (255, 255));
+        }
+
+        anonymizer.set_fake_ip_range(Ipv4Addr::new(10, 0, 0, 7), 32);
+        assert_eq!(
+            anonymizer.anonymize("1.2.3.4", &AnonymizationType::FakeIp, false),
+            "10.0.0.7"
+        );
+        anonymizer.set_fake_ip_range(Ipv4Addr::new(10, 0, 0, 6), 31);
+        let pair = anonymizer.anonymize("1.2.3.4", &AnonymizationType::FakeIp, false);
+        assert!(pair == "10.0.0.6" || pair == "10.0.0.7");
+    }
+
+    #[test]
+    fn test_anonymizer_fake_gender() {
+        let mut anonymizer = Anonymizer::with_seed(3);
+        let mut fakes = HashSet::new();
+        for original in ["male", "female", "nonbinary", "unknown", "m", "f"] {
+            let fake = anonymizer.anonymize(original, &AnonymizationType::FakeGender, true);
+            assert!(["M", "F", "X"].contains(&fake.as_str()));
+            // The same original always maps to the same value
+            for _ in 0..10 {
+                assert_eq!(
+                    anonymizer.anonymize(original, &AnonymizationType::FakeGender, true),
+                    fake
+                );
+            }
+            fakes.insert(fake);
+        }
+        assert!(fakes.len() > 1);
+
+        // An empty list keeps the values already set
+        anonymizer.set_gender_values(vec![]);
+        let fake = anonymizer.anonymize("other", &AnonymizationType::FakeGender, false);
+        assert!(["M", "F", "X"].contains(&fake.as_str()));
+
+        anonymizer.set_gender_values(vec!["W".to_string(), "D".to_string()]);
+        let fake = anonymizer.anonymize("other", &AnonymizationType::FakeGender, false);
+        assert!(fake == "W" || fake == "D");
+
+        let config: Config = serde_yaml::from_str("gender_values: [a, b]").unwrap();
+        assert_eq!(config.gender_values, ["a", "b"]);
+        assert_eq!(Config::default().gender_values, ["M", "F", "X"]);
+    }
+
+    #[test]
+    fn test_parse_ipv4_range() {
+        assert_eq!(
+            parse_ipv4_range("10.1.2.3/8"),
+            Some((Ipv4Addr::new(10, 0, 0, 0), 8))
+        );
+        assert_eq!(
+            parse_ipv4_range(" 192.168.1.9 "),
+            Some((Ipv4Addr::new(192, 168, 1, 9), 32))
+        );
+        assert_eq!(
+            parse_ipv4_range("0.0.0.0/0"),
+            Some((Ipv4Addr::new(0, 0, 0, 0), 0))
+        );
+        assert_eq!(parse_ipv4_range("10.0.0.0/33"), None);
+        assert_eq!(parse_ipv4_range("10.0.0/8"), None);
+        assert_eq!(parse_ipv4_range("::1/64"), None);
+    }
+
+    #[test]
+    fn test_decode_key() {
+        let hex = "00".repeat(31) + "ff";
+        let key = decode_key(&hex).unwrap();
+        assert_eq!(key[31], 0xff);
+
+        assert_eq!(decode_key(&BASE64.encode([1u8; 32])), Some([1u8; 32]));
+        assert_eq!(decode_key("too-short"), None);
+    }
+
+    #[test]
+    fn test_anonymizer_mask_iban() {
+        let mut anonymizer = Anonymizer::new();
+
+        let masked = anonymizer.anonymize(
+            "DE89370400440532013000",
+            &AnonymizationType::MaskIban,
+            false,
+        );
+        assert_eq!(masked, "DE89******************");
+
+        let spaced = anonymizer.anonymize(
+            "DE89 3704 0044 0532 0130 00",
+            &AnonymizationType::MaskIban,
+            false,
+        );
+        assert_eq!(spaced, "DE89 **** **** **** **** **");
+
+        let short = anonymizer.anonymize("DE89", &AnonymizationType::MaskIban, false);
+        assert_eq!(short, "****");
+
+        anonymizer.set_mask_char('X');
+        let spaced = anonymizer.anonymize(
+            "DE89 3704 0044 0532 0130 00",
+            &AnonymizationType::MaskIban,
+            false,
+        );
+        assert_eq!(spaced, "DE89 XXXX XXXX XXXX XXXX XX");
+    }
+
+    #[test]
+    fn test_anonymizer_mask_middle() {
+        let mut anonymizer = Anonymizer::new();
+        let mut mask =
+            |value: &str| anonymizer.anonymize(value, &AnonymizationType::MaskMiddle, true);
+
+        assert_eq!(mask("johndoe"), "j*****e");
+        assert_eq!(mask("abc"), "a*c");
+        assert_eq!(mask("Zoë_Ünal"), "Z******l");
+
+        // Too short to keep anything
+        assert_eq!(mask("ab"), "**");
+        assert_eq!(mask("a"), "*");
+        assert_eq!(mask(""), "");
+
+        anonymizer.set_mask_char('x');
+        assert_eq!(
+            anonymizer.anonymize("johndoe", &AnonymizationType::MaskMiddle, true),
+            "jxxxxxe"
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_mask_ip() {
+        let mut anonymizer = Anonymizer::new();
+        let mut mask = |value: &str| anonymizer.anonymize(value, &AnonymizationType::MaskIp, true);
+
+        assert_eq!(mask("192.168.1.55"), "192.168.1.0");
+        assert_eq!(mask("10.0.0.0"), "10.0.0.0");
+        assert_eq!(
+            mask("2001:db8:85a3:8d3:1319:8a2e:370:7348"),
+            "2001:db8:85a3::"
+        );
+        assert_eq!(mask("fe80::1"), "fe80::");
+        assert_eq!(mask("::ffff:192.168.1.55"), "::ffff:192.168.1.0");
+
+        // Anything else passes through
+        assert_eq!(mask("192.168.1.256"), "192.168.1.256");
+        assert_eq!(mask("not an ip"), "not an ip");
+        assert_eq!(mask("192.168.1.0/24"), "192.168.1.0/24");
+    }
+
+    #[test]
+    fn test_anonymizer_stats() {
+        let mut anonymizer = Anonymizer::with_seed(1);
+        for email in ["a@x.com", "b@x.com", "a@x.com"] {
+            anonymizer.anonymize(email, &AnonymizationType::FakeEmail, true);
+        }
+        anonymizer.anonymize("id-1", &AnonymizationType::Skip, true);
+        anonymizer.shift_date("2024-03-10", "user-1");
+
+        let stats = anonymizer.stats();
+        assert_eq!(
+            stats[&AnonymizationType::FakeEmail],
+            TypeStats {
+                invocations: 3,
+                distinct: 2
+            }
+        );
+        assert_eq!(stats[&AnonymizationType::Skip].invocations, 1);
+        assert_eq!(stats[&AnonymizationType::ShiftDate].distinct, 1);
+        assert!(!stats.contains_key(&AnonymizationType::Hash));
+
+        // A fork counts on its own until merged back
+        let mut fork = anonymizer.fork();
+        assert!(fork.stats().is_empty());
+        fork.anonymize("c@x.com", &AnonymizationType::FakeEmail, true);
+        fork.anonymize("a@x.com", &AnonymizationType::FakeEmail, true);
+        anonymizer.merge_stats(&fork);
+        assert_eq!(
+            anonymizer.stats()[&AnonymizationType::FakeEmail],
+            TypeStats {
+                invocations: 5,
+                distinct: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_shift_date() {
+        let mut anonymizer = Anonymizer::with_seed(1);
+
+        let created = anonymizer.shift_date("2024-03-10", "user-1");
+        let shipped = anonymizer.shift_date("2024-03-15 08:30:00", "user-1");
+        assert_ne!(created, "2024-03-10");
+        assert!(shipped.ends_with(" 08:30:00"));
+
+        // Same entity, same offset: the 5 day interval survives
+        assert_eq!(
+            date::shift_days(&created, 5).unwrap(),
+            shipped.replace(" 08:30:00", "")
+        );
+
+        assert_eq!(
+            anonymizer.anonymize("not-a-date", &AnonymizationType::ShiftDate, true),
+            "not-a-date"
+        );
+    }
+
+    #[test]
+    fn test_anonymizer_perturb() {
+        let mut anonymizer = Anonymizer::with_seed(3);
+        anonymizer.set_perturb_percent(10.0);
+
+        for value in ["52000", "-52000", "37", "1234.56", "0.05", "999999999999"] {
+            let perturbed = anonymizer.anonymize(value, &AnonymizationType::Perturb, false);
+            let original: f64 = value.parse().unwrap();
+            let moved: f64 = perturbed.parse().unwrap();
+            assert!(
+                (moved - original).abs() <= original.abs() * 0.1,
+                "{value} -> {perturbed}"
+            );
+            // Decimal places are kept
+            assert_eq!(
+                perturbed.split_once('.').map(|(_, f)| f.len()),
+                value.split_once('.').map(|(_, f)| f.len())
+            );
+        }
+
+        for value in ["n/a", "1e5", "12.", "-", "1,000"] {
+            assert_eq!(
+                anonymizer.anonymize(value, &AnonymizationType::Perturb, true),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_anonymizer_perturb_is_stable() {
+        let mut anonymizer = Anonymizer::new();
+        let first = anonymizer.anonymize("85000", &AnonymizationType::Perturb, true);
+        assert_ne!(first, "85000");
+        assert_eq!(
+            anonymizer.anonymize("85000", &AnonymizationType::Perturb, true),
+            first
+        );
+        assert_eq!(
+            anonymizer.anonymize("85000", &AnonymizationType::Perturb, false),
+            first
+        );
+
+        anonymizer.set_perturb_percent(0.0);
+        assert_eq!(
+            anonymizer.anonymize("85000", &AnonymizationType::Perturb, true),
+            "85000"
+        );
+    }
+
+    #[test]
+    fn test_config_default() {
+        let config = Config::default();
+        assert!(!config.auto_detect); // Free version
+        assert!(config.preserve_relationships);
+        assert_eq!(config.custom_rules.len(), 0);
+        assert_eq!(config.date_shift_max_days, 30);
+        assert_eq!(config.perturb_percent, 10.0);
+        assert!(!config.fake_email_keep_domain);
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let mut config = Config::default();
+        config
+            .custom_rules
+            .insert("users.email".to_string(), "fake_email".into());
+        assert_eq!(config.validate(), Ok(()));
+
+        config
+            .custom_rules
+            .insert("users.phone".to_string(), "fak_phone".into());
+        config
+            .custom_rules
+            .insert("orders.card".to_string(), "mask_card".into());
+        assert_eq!(
+            config.validate(),
+            Err(vec!["orders.card".to_string(), "users.phone".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_config_method_column_lists() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  fake_email: [users.email, contacts.email]\n  mask_ssn:\n    - users.ssn\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.custom_rules.len(), 3);
+        assert_eq!(config.custom_rules["users.email"].method(), "fake_email");
+        assert_eq!(config.custom_rules["contacts.email"].method(), "fake_email");
+        assert_eq!(config.custom_rules["users.ssn"].method(), "mask_ssn");
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_config_mixed_rule_forms() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  users.phone: { method: fake_phone, preserve: false }\n  fake_email: [users.email, contacts.email]\n  users.name: fake_name\n",
+        )
+        .unwrap();
+        let rules = RuleSet::from_config(&config);
+
+        assert_eq!(rules.len(), 4);
+        assert_eq!(
+            rules.matches("contacts", "email"),
+            Some(&AnonymizationType::FakeEmail)
+        );
+        assert_eq!(rules.find("users", "phone").unwrap().preserve, Some(false));
+        assert_eq!(
+            rules.matches("users", "name"),
+            Some(&AnonymizationType::FakeName)
+        );
+
+        let duplicate = serde_yaml::from_str::<Config>(
+            "custom_rules:\n  users.email: hash\n  fake_email: [users.email]\n",
+        );
+        assert!(duplicate
+            .unwrap_err()
+            .to_string()
+            .contains("users.email has more than one rule"));
+    }
+
+    #[test]
+    fn test_config_rule_forms() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  users.email: fake_email\n  users.phone: { method: fake_phone, preserve: false }\n  users.name:\n    method: fake_name\n    group: people\n",
+        )
+        .unwrap();
+
+        let email = &config.custom_rules["users.email"];
+        assert_eq!(email, &RuleConfig::Method("fake_email".to_string()));
+        assert_eq!(email.method(), "fake_email");
+        assert_eq!(email.preserve(), None);
+
+        let phone = &config.custom_rules["users.phone"];
+        assert_eq!(phone.method(), "fake_phone");
+        assert_eq!(phone.preserve(), Some(false));
+
+        let name = &config.custom_rules["users.name"];
+        assert_eq!(name.method(), "fake_name");
+        assert_eq!(name.preserve(), None);
+        assert_eq!(name.group(), Some("people"));
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let lookup = |name: &str| (name == "SCRUB_KEY").then(|| "s3cret".to_string());
+
+        assert_eq!(
+            interpolate_with(
+                "encryption_key: ${SCRUB_KEY}\nhash_salt: \"a-${SCRUB_KEY}-b\"\n# salt: ${UNSET}\n",
+                lookup
+            ),
+            Ok("encryption_key: s3cret\nhash_salt: \"a-s3cret-b\"\n# salt: ${UNSET}\n".to_string())
+        );
+        assert_eq!(
+            interpolate_with("hash_salt: ${SCRUB_SALT}", lookup),
+            Err("SCRUB_SALT".to_string())
+        );
+        // Dollar signs without braces are left as they are
+        assert_eq!(
+            interpolate_with("value: \"$2b$12$abc\"", lookup),
+            Ok("value: \"$2b$12$abc\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_config_constant_rule() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  users.password_hash: { method: constant, value: \"XYZ\" }\n  users.pin: constant\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.custom_rules["users.password_hash"].anon_type(),
+            Some(AnonymizationType::Constant("XYZ".to_string()))
+        );
+        // Without a value there is nothing to replace with
+        assert_eq!(config.custom_rules["users.pin"].anon_type(), None);
+        assert_eq!(config.validate(), Err(vec!["users.pin".to_string()]));
+    }
+
+    #[test]
+    fn test_anonymization_type_as_str_round_trip() {
+        for anon_type in AnonymizationType::ALL {
+            assert_eq!(
+                AnonymizationType::from_str(anon_type.as_str()).as_ref(),
+                Some(anon_type)
+            );
+        }
+        assert_eq!(AnonymizationType::MaskSSN.to_string(), "mask_ssn");
+    }
+
+    #[test]
+    fn test_anonymization_type_serde_round_trip() {
+        let mut types = AnonymizationType::ALL.to_vec();
+        types.extend([
+            AnonymizationType::Constant("XYZ".to_string()),
+            AnonymizationType::Custom("account_code".to_string()),
+            AnonymizationType::Pattern(r"E\d{6}".to_string()),
+            AnonymizationType::Mask {
+                keep_first: 1,
+                keep_last: 3,
+            },
+            AnonymizationType::Json(vec![
+                ("a.email".to_string(), AnonymizationType::FakeEmail),
+                ("b".to_string(), AnonymizationType::Hash),
+            ]),
+            AnonymizationType::Template {
+                format: "{first}@example.com".to_string(),
+                from: vec!["first_name".to_string()],
+            },
+            AnonymizationType::Categorical(vec![
+                ("DE".to_string(), 300_000),
+                ("US".to_string(), 700_000),
+            ]),
+        ]);
+        for anon_type in &types {
+            let yaml = serde_yaml::to_string(anon_type).unwrap();
+            assert_eq!(
+                &serde_yaml::from_str::<AnonymizationType>(&yaml).unwrap(),
+                anon_type
+            );
+            let json = serde_json::to_string(anon_type).unwrap();
+            assert_eq!(
+                &serde_json::from_str::<AnonymizationType>(&json).unwrap(),
+                anon_type
+            );
+        }
+
+        assert_eq!(
+            serde_json::to_string(&AnonymizationType::MaskSSN).unwrap(),
+            r#""mask_ssn""#
+        );
+        assert_eq!(
+            serde_json::to_string(&AnonymizationType::Constant("x".to_string())).unwrap(),
+            r#"{"method":"constant","value":"x"}"#
+        );
+        // Aliases are accepted, unknown methods are not
+        assert_eq!(
+            serde_yaml::from_str::<AnonymizationType>("email").unwrap(),
+            AnonymizationType::FakeEmail
+        );
+        let error = serde_yaml::from_str::<AnonymizationType>("fak_email").unwrap_err();
+        assert!(error.to_string().contains("unknown anonymization method"));
+
+        // Usable as a field and map key in caller-defined structs
+        let stats: HashMap<AnonymizationType, usize> =
+            serde_json::from_str(r#"{"fake_email":2,"hash":1}"#).unwrap();
+        assert_eq!(stats[&AnonymizationType::FakeEmail], 2);
+    }
+
+    #[test]
     fn test_anonymization_type_from_str() {
         assert_eq!(
             AnonymizationType::from_str("fake_email"),
@@ -212,6 +4001,61 @@ mod tests {
             AnonymizationType::from_str("mask_ssn"),
             Some(AnonymizationType::MaskSSN)
         );
+        assert_eq!(
+            AnonymizationType::from_str("first_name"),
+            Some(AnonymizationType::FakeFirstName)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("fake_last_name"),
+            Some(AnonymizationType::FakeLastName)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("company"),
+            Some(AnonymizationType::FakeCompany)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("iban"),
+            Some(AnonymizationType::MaskIban)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("zip_code"),
+            Some(AnonymizationType::FakeZip)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("uuid"),
+            Some(AnonymizationType::FakeUuid)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("mask_email"),
+            Some(AnonymizationType::MaskEmail)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("null"),
+            Some(AnonymizationType::Nullify)
+        );
+        assert_eq!(
+            AnonymizationType::from_str("scramble"),
+            Some(AnonymizationType::Scramble)
+        );
         assert_eq!(AnonymizationType::from_str("invalid"), None);
     }
+
+    #[test]
+    fn test_anonymization_type_try_from() {
+        assert_eq!(
+            AnonymizationType::try_from("Fake_Email"),
+            Ok(AnonymizationType::FakeEmail)
+        );
+
+        let error = AnonymizationType::try_from("fak_email").unwrap_err();
+        assert_eq!(error.input, "fak_email");
+        let message = error.to_string();
+        assert!(message.starts_with(
+            "unknown anonymization method \"fak_email\", expected one of: fake_email, fake_name,"
+        ));
+        for anon_type in AnonymizationType::ALL {
+            assert!(message.contains(anon_type.as_str()));
+        }
+        assert!(!message.contains("constant"));
+    }
 }
@@ -0,0 +1,67 @@
+// Locale selection for fake data generators
+// Maps config locale strings onto the locales shipped with the `fake` crate.
+
+/// Locale used by the fake name, phone and address generators
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    FrFr,
+    JaJp,
+    PtBr,
+    ZhCn,
+    ZhTw,
+    ArSa,
+}
+
+impl Locale {
+    /// Parse a locale from a config string such as `"en"`, `"fr"` or `"ja_JP"`
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "en" | "en_us" => Some(Self::En),
+            "fr" | "fr_fr" => Some(Self::FrFr),
+            "ja" | "ja_jp" => Some(Self::JaJp),
+            "pt" | "pt_br" => Some(Self::PtBr),
+            "zh" | "zh_cn" => Some(Self::ZhCn),
+            "zh_tw" => Some(Self::ZhTw),
+            "ar" | "ar_sa" => Some(Self::ArSa),
+            _ => None,
+        }
+    }
+}
+
+/// Run a raw `fake` generator with the locale chosen at runtime
+///
+/// `localized!(locale, name::raw::Name, rng)` expands to a match over every
+/// supported locale, since the `fake` crate selects locales by type.
+macro_rules! localized {
+    ($locale:expr, $($faker:ident)::+, $rng:expr) => {{
+        use fake::faker::$($faker)::+ as Faker;
+        use fake::locales::{AR_SA, EN, FR_FR, JA_JP, PT_BR, ZH_CN, ZH_TW};
+        match $locale {
+            $crate::Locale::En => Faker(EN).fake_with_rng::<String, _>($rng),
+            $crate::Locale::FrFr => Faker(FR_FR).fake_with_rng::<String, _>($rng),
+            $crate::Locale::JaJp => Faker(JA_JP).fake_with_rng::<String, _>($rng),
+            $crate::Locale::PtBr => Faker(PT_BR).fake_with_rng::<String, _>($rng),
+            $crate::Locale::ZhCn => Faker(ZH_CN).fake_with_rng::<String, _>($rng),
+            $crate::Locale::ZhTw => Faker(ZH_TW).fake_with_rng::<String, _>($rng),
+            $crate::Locale::ArSa => Faker(AR_SA).fake_with_rng::<String, _>($rng),
+        }
+    }};
+}
+
+pub(crate) use localized;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_str() {
+        assert_eq!(Locale::from_str("en"), Some(Locale::En));
+        assert_eq!(Locale::from_str("fr_FR"), Some(Locale::FrFr));
+        assert_eq!(Locale::from_str("ja-JP"), Some(Locale::JaJp));
+        assert_eq!(Locale::from_str("de"), None);
+    }
+}
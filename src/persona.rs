@@ -0,0 +1,119 @@
+// Coherent fake identities
+// A persona's name, email and phone are generated together, so columns that
+// describe the same person (full name, first/last name, email) stay consistent.
+
+use crate::detect::reserve_phone;
+use crate::locale::localized;
+use crate::{AnonymizationType, Locale};
+use fake::faker::internet::en::SafeEmail;
+use fake::Fake;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+/// Domains reserved for documentation, so persona emails never reach anyone
+pub(crate) const EMAIL_DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+/// A fake person whose fields all belong together
+#[derive(Debug, Clone, PartialEq)]
+pub struct Persona {
+    /// Full name, made of `first_name` and `last_name`
+    pub name: String,
+    pub first_name: String,
+    pub last_name: String,
+    /// Email derived from the name where the locale allows it
+    pub email: String,
+    pub phone: String,
+}
+
+impl Persona {
+    /// Generate a persona for a locale
+    pub(crate) fn generate(locale: Locale, rng: &mut StdRng) -> Self {
+        let first_name = localized!(locale, name::raw::FirstName, rng);
+        let last_name = localized!(locale, name::raw::LastName, rng);
+
+        // Family name first where that's the convention
+        let name = match locale {
+            Locale::JaJp | Locale::ZhCn | Locale::ZhTw => format!("{}{}", last_name, first_name),
+            _ => format!("{} {}", first_name, last_name),
+        };
+
+        let first = email_part(&first_name);
+        let last = email_part(&last_name);
+        let email = if first.is_empty() || last.is_empty() {
+            SafeEmail().fake_with_rng(rng)
+        } else {
+            let domain = EMAIL_DOMAINS.choose(rng).unwrap();
+            format!("{}.{}@{}", first, last, domain)
+        };
+
+        let phone = localized!(locale, phone_number::raw::PhoneNumber, rng);
+        let phone = reserve_phone(&phone, rng);
+
+        Self {
+            name,
+            first_name,
+            last_name,
+            email,
+            phone,
+        }
+    }
+
+    /// The field of this persona a method would produce, if it has one
+    pub fn field(&self, anon_type: &AnonymizationType) -> Option<&str> {
+        match anon_type {
+            AnonymizationType::FakeName => Some(&self.name),
+            AnonymizationType::FakeFirstName => Some(&self.first_name),
+            AnonymizationType::FakeLastName => Some(&self.last_name),
+            AnonymizationType::FakeEmail => Some(&self.email),
+            AnonymizationType::FakePhone => Some(&self.phone),
+            _ => None,
+        }
+    }
+}
+
+/// A name lowercased down to ASCII letters and digits for an email local part
+fn email_part(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_persona_fields_agree() {
+        let persona = Persona::generate(Locale::En, &mut StdRng::seed_from_u64(1));
+
+        assert_eq!(
+            persona.name,
+            format!("{} {}", persona.first_name, persona.last_name)
+        );
+        assert!(persona.email.starts_with(&format!(
+            "{}.{}@",
+            email_part(&persona.first_name),
+            email_part(&persona.last_name)
+        )));
+        assert_eq!(
+            persona.field(&AnonymizationType::FakeLastName),
+            Some(persona.last_name.as_str())
+        );
+        assert_eq!(persona.field(&AnonymizationType::Hash), None);
+    }
+
+    #[test]
+    fn test_persona_non_latin_locale() {
+        let persona = Persona::generate(Locale::JaJp, &mut StdRng::seed_from_u64(1));
+
+        assert_eq!(
+            persona.name,
+            format!("{}{}", persona.last_name, persona.first_name)
+        );
+        // Names that can't form an ASCII local part still get a usable email
+        assert!(persona.email.is_ascii());
+        assert!(persona.email.contains('@'));
+    }
+}
@@ -0,0 +1,202 @@
+// PostgreSQL COPY block anonymization
+// pg_dump writes table data as `COPY table (cols) FROM stdin;` followed by
+// tab-separated rows and a terminating `\.` line. Fields use COPY's text
+// escapes, and `\N` is NULL.
+
+use crate::{sql, AnonymizeOutcome};
+use std::io::{self, BufRead, Write};
+
+/// Anonymize the rows of every COPY block in a dump
+///
+/// `anonymize_field` is called with the table, column name, unescaped field
+/// value and the row's first field (a stable key for the row, usually its
+/// id). Returning `Some` replaces the field, with `\N` if the outcome is
+/// NULL. NULL fields (`\N`) and every line outside a COPY block are passed
+/// through unchanged, except that a byte order mark before the first line is
+/// dropped. Returns the number of lines processed.
+pub fn anonymize<R, W, F>(reader: R, mut writer: W, mut anonymize_field: F) -> io::Result<usize>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&str, &str, &str, &str) -> Option<AnonymizeOutcome>,
+{
+    let mut block: Option<(String, Vec<String>)> = None;
+    let mut count = 0;
+
+    for line in reader.lines() {
+        let line = if count == 0 {
+            crate::strip_bom(line?)
+        } else {
+            line?
+        };
+        count += 1;
+
+        let Some((table, columns)) = &block else {
+            block = sql::parse_copy(&line);
+            writeln!(writer, "{}", line)?;
+            continue;
+        };
+        if line == "\\." {
+            block = None;
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let row_key = unescape(fields[0]);
+        let anonymized: Vec<String> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                if *field == "\\N" {
+                    return field.to_string();
+                }
+                columns
+                    .get(i)
+                    .and_then(|column| anonymize_field(table, column, &unescape(field), &row_key))
+                    .map(|outcome| {
+                        if outcome.is_null {
+                            "\\N".to_string()
+                        } else {
+                            escape(&outcome.value)
+                        }
+                    })
+                    .unwrap_or_else(|| field.to_string())
+            })
+            .collect();
+        writeln!(writer, "{}", anonymized.join("\t"))?;
+    }
+
+    Ok(count)
+}
+
+/// Decode COPY text escapes such as `\t`, `\n` and `\\`
+fn unescape(field: &str) -> String {
+    let mut value = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => value.push('\u{8}'),
+            Some('f') => value.push('\u{c}'),
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some('t') => value.push('\t'),
+            Some('v') => value.push('\u{b}'),
+            Some(other) => value.push(other),
+            None => value.push('\\'),
+        }
+    }
+    value
+}
+
+/// Encode a value with COPY text escapes so it stays a single field
+fn escape(value: &str) -> String {
+    let mut field = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => field.push_str("\\\\"),
+            '\n' => field.push_str("\\n"),
+            '\r' => field.push_str("\\r"),
+            '\t' => field.push_str("\\t"),
+            c => field.push(c),
+        }
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replaced(value: String) -> AnonymizeOutcome {
+        AnonymizeOutcome {
+            value,
+            was_cached: false,
+            changed: true,
+            is_null: false,
+        }
+    }
+
+    #[test]
+    fn test_anonymize_copy_block() {
+        let input = "SET client_encoding = 'UTF8';\n\
+            COPY public.users (id, email, note) FROM stdin;\n\
+            1\tjohn@example.com\thello\\tworld\n\
+            2\t\\N\tsay \\\\hi\n\
+            \\.\n\
+            -- 1\tjohn@example.com\n";
+        let mut output = Vec::new();
+
+        let count = anonymize(
+            input.as_bytes(),
+            &mut output,
+            |table, column, value, row_key| match column {
+                "email" => Some(replaced(format!("user{}@{}", row_key, table))),
+                "note" => Some(replaced(format!("[{}]", value))),
+                _ => None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(count, 6);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "SET client_encoding = 'UTF8';\n\
+            COPY public.users (id, email, note) FROM stdin;\n\
+            1\tuser1@public.users\t[hello\\tworld]\n\
+            2\t\\N\t[say \\\\hi]\n\
+            \\.\n\
+            -- 1\tjohn@example.com\n"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_copy_bom_and_crlf() {
+        let input = "\u{feff}COPY users (id, email) FROM stdin;\r\n1\tjohn@example.com\r\n\\.\r\n";
+        let mut output = Vec::new();
+
+        anonymize(input.as_bytes(), &mut output, |_, column, _, _| {
+            (column == "email").then(|| replaced("fake@example.com".to_string()))
+        })
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "COPY users (id, email) FROM stdin;\n1\tfake@example.com\n\\.\n"
+        );
+    }
+
+    #[test]
+    fn test_anonymize_copy_null() {
+        let input = "COPY users (id, email) FROM stdin;\n1\tjohn@example.com\n\\.\n";
+        let mut output = Vec::new();
+
+        anonymize(input.as_bytes(), &mut output, |_, column, _, _| {
+            (column == "email").then(|| AnonymizeOutcome {
+                value: "NULL".to_string(),
+                was_cached: false,
+                changed: true,
+                is_null: true,
+            })
+        })
+        .unwrap();
+
+        // A real NULL, not the text "NULL" or an escaped backslash
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "COPY users (id, email) FROM stdin;\n1\t\\N\n\\.\n"
+        );
+    }
+
+    #[test]
+    fn test_copy_escapes_round_trip() {
+        let value = "tab\there\nback\\slash";
+        assert_eq!(escape(value), "tab\\there\\nback\\\\slash");
+        assert_eq!(unescape(&escape(value)), value);
+        assert_eq!(unescape("\\N"), "N");
+    }
+}
@@ -0,0 +1,566 @@
+// Statement processing
+// `Processor` holds the anonymization state carried across one SQL dump and
+// anonymizes it block by block: parsed statements value by value with the
+// column rules, everything else with the line detectors. The CLI and
+// `AnonymizingReader` both run dumps through it.
+
+use crate::sql::{self, Block};
+use crate::{
+    anonymize_line_matches, anonymize_line_with, anonymize_matches, detect_type_for_column,
+    line_regex, table_matches, AnonymizationType, AnonymizeOutcome, Anonymizer, Detector,
+    DistinctCount, RuleSet,
+};
+use regex::Regex;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+/// How often a column rule fired during a run
+#[derive(Debug, Clone)]
+pub struct RuleCount {
+    pub anon_type: AnonymizationType,
+    pub values: usize,
+    pub cache_hits: usize,
+    /// Distinct originals seen, counted without keeping them
+    pub distinct: DistinctCount,
+}
+
+/// Anonymization state carried across the statements of one dump
+///
+/// The fields are the run's settings and statistics; set the settings
+/// before the first block.
+pub struct Processor {
+    pub anonymizer: Anonymizer,
+    /// Rule keys as line patterns, for lines outside parsed statements
+    pub rules: Vec<(Regex, AnonymizationType)>,
+    /// Per-rule `preserve` overrides, in the same order as `rules`
+    pub rule_preserve: Vec<Option<bool>>,
+    /// Patterns anonymized wherever they match, from `value_rules`
+    pub value_rules: Vec<(Regex, AnonymizationType)>,
+    pub detectors: Vec<Detector>,
+    pub column_rules: RuleSet,
+    /// Give columns without a rule the type their name suggests
+    pub auto_detect: bool,
+    /// Tables whose rows pass through untouched, from `skip_tables`
+    pub skip_tables: Vec<String>,
+    pub preserve_relationships: bool,
+    /// Column order from CREATE TABLE, for INSERTs without a column list
+    pub table_columns: HashMap<String, Vec<String>>,
+    pub rule_counts: BTreeMap<String, RuleCount>,
+    /// Lines outside parsed statements changed by the line detectors
+    pub detector_lines: usize,
+    /// Examples kept per rule for `--sample`; 0 keeps none
+    pub sample_size: usize,
+    /// Masked original and anonymized value of the first distinct values
+    /// each rule changed
+    pub samples: BTreeMap<String, Vec<(String, String)>>,
+}
+
+impl Processor {
+    /// A processor for `column_rules`, preserving relationships and running
+    /// every line detector
+    ///
+    /// The rule keys double as line patterns, each with its rule's
+    /// `preserve` override.
+    pub fn new(column_rules: RuleSet, anonymizer: Anonymizer) -> Self {
+        let (rules, rule_preserve) = column_rules
+            .iter()
+            .filter_map(|rule| {
+                let pattern = line_regex(&rule.key).ok()?;
+                Some(((pattern, rule.anon_type.clone()), rule.preserve))
+            })
+            .unzip();
+        Self {
+            anonymizer,
+            rules,
+            rule_preserve,
+            value_rules: Vec::new(),
+            detectors: Detector::DEFAULT.to_vec(),
+            column_rules,
+            auto_detect: false,
+            skip_tables: Vec::new(),
+            preserve_relationships: true,
+            table_columns: HashMap::new(),
+            rule_counts: BTreeMap::new(),
+            detector_lines: 0,
+            sample_size: 0,
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// A processor for a worker thread, sharing this one's relationship cache
+    pub fn worker(&self) -> Processor {
+        Processor {
+            anonymizer: self.anonymizer.fork(),
+            rules: self.rules.clone(),
+            rule_preserve: self.rule_preserve.clone(),
+            value_rules: self.value_rules.clone(),
+            detectors: self.detectors.clone(),
+            column_rules: self.column_rules.clone(),
+            auto_detect: self.auto_detect,
+            skip_tables: self.skip_tables.clone(),
+            preserve_relationships: self.preserve_relationships,
+            table_columns: self.table_columns.clone(),
+            rule_counts: BTreeMap::new(),
+            detector_lines: 0,
+            sample_size: self.sample_size,
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// Fold a worker's rule statistics into this processor's
+    pub fn merge(&mut self, worker: Processor) {
+        // Columns the worker detected become rules here too, so they are reported
+        for rule in worker.column_rules.iter() {
+            if !self.column_rules.iter().any(|known| known.key == rule.key) {
+                self.column_rules.add(&rule.key, rule.anon_type.clone());
+            }
+        }
+        for (rule, count) in worker.rule_counts {
+            match self.rule_counts.entry(rule) {
+                Entry::Vacant(entry) => {
+                    entry.insert(count);
+                }
+                Entry::Occupied(mut entry) => {
+                    let total = entry.get_mut();
+                    total.values += count.values;
+                    total.cache_hits += count.cache_hits;
+                    total.distinct.merge(&count.distinct);
+                }
+            }
+        }
+        self.detector_lines += worker.detector_lines;
+        for (rule, samples) in worker.samples {
+            let kept = self.samples.entry(rule).or_default();
+            let room = self.sample_size.saturating_sub(kept.len());
+            kept.extend(samples.into_iter().take(room));
+        }
+        self.anonymizer.merge_stats(&worker.anonymizer);
+    }
+
+    /// Anonymize a block of a dump, returning it as it goes in the output,
+    /// with its trailing newline
+    ///
+    /// Comments and stored routine definitions pass through unchanged.
+    pub fn block(&mut self, block: &Block) -> String {
+        match block {
+            Block::Statement(statement) => self.statement(statement),
+            Block::Delimited {
+                statement,
+                delimiter,
+            } => self.delimited(statement, delimiter),
+            Block::Line(line) => self.line(line) + "\n",
+            Block::Comment(line) | Block::Routine(line) => format!("{}\n", line),
+        }
+    }
+
+    /// Anonymize a statement ending in a custom delimiter
+    ///
+    /// The parser only knows `;`, so it sees the statement with `;` in place
+    /// of the delimiter, which is put back afterwards.
+    fn delimited(&mut self, statement: &str, delimiter: &str) -> String {
+        let Some(end) = statement.rfind(delimiter) else {
+            return self.statement(statement);
+        };
+        let anonymized = self.statement(&format!("{};\n", &statement[..end]));
+        let anonymized = anonymized.strip_suffix(";\n").unwrap_or(&anonymized);
+        format!("{}{}", anonymized, &statement[end..])
+    }
+
+    /// Anonymize a complete INSERT or UPDATE statement value by value using column rules
+    ///
+    /// CREATE TABLE statements pass through unchanged but record their column
+    /// order. Statements the parser can't handle fall back to the line detectors.
+    pub fn statement(&mut self, statement: &str) -> String {
+        if let Some((table, columns)) = sql::parse_create_table(statement) {
+            self.table_columns.insert(table, columns);
+            return statement.to_string();
+        }
+        let update = sql::parse_update(statement)
+            .or_else(|| sql::parse_delete(statement).map(sql::Update::from));
+        if let Some(update) = update {
+            return self.update(statement, &update);
+        }
+
+        let Some(insert) = sql::parse_insert(statement) else {
+            return statement
+                .lines()
+                .map(|line| self.line(line) + "\n")
+                .collect();
+        };
+        if self.is_skipped(&insert.table) {
+            return statement.to_string();
+        }
+
+        let columns = if insert.columns.is_empty() {
+            self.table_columns
+                .get(&insert.table)
+                .cloned()
+                .unwrap_or_default()
+        } else {
+            insert.columns.clone()
+        };
+
+        let mut replacements = Vec::new();
+        for row in &insert.rows {
+            let fields: Vec<(&str, &str)> = columns
+                .iter()
+                .map(String::as_str)
+                .zip(row.iter().map(|value| value.text.as_str()))
+                .collect();
+            let values: Vec<(&str, &sql::Value)> =
+                columns.iter().map(String::as_str).zip(row).collect();
+            replacements.extend(self.row(&insert.table, &values, &row[0].text, &fields));
+        }
+
+        sql::replace_spans(statement, replacements)
+    }
+
+    /// Anonymize the literals an UPDATE assigns or compares in its WHERE clause
+    /// (DELETEs come here too, as UPDATEs assigning nothing)
+    ///
+    /// WHERE literals go through the same rules and cache as assignments, so
+    /// `WHERE email = 'x'` still finds the row whose email became x's fake.
+    /// The first WHERE literal (usually the id) keys `shift_date`, like a
+    /// row's first column does for INSERTs.
+    fn update(&mut self, statement: &str, update: &sql::Update) -> String {
+        if self.is_skipped(&update.table) {
+            return statement.to_string();
+        }
+
+        let row_key = update
+            .conditions
+            .first()
+            .map(|(_, value)| value.text.as_str())
+            .unwrap_or_default();
+        let fields: Vec<(&str, &str)> = update
+            .assignments
+            .iter()
+            .map(|(column, value)| (column.as_str(), value.text.as_str()))
+            .collect();
+
+        let values: Vec<(&str, &sql::Value)> = update
+            .assignments
+            .iter()
+            .chain(&update.conditions)
+            .map(|(column, value)| (column.as_str(), value))
+            .collect();
+        let replacements = self.row(&update.table, &values, row_key, &fields);
+        sql::replace_spans(statement, replacements)
+    }
+
+    /// The replacements for a row's literals
+    ///
+    /// `template` columns go last and get the row's anonymized values in
+    /// place of `fields`, so their placeholders are filled with fakes and
+    /// never with the originals.
+    fn row(
+        &mut self,
+        table: &str,
+        values: &[(&str, &sql::Value)],
+        row_key: &str,
+        fields: &[(&str, &str)],
+    ) -> Vec<(Range<usize>, String)> {
+        let (templates, plain): (Vec<_>, Vec<_>) = values.iter().partition(|(column, _)| {
+            self.column_rules
+                .find(table, column)
+                .is_some_and(|rule| matches!(rule.anon_type, AnonymizationType::Template { .. }))
+        });
+
+        let mut replacements = Vec::new();
+        let mut anonymized: Vec<(&str, String)> = Vec::new();
+        for (column, value) in plain.into_iter().chain(templates) {
+            // NULL means "no value", which no fake should replace
+            if value.is_null() {
+                anonymized.push((column, String::new()));
+                continue;
+            }
+            let filled: Vec<(&str, &str)>;
+            let row = match self.column_rules.find(table, column) {
+                Some(rule) if matches!(rule.anon_type, AnonymizationType::Template { .. }) => {
+                    filled = anonymized
+                        .iter()
+                        .map(|(column, value)| (*column, value.as_str()))
+                        .collect();
+                    &filled
+                }
+                _ => fields,
+            };
+            match self.value(Some(table), column, &value.text, row_key, row) {
+                Some(fake) => {
+                    let rendered = if fake.is_null {
+                        fake.value.clone()
+                    } else {
+                        value.render(&fake.value)
+                    };
+                    replacements.push((value.span.clone(), rendered));
+                    if !fake.is_null {
+                        anonymized.push((column, fake.value));
+                    }
+                }
+                None => anonymized.push((column, value.text.clone())),
+            }
+        }
+        replacements
+    }
+
+    /// Anonymize a single value if a column rule matches it
+    ///
+    /// `row_key` identifies the row (its first column, usually the id) and
+    /// keys the offset for `shift_date` so dates in the same row keep their
+    /// intervals. `row` holds the row's column names and values where they
+    /// are known, for rules that key a persona by other columns or, already
+    /// anonymized, fill a template.
+    pub fn value(
+        &mut self,
+        table: Option<&str>,
+        column: &str,
+        value: &str,
+        row_key: &str,
+        row: &[(&str, &str)],
+    ) -> Option<AnonymizeOutcome> {
+        if table.is_some_and(|table| self.is_skipped(table)) {
+            return None;
+        }
+        let table = table.unwrap_or_default();
+        if self.auto_detect && self.column_rules.find(table, column).is_none() {
+            // A detected column becomes a bare-column rule, so it is detected
+            // once and reported like any other rule
+            if let Some(anon_type) = detect_type_for_column(column) {
+                self.column_rules.add(column, anon_type);
+            }
+        }
+        let Some(rule) = self.column_rules.find(table, column) else {
+            return self.value_matches(value);
+        };
+        let anon_type = &rule.anon_type;
+        let preserve = rule.preserve.unwrap_or(self.preserve_relationships);
+
+        let count = self
+            .rule_counts
+            .entry(rule.key.clone())
+            .or_insert_with(|| RuleCount {
+                anon_type: anon_type.clone(),
+                values: 0,
+                cache_hits: 0,
+                distinct: DistinctCount::new(),
+            });
+        count.values += 1;
+        let is_new = count.distinct.insert(value);
+
+        let outcome = match anon_type {
+            AnonymizationType::ShiftDate => {
+                let shifted = self.anonymizer.shift_date(value, row_key);
+                AnonymizeOutcome {
+                    changed: shifted != value,
+                    was_cached: false,
+                    is_null: false,
+                    value: shifted,
+                }
+            }
+            AnonymizationType::Template { from, .. } => match row_values(from, row) {
+                Some(values) => self.anonymizer.anonymize_template(
+                    value,
+                    anon_type,
+                    preserve,
+                    rule.group.as_deref(),
+                    &values,
+                ),
+                // Hashed rather than half filled
+                None => self.anonymizer.anonymize_grouped(
+                    value,
+                    anon_type,
+                    preserve,
+                    rule.group.as_deref(),
+                ),
+            },
+            _ => {
+                let persona = persona_key(&rule.persona, row)
+                    .and_then(|key| self.anonymizer.anonymize_persona(value, anon_type, &key));
+                persona.unwrap_or_else(|| {
+                    self.anonymizer.anonymize_localized(
+                        value,
+                        anon_type,
+                        preserve,
+                        rule.group.as_deref(),
+                        rule.locale,
+                    )
+                })
+            }
+        };
+        if outcome.was_cached {
+            count.cache_hits += 1;
+        }
+        if is_new && outcome.changed && self.sample_size > 0 {
+            let samples = self.samples.entry(rule.key.clone()).or_default();
+            if samples.len() < self.sample_size {
+                samples.push((preview(value), shorten(&outcome.value)));
+            }
+        }
+        Some(outcome)
+    }
+
+    /// Whether a table is listed in `skip_tables`
+    fn is_skipped(&self, table: &str) -> bool {
+        self.skip_tables.iter().any(|key| table_matches(key, table))
+    }
+
+    /// Anonymize the parts of a value without a column rule that match a value rule
+    fn value_matches(&mut self, value: &str) -> Option<AnonymizeOutcome> {
+        if self.value_rules.is_empty() {
+            return None;
+        }
+        let anonymized = anonymize_matches(
+            value,
+            &self.value_rules,
+            &mut self.anonymizer,
+            self.preserve_relationships,
+        );
+        if anonymized == value {
+            return None;
+        }
+        Some(AnonymizeOutcome {
+            value: anonymized,
+            was_cached: false,
+            changed: true,
+            is_null: false,
+        })
+    }
+
+    /// Anonymize a line outside any parsed statement with the line detectors
+    /// and value rules, leaving a trailing `--` comment as it is
+    pub fn line(&mut self, full_line: &str) -> String {
+        let (line, comment) =
+            full_line.split_at(sql::comment_start(full_line).unwrap_or(full_line.len()));
+        // The first rule matching the line picks the method, so use its override
+        let preserve = self
+            .rules
+            .iter()
+            .zip(&self.rule_preserve)
+            .find(|((pattern, _), _)| pattern.is_match(line))
+            .and_then(|(_, preserve)| *preserve)
+            .unwrap_or(self.preserve_relationships);
+        let mut anonymized = anonymize_line_with(
+            line,
+            &self.rules,
+            &self.detectors,
+            &mut self.anonymizer,
+            preserve,
+        );
+        if !self.value_rules.is_empty() {
+            anonymized = anonymize_line_matches(
+                &anonymized,
+                &self.value_rules,
+                &mut self.anonymizer,
+                self.preserve_relationships,
+            );
+        }
+        if anonymized != line {
+            self.detector_lines += 1;
+        }
+        anonymized + comment
+    }
+}
+
+/// An original value masked for `--sample`: its first two characters and
+/// its length, or only the length for values too short to give any away
+fn preview(value: &str) -> String {
+    let len = value.chars().count();
+    if len <= 4 {
+        return format!("*** ({} chars)", len);
+    }
+    let start: String = value.chars().take(2).collect();
+    format!("{}*** ({} chars)", start, len)
+}
+
+/// An anonymized value cut to a length that fits on one line of `--sample`
+fn shorten(value: &str) -> String {
+    const MAX_CHARS: usize = 60;
+    match value.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &value[..end]),
+        None => value.to_string(),
+    }
+}
+
+/// The key of a row's persona: the values of its persona columns joined by a space
+///
+/// Returns `None` when the rule has no persona or a column is missing from
+/// the row, e.g. for CSV and JSON input.
+fn persona_key(columns: &[String], row: &[(&str, &str)]) -> Option<String> {
+    if columns.is_empty() {
+        return None;
+    }
+    Some(row_values(columns, row)?.join(" "))
+}
+
+/// The values of `columns` in a row, or `None` if any is missing
+///
+/// Columns may be qualified like `users.first_name`; only the column name
+/// is compared, case-insensitively.
+fn row_values<'a>(columns: &[String], row: &[(&str, &'a str)]) -> Option<Vec<&'a str>> {
+    columns
+        .iter()
+        .map(|column| {
+            let column = column.rsplit('.').next().unwrap_or(column);
+            row.iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(column))
+                .map(|(_, value)| *value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persona_key() {
+        let row = [("id", "1"), ("First_Name", "John"), ("last_name", "Smith")];
+        let columns = ["first_name".to_string(), "last_name".to_string()];
+
+        assert_eq!(persona_key(&columns, &row).as_deref(), Some("John Smith"));
+        assert_eq!(persona_key(&columns, &row[..2]), None);
+        assert_eq!(persona_key(&[], &row), None);
+        assert_eq!(
+            row_values(&["users.last_name".to_string()], &row),
+            Some(vec!["Smith"])
+        );
+    }
+
+    #[test]
+    fn test_sample_preview() {
+        assert_eq!(preview("abc"), "*** (3 chars)");
+        assert_eq!(preview("user0@acme.com"), "us*** (14 chars)");
+        assert_eq!(shorten(&"x".repeat(100)), format!("{}...", "x".repeat(60)));
+    }
+
+    #[test]
+    fn test_delimited_statement() {
+        let mut rules = RuleSet::new();
+        rules.add("users.email", AnonymizationType::FakeEmail);
+        let mut processor = Processor::new(rules, Anonymizer::with_seed(1));
+        let block = Block::Delimited {
+            statement: "INSERT INTO users (id, email) VALUES (1, 'real@corp.com')// -- x\n"
+                .to_string(),
+            delimiter: "//".to_string(),
+        };
+
+        let anonymized = processor.block(&block);
+        assert!(!anonymized.contains("real@corp.com"));
+        assert!(anonymized.ends_with("')// -- x\n"));
+        assert_eq!(processor.rule_counts["users.email"].values, 1);
+    }
+
+    #[test]
+    fn test_new_derives_line_rules() {
+        let mut rules = RuleSet::new();
+        rules.add("users.email", AnonymizationType::FakeEmail);
+        let mut processor = Processor::new(rules, Anonymizer::with_seed(1));
+        processor.detectors = vec![Detector::Email];
+
+        assert_eq!(processor.rule_preserve, [None]);
+        let line = processor.line("SELECT 1 WHERE `users`.`email` = 'jane@x.com';");
+        assert!(!line.contains("jane@x.com"));
+        assert_eq!(processor.detector_lines, 1);
+    }
+}
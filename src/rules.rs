@@ -0,0 +1,324 @@
+// Column rule matching
+// Rules are keyed by `column`, `table.column` or `schema.table.column` and
+// matched case-insensitively against the identifiers found in the input.
+// Identifiers may be quoted MySQL-style (`users`) or ANSI-style ("users").
+
+use crate::{AnonymizationType, Config, Locale};
+use regex::Regex;
+use std::cmp::Reverse;
+
+/// A single column rule from `custom_rules`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// The rule key as written in the config
+    pub key: String,
+    pub anon_type: AnonymizationType,
+    /// Overrides the global `preserve_relationships` for this rule
+    pub preserve: Option<bool>,
+    /// Consistency group sharing fake values with other rules
+    pub group: Option<String>,
+    /// Columns of the row whose values key a shared persona
+    pub persona: Vec<String>,
+    /// Overrides the anonymizer's locale for this rule's fakes
+    pub locale: Option<Locale>,
+    /// Lowercased key segments, e.g. `["users", "email"]`
+    segments: Vec<String>,
+}
+
+impl Rule {
+    /// A rule for a `column`, `table.column` or `schema.table.column` key
+    pub fn new(key: &str, anon_type: AnonymizationType) -> Self {
+        Self {
+            key: key.to_string(),
+            anon_type,
+            preserve: None,
+            group: None,
+            persona: Vec::new(),
+            locale: None,
+            segments: split_identifier(key),
+        }
+    }
+}
+
+/// The set of column rules used to decide how each value is anonymized
+///
+/// A rule matches when its segments are a suffix of the value's full
+/// identifier, so `email` matches every `email` column, `users.email` also
+/// matches `public.users.email`, and JSON paths like `user.contact.email`
+/// work the same way. When several rules match, the most specific wins.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a rule set from `custom_rules`, skipping unknown methods
+    ///
+    /// A rule with an unknown `locale` uses the anonymizer's own.
+    pub fn from_config(config: &Config) -> Self {
+        let mut rules = Self::new();
+        for (key, rule) in &config.custom_rules {
+            if let Some(anon_type) = rule.anon_type() {
+                rules.insert(Rule {
+                    preserve: rule.preserve(),
+                    group: rule.group().map(str::to_string),
+                    persona: rule.persona().to_vec(),
+                    locale: rule.locale().and_then(Locale::from_str),
+                    ..Rule::new(key, anon_type)
+                });
+            }
+        }
+        rules
+    }
+
+    /// Add a rule for a `column`, `table.column` or `schema.table.column` key
+    pub fn add(&mut self, key: &str, anon_type: AnonymizationType) {
+        self.insert(Rule::new(key, anon_type));
+    }
+
+    /// Add a rule with its per-rule options
+    pub fn insert(&mut self, rule: Rule) {
+        self.rules.push(rule);
+        // Most specific rules first, so the first match is the best one
+        self.rules.sort_by_key(|rule| Reverse(rule.segments.len()));
+    }
+
+    /// The anonymization type for a column of a table, if any rule matches
+    ///
+    /// `table` may be schema-qualified or empty when the table is unknown.
+    pub fn matches(&self, table: &str, column: &str) -> Option<&AnonymizationType> {
+        self.find(table, column).map(|rule| &rule.anon_type)
+    }
+
+    /// The most specific rule matching a column of a table
+    pub fn find(&self, table: &str, column: &str) -> Option<&Rule> {
+        let mut identifier = split_identifier(table);
+        identifier.extend(split_identifier(column));
+
+        self.rules
+            .iter()
+            .find(|rule| identifier.ends_with(&rule.segments))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// Whether a `table` or `schema.table` key names a table
+///
+/// Matches like a column rule key: case-insensitively, ignoring identifier
+/// quotes, and `countries` also names `public.countries`.
+pub fn table_matches(key: &str, table: &str) -> bool {
+    let key = split_identifier(key);
+    !key.is_empty() && split_identifier(table).ends_with(&key)
+}
+
+/// A regex finding a rule key in a raw line, however its identifiers are quoted
+///
+/// `users.email` matches `users.email`, `` `users`.`email` `` and
+/// `"users"."email"`, case-insensitively and on word boundaries.
+pub fn line_regex(key: &str) -> Result<Regex, regex::Error> {
+    let segments: Vec<String> = split_identifier(key)
+        .iter()
+        .map(|segment| format!(r"\b{}\b", regex::escape(segment)))
+        .collect();
+    Regex::new(&format!("(?i){}", segments.join(r#"[`"]?\.[`"]?"#)))
+}
+
+/// Split a dotted identifier into lowercased segments, dropping any quotes
+fn split_identifier(identifier: &str) -> Vec<String> {
+    identifier
+        .split('.')
+        .map(|segment| unquote(segment.trim()).to_lowercase())
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// Strip the backticks or double quotes around a quoted identifier
+fn unquote(segment: &str) -> &str {
+    ['`', '"']
+        .iter()
+        .find_map(|&quote| {
+            segment
+                .strip_prefix(quote)
+                .and_then(|rest| rest.strip_suffix(quote))
+        })
+        .unwrap_or(segment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_set(rules: &[(&str, AnonymizationType)]) -> RuleSet {
+        let mut set = RuleSet::new();
+        for (key, anon_type) in rules {
+            set.add(key, anon_type.clone());
+        }
+        set
+    }
+
+    #[test]
+    fn test_matches_case_insensitive() {
+        let rules = rule_set(&[("Users.Email", AnonymizationType::FakeEmail)]);
+
+        assert_eq!(
+            rules.matches("users", "email"),
+            Some(&AnonymizationType::FakeEmail)
+        );
+        assert_eq!(
+            rules.matches("USERS", "EMAIL"),
+            Some(&AnonymizationType::FakeEmail)
+        );
+        assert_eq!(rules.matches("orders", "email"), None);
+    }
+
+    #[test]
+    fn test_matches_schema_qualified() {
+        let rules = rule_set(&[("users.email", AnonymizationType::FakeEmail)]);
+        assert_eq!(
+            rules.matches("public.users", "email"),
+            Some(&AnonymizationType::FakeEmail)
+        );
+
+        let rules = rule_set(&[("public.users.email", AnonymizationType::Hash)]);
+        assert_eq!(
+            rules.matches("public.users", "email"),
+            Some(&AnonymizationType::Hash)
+        );
+        assert_eq!(rules.matches("audit.users", "email"), None);
+        assert_eq!(rules.matches("users", "email"), None);
+    }
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let rules = rule_set(&[
+            ("email", AnonymizationType::Hash),
+            ("users.email", AnonymizationType::FakeEmail),
+        ]);
+
+        assert_eq!(
+            rules.matches("public.users", "email"),
+            Some(&AnonymizationType::FakeEmail)
+        );
+        assert_eq!(
+            rules.matches("contacts", "email"),
+            Some(&AnonymizationType::Hash)
+        );
+        assert_eq!(rules.find("contacts", "email").unwrap().key, "email");
+    }
+
+    #[test]
+    fn test_from_config_keeps_preserve_override() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  email: fake_email\n  phone: { method: fake_phone, preserve: false }\n  ssn: nope\n",
+        )
+        .unwrap();
+        let rules = RuleSet::from_config(&config);
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules.find("users", "email").unwrap().preserve, None);
+        assert_eq!(rules.find("users", "phone").unwrap().preserve, Some(false));
+        assert!(rules.find("users", "email").unwrap().persona.is_empty());
+    }
+
+    #[test]
+    fn test_from_config_keeps_persona_columns() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  first_name: { method: fake_first_name, persona: [first_name, last_name] }\n",
+        )
+        .unwrap();
+        let rules = RuleSet::from_config(&config);
+
+        assert_eq!(
+            rules.find("users", "first_name").unwrap().persona,
+            vec!["first_name".to_string(), "last_name".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_from_config_keeps_locale_override() {
+        let config: Config = serde_yaml::from_str(
+            "custom_rules:\n  customers.name: fake_name\n  suppliers.name: { method: fake_name, locale: ja }\n  vendors.name: { method: fake_name, locale: xx }\n",
+        )
+        .unwrap();
+        let rules = RuleSet::from_config(&config);
+
+        assert_eq!(rules.find("customers", "name").unwrap().locale, None);
+        assert_eq!(
+            rules.find("suppliers", "name").unwrap().locale,
+            Some(Locale::JaJp)
+        );
+        assert_eq!(rules.find("vendors", "name").unwrap().locale, None);
+    }
+
+    #[test]
+    fn test_matches_quoted_identifiers() {
+        for key in ["users.email", "`users`.`email`", "\"users\".\"email\""] {
+            let rules = rule_set(&[(key, AnonymizationType::FakeEmail)]);
+            for (table, column) in [
+                ("users", "email"),
+                ("`users`", "`email`"),
+                ("\"public\".\"users\"", "\"email\""),
+            ] {
+                assert_eq!(
+                    rules.matches(table, column),
+                    Some(&AnonymizationType::FakeEmail),
+                    "{} against {}.{}",
+                    key,
+                    table,
+                    column
+                );
+            }
+            assert_eq!(rules.matches("`orders`", "`email`"), None);
+        }
+    }
+
+    #[test]
+    fn test_table_matches() {
+        assert!(table_matches("countries", "countries"));
+        assert!(table_matches("countries", "\"public\".\"Countries\""));
+        assert!(table_matches("public.countries", "`public`.`countries`"));
+        assert!(!table_matches("public.countries", "countries"));
+        assert!(!table_matches("countries", "user_countries"));
+        assert!(!table_matches("", "countries"));
+    }
+
+    #[test]
+    fn test_line_regex_quoting_styles() {
+        let regex = line_regex("users.email").unwrap();
+
+        assert!(regex.is_match("UPDATE users SET users.email = 'a@b.com';"));
+        assert!(regex.is_match("UPDATE `users` SET `users`.`email` = 'a@b.com';"));
+        assert!(regex.is_match(r#"UPDATE "users" SET "Users"."Email" = 'a@b.com';"#));
+        assert!(!regex.is_match("UPDATE users SET users.email_verified = true;"));
+        assert!(!regex.is_match("UPDATE staff_users SET staff_users.email = '';"));
+
+        let quoted = line_regex("`users`.`email`").unwrap();
+        assert!(quoted.is_match("-- users.email"));
+    }
+
+    #[test]
+    fn test_matches_dotted_paths_without_table() {
+        let rules = rule_set(&[("user.contact.email", AnonymizationType::FakeEmail)]);
+
+        assert_eq!(
+            rules.matches("", "user.contact.email"),
+            Some(&AnonymizationType::FakeEmail)
+        );
+        assert_eq!(rules.matches("", "email"), None);
+    }
+}
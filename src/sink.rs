@@ -0,0 +1,92 @@
+// Output sinks
+// Where anonymized SQL goes. Every `Write` is a sink that writes the text
+// as is; sinks that aren't byte streams, such as a connection to a test
+// database, implement `OutputSink` directly and get one call per statement.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A destination for anonymized SQL, one statement or line at a time
+pub trait OutputSink {
+    /// Take one anonymized statement, or one line outside any statement
+    ///
+    /// `sql` is exactly as it appears in the output dump, including its
+    /// trailing newline; a multi-line statement comes in one call.
+    fn write_statement(&mut self, sql: &str) -> io::Result<()>;
+
+    /// Called once after the last statement, to flush or commit
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write + ?Sized> OutputSink for W {
+    fn write_statement(&mut self, sql: &str) -> io::Result<()> {
+        self.write_all(sql.as_bytes())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Buffered output to a file
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    /// Create or truncate the file at `path`
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl Write for FileSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Output to the process's standard output
+#[derive(Default)]
+pub struct StdoutSink;
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Write for StdoutSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writers_are_sinks() {
+        let mut output = Vec::new();
+        let sink: &mut dyn OutputSink = &mut output;
+        sink.write_statement("INSERT INTO t VALUES (1);\n").unwrap();
+        sink.write_statement("-- done\n").unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(output, b"INSERT INTO t VALUES (1);\n-- done\n");
+    }
+}
@@ -0,0 +1,1259 @@
+// SQL statement parsing
+// A small, forgiving tokenizer for the statements found in SQL dumps. It only
+// understands enough SQL to map each value in an INSERT, UPDATE or DELETE to
+// its column.
+
+use std::io;
+use std::ops::Range;
+
+/// A parsed `INSERT INTO table (cols) VALUES (...), (...)` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    /// Table name as written, with identifier quotes removed (e.g. `public.users`)
+    pub table: String,
+    /// Column list, empty if the statement doesn't name its columns
+    pub columns: Vec<String>,
+    /// One entry per VALUES tuple, in statement order
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// A parsed `UPDATE table SET col = value, ... [WHERE ...]` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    /// Table name as written, with identifier quotes removed
+    pub table: String,
+    /// `SET` assignments of a literal, as (column, value); assignments of
+    /// expressions are left out
+    pub assignments: Vec<(String, Value)>,
+    /// Literals compared with a column in the `WHERE` clause, from `col = 'x'`,
+    /// `col <> 'x'` and `col IN ('x', 'y')`
+    pub conditions: Vec<(String, Value)>,
+}
+
+/// A parsed `DELETE FROM table [WHERE ...]` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    /// Table name as written, with identifier quotes removed
+    pub table: String,
+    /// Literals compared with a column in the `WHERE` clause, as for `Update`
+    pub conditions: Vec<(String, Value)>,
+}
+
+/// A DELETE is anonymized like an UPDATE that assigns nothing
+impl From<Delete> for Update {
+    fn from(delete: Delete) -> Self {
+        Self {
+            table: delete.table,
+            assignments: Vec::new(),
+            conditions: delete.conditions,
+        }
+    }
+}
+
+/// A single value inside a VALUES tuple
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    /// Byte range of the value in the statement, including any quotes
+    pub span: Range<usize>,
+    /// Unescaped contents for string literals, raw text otherwise
+    pub text: String,
+    /// Whether the value was a quoted string literal
+    pub quoted: bool,
+    /// Whether the literal used MySQL-style backslash escapes, which its
+    /// replacement then needs too
+    pub backslash_escaped: bool,
+}
+
+impl Value {
+    /// True for an unquoted SQL `NULL`
+    pub fn is_null(&self) -> bool {
+        !self.quoted && self.text.eq_ignore_ascii_case("null")
+    }
+
+    /// Render a replacement for this value as SQL
+    ///
+    /// String literals stay quoted. Unquoted values (numbers) stay unquoted
+    /// only if the replacement is still a plain number such as `-12.5`; an
+    /// exponent, `inf` or `NaN` would load as a different number or not at
+    /// all, so those are quoted. Backslashes are escaped when
+    /// the original literal shows the dump uses backslash escapes, so e.g. a
+    /// JSON document's `\"` survives the round trip.
+    pub fn render(&self, replacement: &str) -> String {
+        if !self.quoted && is_numeric_literal(replacement) {
+            replacement.to_string()
+        } else if self.backslash_escaped {
+            quote_literal(&replacement.replace('\\', "\\\\"))
+        } else {
+            quote_literal(replacement)
+        }
+    }
+}
+
+/// Whether `text` is a plain SQL number: digits with an optional leading `-`
+/// and an optional fraction
+fn is_numeric_literal(text: &str) -> bool {
+    let text = text.strip_prefix('-').unwrap_or(text);
+    let (whole, fraction) = match text.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (text, None),
+    };
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    digits(whole) && fraction.is_none_or(digits)
+}
+
+/// Escape a string for use inside a single-quoted SQL literal by doubling
+/// its single quotes
+pub fn escape_literal(value: &str) -> String {
+    value.replace('\'', "''")
+}
+
+/// Quote a string as a SQL literal, doubling embedded single quotes
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", escape_literal(value))
+}
+
+/// Whether a byte offset of a line falls inside a single-quoted string literal
+pub fn in_string_literal(line: &str, offset: usize) -> bool {
+    let mut in_quote = false;
+    let mut chars = line[..offset].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_quote => {
+                chars.next();
+            }
+            '\'' => in_quote = !in_quote,
+            _ => {}
+        }
+    }
+    in_quote
+}
+
+/// Parse an INSERT statement
+///
+/// Returns `None` for anything that isn't a well-formed `INSERT ... VALUES`.
+/// The statement may span multiple lines.
+pub fn parse_insert(statement: &str) -> Option<Insert> {
+    let mut cursor = Cursor::new(statement);
+    cursor.keyword("INSERT")?;
+    cursor.keyword("IGNORE");
+    cursor.keyword("INTO")?;
+    let table = cursor.qualified_identifier()?;
+
+    let mut columns = Vec::new();
+    if cursor.symbol('(').is_some() {
+        loop {
+            columns.push(cursor.identifier()?);
+            if cursor.symbol(',').is_none() {
+                break;
+            }
+        }
+        cursor.symbol(')')?;
+    }
+
+    cursor.keyword("VALUES")?;
+    let mut rows = Vec::new();
+    loop {
+        cursor.symbol('(')?;
+        let mut row = Vec::new();
+        loop {
+            row.push(cursor.value()?);
+            if cursor.symbol(',').is_none() {
+                break;
+            }
+        }
+        cursor.symbol(')')?;
+        rows.push(row);
+        if cursor.symbol(',').is_none() {
+            break;
+        }
+    }
+
+    Some(Insert {
+        table,
+        columns,
+        rows,
+    })
+}
+
+/// Parse the table name and column names out of a CREATE TABLE statement
+///
+/// Used to recover the column order for INSERTs that omit a column list.
+pub fn parse_create_table(statement: &str) -> Option<(String, Vec<String>)> {
+    const CONSTRAINT_KEYWORDS: [&str; 10] = [
+        "PRIMARY",
+        "KEY",
+        "UNIQUE",
+        "CONSTRAINT",
+        "INDEX",
+        "FOREIGN",
+        "CHECK",
+        "FULLTEXT",
+        "SPATIAL",
+        "EXCLUDE",
+    ];
+
+    let mut cursor = Cursor::new(statement);
+    cursor.keyword("CREATE")?;
+    cursor.keyword("TEMPORARY");
+    cursor.keyword("TABLE")?;
+    if cursor.keyword("IF").is_some() {
+        cursor.keyword("NOT")?;
+        cursor.keyword("EXISTS")?;
+    }
+    let table = cursor.qualified_identifier()?;
+    cursor.symbol('(')?;
+
+    let mut columns = Vec::new();
+    loop {
+        let is_constraint = CONSTRAINT_KEYWORDS.iter().any(|kw| cursor.peek_keyword(kw));
+        let name = cursor.identifier()?;
+        if !is_constraint {
+            columns.push(name);
+        }
+        // Skip the rest of the definition (type, defaults, nested parens)
+        cursor.skip_until_top_level(&[',', ')'])?;
+        if cursor.symbol(',').is_none() {
+            break;
+        }
+    }
+    cursor.symbol(')')?;
+
+    Some((table, columns))
+}
+
+/// Parse the table and column list of a `COPY table (cols) FROM stdin;` line
+///
+/// Returns `None` for anything else, including `COPY ... TO` and copies
+/// from a file, whose rows aren't in the dump.
+pub fn parse_copy(statement: &str) -> Option<(String, Vec<String>)> {
+    let mut cursor = Cursor::new(statement);
+    cursor.keyword("COPY")?;
+    let table = cursor.qualified_identifier()?;
+
+    let mut columns = Vec::new();
+    if cursor.symbol('(').is_some() {
+        loop {
+            columns.push(cursor.identifier()?);
+            if cursor.symbol(',').is_none() {
+                break;
+            }
+        }
+        cursor.symbol(')')?;
+    }
+
+    cursor.keyword("FROM")?;
+    cursor.keyword("STDIN")?;
+    Some((table, columns))
+}
+
+/// Parse an `UPDATE table SET col = value, ... [WHERE ...]` statement
+///
+/// Returns `None` for anything else. Column names lose any table qualifier,
+/// so `users.email = 'x'` is reported as `email`.
+pub fn parse_update(statement: &str) -> Option<Update> {
+    let mut cursor = Cursor::new(statement);
+    cursor.keyword("UPDATE")?;
+    cursor.keyword("ONLY");
+    let table = cursor.qualified_identifier()?;
+    cursor.keyword("SET")?;
+
+    let mut assignments = Vec::new();
+    loop {
+        let column = cursor.column()?;
+        cursor.symbol('=')?;
+        match cursor.scalar() {
+            Some(value) => assignments.push((column, value)),
+            None => cursor.skip_expression()?,
+        }
+        if cursor.symbol(',').is_none() {
+            break;
+        }
+    }
+
+    let conditions = if cursor.keyword("WHERE").is_some() {
+        cursor.conditions()
+    } else {
+        Vec::new()
+    };
+
+    Some(Update {
+        table,
+        assignments,
+        conditions,
+    })
+}
+
+/// Parse a `DELETE FROM table [[AS] alias] [WHERE ...]` statement
+///
+/// MySQL's `LOW_PRIORITY`, `QUICK` and `IGNORE` modifiers are accepted.
+/// Returns `None` for anything else, including multi-table deletes.
+pub fn parse_delete(statement: &str) -> Option<Delete> {
+    let mut cursor = Cursor::new(statement);
+    cursor.keyword("DELETE")?;
+    for modifier in ["LOW_PRIORITY", "QUICK", "IGNORE"] {
+        cursor.keyword(modifier);
+    }
+    cursor.keyword("FROM")?;
+    cursor.keyword("ONLY");
+    let table = cursor.qualified_identifier()?;
+
+    if cursor.keyword("WHERE").is_none() {
+        cursor.skip_whitespace();
+        if matches!(cursor.peek(), None | Some(';')) {
+            return Some(Delete {
+                table,
+                conditions: Vec::new(),
+            });
+        }
+        cursor.keyword("AS");
+        cursor.identifier()?;
+        cursor.keyword("WHERE")?;
+    }
+
+    Some(Delete {
+        table,
+        conditions: cursor.conditions(),
+    })
+}
+
+/// Replace byte ranges of a statement, leaving everything else untouched
+///
+/// Ranges must not overlap; they may be given in any order.
+pub fn replace_spans(statement: &str, mut replacements: Vec<(Range<usize>, String)>) -> String {
+    replacements.sort_by_key(|(span, _)| span.start);
+
+    let mut output = String::with_capacity(statement.len());
+    let mut last = 0;
+    for (span, replacement) in replacements {
+        output.push_str(&statement[last..span.start]);
+        output.push_str(&replacement);
+        last = span.end;
+    }
+    output.push_str(&statement[last..]);
+    output
+}
+
+/// True once a statement ends with a `;` that is outside any string literal
+/// or `--` comment
+pub fn is_complete(statement: &str) -> bool {
+    is_terminated(statement, ";")
+}
+
+/// Like `is_complete` for a statement ending in a custom `DELIMITER`
+pub fn is_terminated(statement: &str, delimiter: &str) -> bool {
+    let mut in_quote: Option<char> = None;
+    let mut complete = false;
+    let mut chars = statement.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match in_quote {
+            Some(_) if c == '\\' => {
+                chars.next();
+            }
+            Some(quote) if c == quote => in_quote = None,
+            Some(_) => {}
+            None => match c {
+                '\'' | '"' | '`' => {
+                    in_quote = Some(c);
+                    complete = false;
+                }
+                '-' if chars.peek().is_some_and(|&(_, c)| c == '-') => {
+                    for (_, c) in chars.by_ref() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                _ if statement[i..].starts_with(delimiter) => {
+                    complete = true;
+                    for _ in delimiter.chars().skip(1) {
+                        chars.next();
+                    }
+                }
+                c if c.is_whitespace() => {}
+                _ => complete = false,
+            },
+        }
+    }
+
+    in_quote.is_none() && complete
+}
+
+/// Byte offset where a `--` comment starts in a line, outside any string
+/// literal or quoted identifier
+pub fn comment_start(line: &str) -> Option<usize> {
+    let mut in_quote: Option<char> = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match in_quote {
+            Some(_) if c == '\\' => {
+                chars.next();
+            }
+            Some(quote) if c == quote => in_quote = None,
+            Some(_) => {}
+            None => match c {
+                '\'' | '"' | '`' => in_quote = Some(c),
+                '-' if chars.peek().is_some_and(|&(_, c)| c == '-') => return Some(i),
+                _ => {}
+            },
+        }
+    }
+    None
+}
+
+/// Whether a whole line is comment, and if so whether a `/* */` comment is
+/// still open after it
+///
+/// `in_comment` says a `/* */` comment was open before the line. A line
+/// ending a block comment counts only if nothing but a `;` follows the
+/// `*/`, so code after it is never mistaken for comment. `#` comments are
+/// MySQL's, and only recognized at the start of a line.
+fn comment_line(line: &str, in_comment: bool) -> Option<bool> {
+    let trimmed = line.trim_start();
+    let body = if in_comment {
+        trimmed
+    } else if trimmed.starts_with("--") || trimmed.starts_with('#') {
+        return Some(false);
+    } else {
+        trimmed.strip_prefix("/*")?
+    };
+    match body.find("*/") {
+        None => Some(true),
+        Some(end) => matches!(body[end + 2..].trim(), "" | ";").then_some(false),
+    }
+}
+
+/// The delimiter a MySQL `DELIMITER` directive line switches to
+fn delimiter_directive(line: &str) -> Option<&str> {
+    let mut words = line.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("DELIMITER") {
+        return None;
+    }
+    words.next()
+}
+
+/// Whether a line starts a stored procedure, function, trigger or event
+///
+/// Such a definition runs up to the custom delimiter and is copied as it
+/// is. The `/*!50003 ... */` version comments mysqldump wraps around
+/// trigger definitions are looked through.
+fn is_routine_start(line: &str) -> bool {
+    let mut words = line
+        .split_whitespace()
+        .filter(|word| !word.starts_with("/*!"))
+        .map(|word| word.trim_end_matches("*/"))
+        .filter(|word| !word.is_empty());
+    if !words
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case("CREATE"))
+    {
+        return false;
+    }
+    // `DEFINER = user` may be written with spaces around the `=`
+    let mut definer_value = false;
+    for word in words {
+        let word = word.to_ascii_uppercase();
+        match word.as_str() {
+            "PROCEDURE" | "FUNCTION" | "TRIGGER" | "EVENT" => return true,
+            "OR" | "REPLACE" | "AGGREGATE" | "DEFINER" => {}
+            "=" => definer_value = true,
+            _ if definer_value => definer_value = false,
+            _ if word.starts_with("DEFINER=") => {}
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Whether a line starts a statement the parser handles
+pub fn is_parsed_statement(line: &str) -> bool {
+    let line = line.trim_start().to_ascii_uppercase();
+    ["INSERT", "UPDATE", "DELETE", "CREATE TABLE"]
+        .iter()
+        .any(|keyword| line.starts_with(keyword))
+}
+
+/// A unit of a SQL dump that can be anonymized on its own
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    /// A statement `is_parsed_statement` recognizes, each of its lines ending
+    /// in a newline
+    Statement(String),
+    /// A line outside any such statement, without its newline
+    Line(String),
+    /// A comment line outside any statement, without its newline: a `--` or
+    /// `#` line, or a line of a `/* */` comment; never anonymized
+    Comment(String),
+    /// A line of a stored procedure, function, trigger or event defined
+    /// under a custom `DELIMITER`, or a `DELIMITER` directive, without its
+    /// newline
+    ///
+    /// Routine bodies hold statements ending in `;` that must not be split
+    /// off or anonymized.
+    Routine(String),
+    /// A statement `is_parsed_statement` recognizes, ending in a custom
+    /// `DELIMITER` rather than `;`
+    Delimited {
+        statement: String,
+        delimiter: String,
+    },
+}
+
+impl Block {
+    /// The text of a `Statement` or `Delimited` block
+    pub fn statement(&self) -> Option<&str> {
+        match self {
+            Block::Statement(statement) | Block::Delimited { statement, .. } => Some(statement),
+            Block::Line(_) | Block::Comment(_) | Block::Routine(_) => None,
+        }
+    }
+}
+
+/// Group the lines of a SQL dump into blocks
+///
+/// A statement runs from the line starting it to the line completing it,
+/// however many lines its values span. A statement still open at the end
+/// of the input is yielded as it is; check `is_complete` (`is_terminated`
+/// for a `Delimited` one) to tell. A byte order mark before the first line
+/// is dropped.
+pub fn blocks<I>(lines: I) -> Blocks<I>
+where
+    I: Iterator<Item = io::Result<String>>,
+{
+    Blocks {
+        lines,
+        statement: String::new(),
+        started: false,
+        in_comment: false,
+        delimiter: None,
+        in_routine: false,
+    }
+}
+
+/// Iterator returned by `blocks`
+pub struct Blocks<I> {
+    lines: I,
+    statement: String,
+    /// Whether the first line, which may carry a byte order mark, was read
+    started: bool,
+    /// Whether a `/* */` comment is open
+    in_comment: bool,
+    /// The active delimiter, when a `DELIMITER` directive changed it from `;`
+    delimiter: Option<String>,
+    /// Whether a routine definition is open, up to the custom delimiter
+    in_routine: bool,
+}
+
+impl<I> Blocks<I> {
+    /// The statement read so far, as a block ending in the active delimiter
+    fn take_statement(&mut self) -> Block {
+        let statement = std::mem::take(&mut self.statement);
+        match &self.delimiter {
+            Some(delimiter) => Block::Delimited {
+                statement,
+                delimiter: delimiter.clone(),
+            },
+            None => Block::Statement(statement),
+        }
+    }
+}
+
+impl<I> Iterator for Blocks<I>
+where
+    I: Iterator<Item = io::Result<String>>,
+{
+    type Item = io::Result<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(e)),
+                None if self.statement.is_empty() => return None,
+                None => return Some(Ok(self.take_statement())),
+            };
+            let line = if self.started {
+                line
+            } else {
+                self.started = true;
+                crate::strip_bom(line)
+            };
+            if self.statement.is_empty() {
+                if let Some(delimiter) = delimiter_directive(&line) {
+                    self.delimiter = (delimiter != ";").then(|| delimiter.to_string());
+                    self.in_routine = false;
+                    return Some(Ok(Block::Routine(line)));
+                }
+                if let Some(delimiter) = &self.delimiter {
+                    if self.in_routine || is_routine_start(&line) {
+                        self.in_routine = !line.trim_end().ends_with(delimiter.as_str());
+                        return Some(Ok(Block::Routine(line)));
+                    }
+                }
+                match comment_line(&line, self.in_comment) {
+                    Some(open) => {
+                        self.in_comment = open;
+                        return Some(Ok(Block::Comment(line)));
+                    }
+                    None => self.in_comment = false,
+                }
+                if !is_parsed_statement(&line) {
+                    return Some(Ok(Block::Line(line)));
+                }
+            }
+            self.statement.push_str(&line);
+            self.statement.push('\n');
+            if is_terminated(&self.statement, self.delimiter.as_deref().unwrap_or(";")) {
+                return Some(Ok(self.take_statement()));
+            }
+        }
+    }
+}
+
+/// Byte-level cursor over a statement
+struct Cursor<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Skip whitespace and `--` / `/* */` comments
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("--") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn peek_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let rest = self.rest();
+        rest.len() >= keyword.len()
+            && rest.is_char_boundary(keyword.len())
+            && rest[..keyword.len()].eq_ignore_ascii_case(keyword)
+            && !rest[keyword.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_identifier_char)
+    }
+
+    /// Consume a case-insensitive keyword
+    fn keyword(&mut self, keyword: &str) -> Option<()> {
+        if self.peek_keyword(keyword) {
+            self.pos += keyword.len();
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Consume a single punctuation character
+    fn symbol(&mut self, symbol: char) -> Option<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(symbol) {
+            self.pos += symbol.len_utf8();
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Consume a bare, backtick-quoted or double-quoted identifier
+    fn identifier(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        match self.peek()? {
+            quote @ ('`' | '"') => {
+                let rest = &self.rest()[1..];
+                let end = rest.find(quote)?;
+                self.pos += end + 2;
+                Some(rest[..end].to_string())
+            }
+            c if is_identifier_char(c) => {
+                let rest = self.rest();
+                let end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+                self.pos += end;
+                Some(rest[..end].to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// Consume a dotted identifier such as `public.users`
+    fn qualified_identifier(&mut self) -> Option<String> {
+        let mut parts = vec![self.identifier()?];
+        while self.peek() == Some('.') {
+            self.pos += 1;
+            parts.push(self.identifier()?);
+        }
+        Some(parts.join("."))
+    }
+
+    /// Consume a possibly qualified column name, keeping only the column
+    fn column(&mut self) -> Option<String> {
+        let name = self.qualified_identifier()?;
+        Some(name.rsplit('.').next().unwrap_or_default().to_string())
+    }
+
+    /// Consume a literal that stands alone: a string, number or `NULL` not
+    /// followed by an operator or cast
+    ///
+    /// Anything else, such as a column reference or `now()`, is left
+    /// unconsumed and `None` returned.
+    fn scalar(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let quoted = self.peek()? == '\'';
+        let text = if quoted {
+            self.string_literal()?
+        } else {
+            let rest = self.rest();
+            let end = rest
+                .find(|c: char| !(is_identifier_char(c) || matches!(c, '.' | '-' | '+')))
+                .unwrap_or(rest.len());
+            let token = &rest[..end];
+            if token.parse::<f64>().is_err() && !token.eq_ignore_ascii_case("null") {
+                return None;
+            }
+            self.pos += end;
+            token.to_string()
+        };
+        let end = self.pos;
+
+        self.skip_whitespace();
+        match self.peek() {
+            None | Some(',' | ';' | ')') => {}
+            Some(c) if is_identifier_char(c) => {}
+            _ => {
+                self.pos = start;
+                return None;
+            }
+        }
+        Some(Value {
+            span: start..end,
+            backslash_escaped: quoted && self.input[start..end].contains('\\'),
+            text,
+            quoted,
+        })
+    }
+
+    /// Skip an expression up to the next top-level `,`, `;` or `WHERE`
+    fn skip_expression(&mut self) -> Option<()> {
+        let mut depth = 0usize;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Some(()),
+                Some(',' | ';') if depth == 0 => return Some(()),
+                _ if depth == 0 && self.peek_keyword("WHERE") => return Some(()),
+                Some('(') => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                Some(')') => {
+                    depth = depth.checked_sub(1)?;
+                    self.pos += 1;
+                }
+                Some('\'') => {
+                    self.string_literal()?;
+                }
+                Some('`' | '"') => {
+                    self.identifier()?;
+                }
+                Some(c) if is_identifier_char(c) => {
+                    self.identifier()?;
+                }
+                Some(c) => self.pos += c.len_utf8(),
+            }
+        }
+    }
+
+    /// Collect the literals compared with a column in a `WHERE` clause
+    fn conditions(&mut self) -> Vec<(String, Value)> {
+        let mut conditions = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some(';') => break,
+                Some('\'') => {
+                    // A literal not preceded by a column, e.g. `'x' = email`
+                    if self.string_literal().is_none() {
+                        break;
+                    }
+                }
+                Some(c) if is_identifier_char(c) || c == '`' || c == '"' => {
+                    let Some(column) = self.column() else {
+                        break;
+                    };
+                    if self.comparison() {
+                        conditions.extend(self.scalar().map(|value| (column, value)));
+                        continue;
+                    }
+                    self.keyword("NOT");
+                    if self.keyword("IN").is_some() && self.symbol('(').is_some() {
+                        while let Some(value) = self.scalar() {
+                            conditions.push((column.clone(), value));
+                            if self.symbol(',').is_none() {
+                                break;
+                            }
+                        }
+                    }
+                }
+                Some(c) => self.pos += c.len_utf8(),
+            }
+        }
+        conditions
+    }
+
+    /// Consume `=`, `<>` or `!=`
+    fn comparison(&mut self) -> bool {
+        self.skip_whitespace();
+        for operator in ["=", "<>", "!="] {
+            if self.rest().starts_with(operator) {
+                self.pos += operator.len();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Consume one value inside a VALUES tuple
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        let start = self.pos;
+
+        if self.peek()? == '\'' {
+            let text = self.string_literal()?;
+            let end = self.pos;
+            self.skip_whitespace();
+            if matches!(self.peek(), Some(',' | ')')) {
+                return Some(Value {
+                    span: start..end,
+                    text,
+                    quoted: true,
+                    backslash_escaped: self.input[start..end].contains('\\'),
+                });
+            }
+            // A literal with a cast or operator ('...'::date) is kept as an expression
+        }
+
+        self.skip_until_top_level(&[',', ')'])?;
+        let raw = self.input[start..self.pos].trim_end();
+        if raw.is_empty() {
+            return None;
+        }
+        Some(Value {
+            span: start..start + raw.len(),
+            text: raw.to_string(),
+            quoted: false,
+            backslash_escaped: false,
+        })
+    }
+
+    /// Consume a single-quoted literal, returning its unescaped contents
+    ///
+    /// Handles both `''` doubling and MySQL-style backslash escapes.
+    fn string_literal(&mut self) -> Option<String> {
+        let mut text = String::new();
+        let mut chars = self.rest().char_indices().skip(1).peekable();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    let (_, escaped) = chars.next()?;
+                    text.push(match escaped {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        '0' => '\0',
+                        other => other,
+                    });
+                }
+                '\'' if chars.peek().map(|(_, c)| *c) == Some('\'') => {
+                    chars.next();
+                    text.push('\'');
+                }
+                '\'' => {
+                    self.pos += i + 1;
+                    return Some(text);
+                }
+                c => text.push(c),
+            }
+        }
+        None
+    }
+
+    /// Advance to the next top-level occurrence of one of `stops`
+    ///
+    /// Skips over string literals, quoted identifiers and nested parentheses.
+    fn skip_until_top_level(&mut self, stops: &[char]) -> Option<()> {
+        let mut depth = 0usize;
+        loop {
+            match self.peek()? {
+                c if depth == 0 && stops.contains(&c) => return Some(()),
+                '(' => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                ')' => {
+                    depth = depth.checked_sub(1)?;
+                    self.pos += 1;
+                }
+                '\'' => {
+                    self.string_literal()?;
+                }
+                '`' | '"' => {
+                    self.identifier()?;
+                }
+                c => self.pos += c.len_utf8(),
+            }
+        }
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_insert_with_columns() {
+        let sql = "INSERT INTO public.users (id, email) VALUES (1, 'john@example.com'), (2, NULL);";
+        let insert = parse_insert(sql).unwrap();
+
+        assert_eq!(insert.table, "public.users");
+        assert_eq!(insert.columns, vec!["id", "email"]);
+        assert_eq!(insert.rows.len(), 2);
+
+        let email = &insert.rows[0][1];
+        assert_eq!(email.text, "john@example.com");
+        assert!(email.quoted);
+        assert_eq!(&sql[email.span.clone()], "'john@example.com'");
+        assert!(insert.rows[1][1].is_null());
+    }
+
+    #[test]
+    fn test_parse_insert_cast_is_an_expression() {
+        let sql = "INSERT INTO events (id, at) VALUES (1, '2024-01-01'::date);";
+        let value = &parse_insert(sql).unwrap().rows[0][1];
+
+        assert!(!value.quoted);
+        assert_eq!(value.text, "'2024-01-01'::date");
+    }
+
+    #[test]
+    fn test_parse_insert_multiline_and_escapes() {
+        let sql = "INSERT INTO `users` (`id`, `note`) VALUES\n(1, 'it''s\nmultiline'),\n(2, 'back\\'slash');";
+        let insert = parse_insert(sql).unwrap();
+
+        assert_eq!(insert.table, "users");
+        assert_eq!(insert.columns, vec!["id", "note"]);
+        assert_eq!(insert.rows[0][1].text, "it's\nmultiline");
+        assert_eq!(insert.rows[1][1].text, "back'slash");
+    }
+
+    #[test]
+    fn test_parse_insert_rejects_other_statements() {
+        assert_eq!(parse_insert("SELECT * FROM users;"), None);
+        assert_eq!(parse_insert("INSERT INTO users (id) VALUES (1"), None);
+    }
+
+    #[test]
+    fn test_parse_create_table() {
+        let sql = "CREATE TABLE public.users (\n    id integer NOT NULL,\n    email character varying(255),\n    PRIMARY KEY (id)\n);";
+        let (table, columns) = parse_create_table(sql).unwrap();
+
+        assert_eq!(table, "public.users");
+        assert_eq!(columns, vec!["id", "email"]);
+    }
+
+    #[test]
+    fn test_parse_update() {
+        let sql = "UPDATE public.users SET email = 'real@x.com', visits = visits + 1, \
+                   `users`.phone = NULL WHERE email = 'old@x.com' AND id IN (1, 2) AND note LIKE '%a';";
+        let update = parse_update(sql).unwrap();
+
+        assert_eq!(update.table, "public.users");
+        let assigned: Vec<(&str, &str)> = update
+            .assignments
+            .iter()
+            .map(|(column, value)| (column.as_str(), &sql[value.span.clone()]))
+            .collect();
+        assert_eq!(assigned, [("email", "'real@x.com'"), ("phone", "NULL")]);
+        assert!(update.assignments[1].1.is_null());
+
+        let conditions: Vec<(&str, &str)> = update
+            .conditions
+            .iter()
+            .map(|(column, value)| (column.as_str(), value.text.as_str()))
+            .collect();
+        assert_eq!(
+            conditions,
+            [("email", "old@x.com"), ("id", "1"), ("id", "2")]
+        );
+    }
+
+    #[test]
+    fn test_parse_update_expressions_and_other_statements() {
+        let sql = "UPDATE t SET a = lower('X'), b = 'y'::text, c = 'keep' WHERE d <> 'z'";
+        let update = parse_update(sql).unwrap();
+        let assigned: Vec<&str> = update.assignments.iter().map(|(c, _)| c.as_str()).collect();
+        assert_eq!(assigned, ["c"]);
+        assert_eq!(update.conditions[0].1.text, "z");
+
+        assert!(parse_update("UPDATE t SET a = 1")
+            .unwrap()
+            .conditions
+            .is_empty());
+        assert_eq!(parse_update("INSERT INTO t VALUES (1);"), None);
+        assert_eq!(parse_update("UPDATE t WHERE a = 1;"), None);
+    }
+
+    #[test]
+    fn test_parse_delete() {
+        let sql = "DELETE FROM `users` WHERE users.email = 'real@x.com' OR id IN (3, 4);";
+        let delete = parse_delete(sql).unwrap();
+        assert_eq!(delete.table, "users");
+        let conditions: Vec<(&str, &str)> = delete
+            .conditions
+            .iter()
+            .map(|(column, value)| (column.as_str(), &sql[value.span.clone()]))
+            .collect();
+        assert_eq!(
+            conditions,
+            [("email", "'real@x.com'"), ("id", "3"), ("id", "4")]
+        );
+
+        let aliased =
+            parse_delete("DELETE IGNORE FROM public.users AS u WHERE u.ssn <> 'x'").unwrap();
+        assert_eq!(aliased.table, "public.users");
+        assert_eq!(aliased.conditions[0].0, "ssn");
+
+        assert!(parse_delete("delete from users;")
+            .unwrap()
+            .conditions
+            .is_empty());
+        assert_eq!(
+            parse_delete("DELETE users FROM users JOIN t ON 1 = 1;"),
+            None
+        );
+        assert_eq!(parse_delete("UPDATE users SET a = 1;"), None);
+    }
+
+    #[test]
+    fn test_parse_copy() {
+        let (table, columns) =
+            parse_copy("COPY public.users (id, \"first name\", email) FROM stdin;").unwrap();
+        assert_eq!(table, "public.users");
+        assert_eq!(columns, vec!["id", "first name", "email"]);
+
+        assert_eq!(
+            parse_copy("copy users from stdin;"),
+            Some(("users".to_string(), vec![]))
+        );
+        assert_eq!(parse_copy("COPY users TO stdout;"), None);
+        assert_eq!(parse_copy("COPY users FROM '/tmp/users.csv';"), None);
+    }
+
+    #[test]
+    fn test_replace_spans_and_render() {
+        let sql = "INSERT INTO users (id, email) VALUES (1, 'john@example.com');";
+        let insert = parse_insert(sql).unwrap();
+        let email = &insert.rows[0][1];
+
+        let output = replace_spans(
+            sql,
+            vec![(email.span.clone(), email.render("o'brien@example.com"))],
+        );
+        assert_eq!(
+            output,
+            "INSERT INTO users (id, email) VALUES (1, 'o''brien@example.com');"
+        );
+
+        // A dump that escapes with backslashes gets its backslashes escaped back
+        let sql = r#"INSERT INTO events (payload) VALUES ('{\"note\":\"a\\\"b\"}');"#;
+        let payload = &parse_insert(sql).unwrap().rows[0][0];
+        assert_eq!(payload.text, r#"{"note":"a\"b"}"#);
+        assert!(payload.backslash_escaped);
+        assert_eq!(payload.render(&payload.text), r#"'{"note":"a\\"b"}'"#);
+        assert_eq!(
+            parse_insert(&output).unwrap().rows[0][1].render("x\\y"),
+            r"'x\y'"
+        );
+    }
+
+    #[test]
+    fn test_render_numeric() {
+        let id = &parse_insert("INSERT INTO t (id) VALUES (193);")
+            .unwrap()
+            .rows[0][0];
+        assert_eq!(id.render("42"), "42");
+        assert_eq!(id.render("-12.50"), "-12.50");
+        // Digests that happen to parse as floats must not load as numbers
+        assert_eq!(id.render("57e93294"), "'57e93294'");
+        assert_eq!(id.render("182262e8"), "'182262e8'");
+        assert_eq!(id.render("inf"), "'inf'");
+        assert_eq!(id.render("NaN"), "'NaN'");
+        assert_eq!(id.render("1."), "'1.'");
+        assert_eq!(id.render("-"), "'-'");
+    }
+
+    #[test]
+    fn test_escape_literal_and_in_string_literal() {
+        assert_eq!(escape_literal("O'Brien St"), "O''Brien St");
+        assert_eq!(escape_literal("plain"), "plain");
+
+        let line = "VALUES ('it''s', 'a\\'b', c)";
+        assert!(in_string_literal(line, line.find("it").unwrap()));
+        assert!(in_string_literal(line, line.find("s'").unwrap()));
+        assert!(in_string_literal(line, line.find('b').unwrap()));
+        assert!(!in_string_literal(line, line.find('c').unwrap()));
+        assert!(!in_string_literal(line, 0));
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("INSERT INTO t VALUES (1);\n"));
+        assert!(!is_complete("INSERT INTO t VALUES ('a;\n"));
+        assert!(!is_complete("INSERT INTO t VALUES (1),\n"));
+        assert!(is_complete(
+            "INSERT INTO t VALUES (1); -- don't stop here\n"
+        ));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let dump = "-- header\nINSERT INTO t VALUES ('a\n', 1),\n(2);\nSELECT 1;\nupdate t SET a = 1;\nINSERT INTO t VALUES (3)";
+        let lines = dump.lines().map(|line| Ok(line.to_string()));
+        let blocks: Vec<Block> = blocks(lines).collect::<io::Result<_>>().unwrap();
+
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Comment("-- header".to_string()),
+                Block::Statement("INSERT INTO t VALUES ('a\n', 1),\n(2);\n".to_string()),
+                Block::Line("SELECT 1;".to_string()),
+                Block::Statement("update t SET a = 1;\n".to_string()),
+                Block::Statement("INSERT INTO t VALUES (3)\n".to_string()),
+            ]
+        );
+        assert!(!is_complete(match &blocks[4] {
+            Block::Statement(statement) => statement,
+            _ => unreachable!(),
+        }));
+    }
+
+    #[test]
+    fn test_blocks_comments() {
+        let dump = "# MySQL dump\n\
+                    /* users: jane@acme.com\n\
+                    INSERT INTO users VALUES ('jane@acme.com');\n\
+                    */\n\
+                    /*!40101 SET NAMES utf8 */;\n\
+                    /* note */ SELECT 'jane@acme.com';\n\
+                    -- INSERT INTO users VALUES ('joe@acme.com');\n\
+                    INSERT INTO users VALUES (1); -- joe@acme.com\n";
+        let lines = dump.lines().map(|line| Ok(line.to_string()));
+        let blocks: Vec<Block> = blocks(lines).collect::<io::Result<_>>().unwrap();
+
+        let comment = |line: &str| Block::Comment(line.to_string());
+        assert_eq!(
+            blocks,
+            vec![
+                comment("# MySQL dump"),
+                comment("/* users: jane@acme.com"),
+                comment("INSERT INTO users VALUES ('jane@acme.com');"),
+                comment("*/"),
+                comment("/*!40101 SET NAMES utf8 */;"),
+                // Code after a comment is still code
+                Block::Line("/* note */ SELECT 'jane@acme.com';".to_string()),
+                comment("-- INSERT INTO users VALUES ('joe@acme.com');"),
+                Block::Statement("INSERT INTO users VALUES (1); -- joe@acme.com\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blocks_delimiter() {
+        let dump = "INSERT INTO t VALUES (1);\n\
+                    DELIMITER //\n\
+                    CREATE PROCEDURE add_user(IN email TEXT)\n\
+                    BEGIN\n  \
+                    INSERT INTO users (email) VALUES (email);\n  \
+                    UPDATE users SET email = 'x@acme.com';\n\
+                    END //\n\
+                    INSERT INTO users (email)\n\
+                    VALUES ('a@acme.com;')//\n\
+                    delimiter ;\n\
+                    INSERT INTO t VALUES (2);\n";
+        let lines = dump.lines().map(|line| Ok(line.to_string()));
+        let blocks: Vec<Block> = blocks(lines).collect::<io::Result<_>>().unwrap();
+
+        let routine = |line: &str| Block::Routine(line.to_string());
+        let mut expected = vec![Block::Statement("INSERT INTO t VALUES (1);\n".to_string())];
+        expected.extend(dump.lines().skip(1).take(6).map(routine));
+        // Statements between routines end in the custom delimiter
+        expected.push(Block::Delimited {
+            statement: "INSERT INTO users (email)\nVALUES ('a@acme.com;')//\n".to_string(),
+            delimiter: "//".to_string(),
+        });
+        expected.push(routine("delimiter ;"));
+        expected.push(Block::Statement("INSERT INTO t VALUES (2);\n".to_string()));
+        assert_eq!(blocks, expected);
+    }
+
+    #[test]
+    fn test_is_routine_start() {
+        assert!(is_routine_start("CREATE PROCEDURE add_user()"));
+        assert!(is_routine_start(
+            "CREATE DEFINER=`root`@`localhost` FUNCTION f() RETURNS int"
+        ));
+        assert!(is_routine_start("create definer = admin@'%' event purge"));
+        assert!(is_routine_start(
+            "/*!50003 CREATE*/ /*!50017 DEFINER=`root`@`localhost`*/ /*!50003 TRIGGER t"
+        ));
+        assert!(!is_routine_start("CREATE TABLE users (id int)//"));
+        assert!(!is_routine_start("INSERT INTO procedure VALUES (1)//"));
+    }
+
+    #[test]
+    fn test_is_terminated() {
+        assert!(is_terminated("INSERT INTO t VALUES (1)//\n", "//"));
+        assert!(is_terminated("INSERT INTO t VALUES (1) ;; -- done\n", ";;"));
+        assert!(!is_terminated("INSERT INTO t VALUES ('//')\n", "//"));
+        assert!(!is_terminated("INSERT INTO t VALUES (1);\n", "//"));
+        assert!(is_complete("INSERT INTO t VALUES (1); -- a;b\n"));
+    }
+
+    #[test]
+    fn test_comment_start() {
+        assert_eq!(comment_start("SELECT 1; -- a@b.com"), Some(10));
+        assert_eq!(comment_start("SELECT '--', \"a--b\" -- x"), Some(20));
+        assert_eq!(comment_start("SELECT 'it''s' - 1"), None);
+        assert_eq!(comment_start("SELECT 'a\\'--'"), None);
+    }
+}
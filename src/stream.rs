@@ -0,0 +1,227 @@
+// Streaming SQL anonymization
+// `AnonymizingReader` wraps a reader of a SQL dump and yields its lines
+// anonymized, so servers and tests get the CLI's SQL handling without its
+// read-write loop. Each block goes through the same `Processor` the CLI uses.
+
+use crate::sql::{self, Blocks};
+use crate::{Anonymizer, Detector, Processor, RuleSet};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Lines};
+
+/// An iterator over the anonymized lines of a SQL dump
+///
+/// Lines are yielded without their newline. A multi-line statement is read
+/// in full before its first line is yielded. Comments outside statements and
+/// stored procedure, function and trigger definitions are yielded unchanged. The
+/// anonymizer's relationship cache persists across the whole input; take it
+/// back with `into_anonymizer` to export the cache after the run.
+///
+/// ```
+/// use scrub_db_core::{AnonymizationType, Anonymizer, AnonymizingReader, RuleSet};
+/// use std::io::Cursor;
+///
+/// let mut rules = RuleSet::new();
+/// rules.add("users.email", AnonymizationType::MaskEmail);
+/// let dump = "INSERT INTO users (id, email) VALUES (1, 'jane@acme.com');\n";
+///
+/// let lines: Vec<String> = AnonymizingReader::new(Cursor::new(dump), rules, Anonymizer::new())
+///     .collect::<std::io::Result<_>>()
+///     .unwrap();
+/// assert_eq!(lines, ["INSERT INTO users (id, email) VALUES (1, '****@acme.com');"]);
+/// ```
+pub struct AnonymizingReader<R> {
+    blocks: Blocks<Lines<R>>,
+    processor: Processor,
+    /// Anonymized lines of the last block not yet yielded
+    pending: VecDeque<String>,
+}
+
+impl<R: BufRead> AnonymizingReader<R> {
+    /// Anonymize `reader` with column rules, preserving relationships and
+    /// running every line detector
+    pub fn new(reader: R, rules: RuleSet, anonymizer: Anonymizer) -> Self {
+        Self::with_processor(reader, Processor::new(rules, anonymizer))
+    }
+
+    /// Anonymize `reader` with a processor's settings, e.g. with
+    /// `skip_tables` or `value_rules` set
+    pub fn with_processor(reader: R, processor: Processor) -> Self {
+        Self {
+            blocks: sql::blocks(reader.lines()),
+            processor,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Run only these detectors on lines outside parsed statements
+    pub fn set_detectors(&mut self, detectors: &[Detector]) {
+        self.processor.detectors = detectors.to_vec();
+    }
+
+    /// Set the default for rules without their own `preserve` override
+    pub fn set_preserve_relationships(&mut self, preserve: bool) {
+        self.processor.preserve_relationships = preserve;
+    }
+
+    /// The anonymizer, e.g. for its stats part-way through
+    pub fn anonymizer(&self) -> &Anonymizer {
+        &self.processor.anonymizer
+    }
+
+    /// Stop reading and return the anonymizer with its cache
+    pub fn into_anonymizer(self) -> Anonymizer {
+        self.processor.anonymizer
+    }
+}
+
+impl<R: BufRead> Iterator for AnonymizingReader<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(line) = self.pending.pop_front() {
+            return Some(Ok(line));
+        }
+        match self.blocks.next()? {
+            Ok(block) => {
+                let anonymized = self.processor.block(&block);
+                self.pending.extend(anonymized.lines().map(str::to_string));
+                self.pending.pop_front().map(Ok)
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnonymizationType, Locale, Rule};
+    use regex::Regex;
+    use std::io::Cursor;
+
+    fn reader(dump: &str) -> AnonymizingReader<Cursor<String>> {
+        let mut rules = RuleSet::new();
+        rules.add("users.email", AnonymizationType::FakeEmail);
+        rules.add("users.ssn", AnonymizationType::MaskSSN);
+        AnonymizingReader::new(
+            Cursor::new(dump.to_string()),
+            rules,
+            Anonymizer::with_seed(1),
+        )
+    }
+
+    #[test]
+    fn test_anonymizing_reader_statements() {
+        let dump = "CREATE TABLE users (id int, email text, ssn text);\n\
+                    INSERT INTO users VALUES (1, 'jane@x.com', '123-45-6789'),\n\
+                    (2, 'joe@x.com', NULL);\n\
+                    UPDATE users SET ssn = '987-65-4321' WHERE email = 'jane@x.com';\n";
+        let lines: Vec<String> = reader(dump).collect::<io::Result<_>>().unwrap();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "CREATE TABLE users (id int, email text, ssn text);"
+        );
+        let first = sql::parse_insert(&format!("{}\n{}", lines[1], lines[2])).unwrap();
+        let fake = &first.rows[0][1].text;
+        assert!(!fake.contains("jane"));
+        assert_eq!(first.rows[0][2].text, "***-**-****");
+        assert!(first.rows[1][2].is_null());
+
+        // The UPDATE's WHERE finds the row by its fake email
+        let update = sql::parse_update(&lines[3]).unwrap();
+        assert_eq!(&update.conditions[0].1.text, fake);
+        assert_eq!(update.assignments[0].1.text, "***-**-****");
+    }
+
+    #[test]
+    fn test_anonymizing_reader_rule_locales() {
+        let mut rules = RuleSet::new();
+        rules.add("customers.name", AnonymizationType::FakeName);
+        let mut supplier = Rule::new("suppliers.name", AnonymizationType::FakeName);
+        supplier.locale = Some(Locale::JaJp);
+        rules.insert(supplier);
+        let dump = "INSERT INTO customers (name) VALUES ('Anna Schmidt');\n\
+                    INSERT INTO suppliers (name) VALUES ('Kenji Sato');\n";
+        let lines: Vec<String> =
+            AnonymizingReader::new(Cursor::new(dump), rules, Anonymizer::with_seed(1))
+                .collect::<io::Result<_>>()
+                .unwrap();
+
+        let name = |line: &str| sql::parse_insert(line).unwrap().rows[0][0].text.clone();
+        assert!(name(&lines[0]).is_ascii());
+        assert!(!name(&lines[1]).is_ascii());
+    }
+
+    #[test]
+    fn test_anonymizing_reader_keeps_comments() {
+        let dump = "-- contact jane@x.com for access\n\
+                    /* owner: joe@x.com */\n\
+                    SELECT 1 FROM users WHERE users.email = 'jane@x.com'; -- was jane@x.com\n";
+        let lines: Vec<String> = reader(dump).collect::<io::Result<_>>().unwrap();
+
+        assert_eq!(lines[0], "-- contact jane@x.com for access");
+        assert_eq!(lines[1], "/* owner: joe@x.com */");
+        assert!(!lines[2].contains("email = 'jane@x.com'"));
+        assert!(lines[2].ends_with("; -- was jane@x.com"));
+    }
+
+    #[test]
+    fn test_anonymizing_reader_custom_delimiter() {
+        let dump = "DELIMITER //\n\
+                    CREATE TRIGGER audit AFTER INSERT ON users FOR EACH ROW\n\
+                    INSERT INTO log (email) VALUES ('ops@x.com');//\n\
+                    INSERT INTO users (id, email) VALUES (1, 'real@corp.com')//\n\
+                    DELIMITER ;\n";
+        let lines: Vec<String> = reader(dump).collect::<io::Result<_>>().unwrap();
+
+        assert_eq!(lines[..3], dump.lines().take(3).collect::<Vec<_>>()[..]);
+        assert!(!lines[3].contains("real@corp.com"));
+        assert!(lines[3].ends_with("')//"));
+        assert_eq!(lines[4], "DELIMITER ;");
+    }
+
+    #[test]
+    fn test_anonymizing_reader_lines_and_cache() {
+        let dump = "-- dump\nSELECT id FROM users WHERE users.email = 'jane@x.com';\nINSERT INTO users (email) VALUES ('jane@x.com');\n";
+        let mut reader = reader(dump);
+        reader.set_detectors(&[Detector::Email]);
+
+        assert_eq!(reader.next().unwrap().unwrap(), "-- dump");
+        let select = reader.next().unwrap().unwrap();
+        let insert = reader.next().unwrap().unwrap();
+        assert!(reader.next().is_none());
+
+        let fake = &sql::parse_insert(&insert).unwrap().rows[0][0].text;
+        assert_eq!(
+            select,
+            format!("SELECT id FROM users WHERE users.email = '{}';", fake)
+        );
+        assert_eq!(reader.into_anonymizer().export_cache()["jane@x.com"], *fake);
+    }
+
+    #[test]
+    fn test_anonymizing_reader_processor_settings() {
+        let mut rules = RuleSet::new();
+        rules.add("users.email", AnonymizationType::FakeEmail);
+        let mut processor = Processor::new(rules, Anonymizer::with_seed(1));
+        processor.skip_tables = vec!["admins".to_string()];
+        processor.value_rules =
+            vec![(Regex::new(r"\d{3}-\d{4}").unwrap(), AnonymizationType::Hash)];
+        let dump = "INSERT INTO admins (email) VALUES ('root@x.com');\n\
+                    INSERT INTO users (email, note) VALUES ('jane@x.com', 'call 555-1234');\n";
+        let lines: Vec<String> = AnonymizingReader::with_processor(Cursor::new(dump), processor)
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            lines[0],
+            "INSERT INTO admins (email) VALUES ('root@x.com');"
+        );
+        let row = &sql::parse_insert(&lines[1]).unwrap().rows[0];
+        assert!(!row[0].text.contains("jane"));
+        assert!(row[1].text.starts_with("call "));
+        assert!(!row[1].text.contains("555-1234"));
+    }
+}
@@ -0,0 +1,103 @@
+// `scrub-db check` run as a separate process, the way CI invokes it
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Write `config` to a file in the temp dir, unique to this test process
+fn config_file(name: &str, config: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("scrub-db-check-{}-{}", std::process::id(), name));
+    std::fs::write(&path, config).unwrap();
+    path
+}
+
+fn check(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_scrub-db"))
+        .arg("check")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_check_good_config() {
+    let path = config_file(
+        "good.yaml",
+        "custom_rules:\n  users.email: fake_email\n  users.country: { method: categorical, values: { US: 0.7, DE: 0.3 } }\n",
+    );
+    let output = check(&["-c", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("is valid: 2 rule(s)"), "{}", stderr);
+    // No SQL is read, so nothing is written
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_check_bad_config() {
+    let path = config_file(
+        "bad.yaml",
+        "custom_rules:\n  users.email: fak_email\n  users.phone: fake_phone\n  users.code: { method: pattern }\n",
+    );
+    let output = check(&["-c", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(r#"users.email: unknown anonymization method "fak_email""#),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("users.code: pattern (missing or invalid options)"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("2 invalid rule(s)"), "{}", stderr);
+}
+
+#[test]
+fn test_check_unparsable_config() {
+    let output = check(&["--config-inline", "custom_rules: [unclosed"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("Failed to parse config file"), "{}", stderr);
+}
+
+#[test]
+fn test_check_bad_value_rule() {
+    let output = check(&["--config-inline", "value_rules:\n  \"([\": fake_email\n"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains(r#"Invalid value rule "([""#), "{}", stderr);
+}
+
+#[test]
+fn test_check_bad_value_rule_method() {
+    let output = check(&["--config-inline", "value_rules:\n  \"@\": nope\n"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(r#"Invalid method for value rule "@""#),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_check_unknown_detector() {
+    let output = check(&["--config-inline", "enabled_detectors: [email, bogus]\n"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(r#"Unknown detector "bogus" in enabled_detectors"#),
+        "{}",
+        stderr
+    );
+}